@@ -15,6 +15,20 @@ extern {
     pub fn set_value(this: &Editor, value: &str);
     #[wasm_bindgen(method, getter)]
     pub fn selection(this: &Editor) -> Selection;
+    #[wasm_bindgen(method, getter)]
+    pub fn session(this: &Editor) -> EditSession;
+    /// A `{ row, column }` object
+    #[wasm_bindgen(method, js_name = getCursorPosition)]
+    pub fn get_cursor_position(this: &Editor) -> JsValue;
+    #[wasm_bindgen(method, js_name = moveCursorTo)]
+    pub fn move_cursor_to(this: &Editor, row: u32, column: u32);
+    
+    #[derive(Clone)]
+    pub type EditSession;
+    #[wasm_bindgen(method, js_name = getScrollTop)]
+    pub fn get_scroll_top(this: &EditSession) -> f64;
+    #[wasm_bindgen(method, js_name = setScrollTop)]
+    pub fn set_scroll_top(this: &EditSession, scroll_top: f64);
     
     #[derive(Clone)]
     pub type Selection;
@@ -1,7 +1,14 @@
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use arc_cell::OptionalArcCell;
 use chrono::{DateTime, Utc};
+use gloo_timers::future::sleep;
 use jwt::{Header, Token};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
@@ -15,6 +22,85 @@ pub fn auth_token() -> String {
     AUTH_TOKEN.get().map(|s| (*s).clone()).unwrap_or_default()
 }
 
+/// Milliseconds since the epoch of the last user interaction with the page
+static LAST_ACTIVITY: AtomicU64 = AtomicU64::new(0);
+pub fn record_activity() {
+    LAST_ACTIVITY.store(js_sys::Date::now() as u64, Ordering::SeqCst);
+}
+
+/// Logs out after `timeout_minutes` without user interaction. "0" disables it.
+/// Only DOM events passed to `record_activity` count, so background refreshers
+/// never keep the session alive.
+pub fn start_inactivity_logout<'a>(
+    cx: Scope<'a>,
+    logged_in: &'a Signal<LoggedInState>,
+    timeout_minutes: &'a Signal<String>,
+) {
+    record_activity();
+    spawn_local_scoped(cx, async move {
+        loop {
+            sleep(Duration::from_secs(15)).await;
+
+            let Ok(minutes) = timeout_minutes.get_untracked().parse::<u64>() else { continue };
+            if minutes == 0 {
+                continue;
+            }
+
+            let idle = (js_sys::Date::now() as u64)
+                .saturating_sub(LAST_ACTIVITY.load(Ordering::SeqCst));
+            if idle >= minutes * 60 * 1000 {
+                logout(logged_in).await;
+                return;
+            }
+        }
+    });
+}
+
+/// Same rule the server applies when a password is changed
+const MIN_PASSWORD_LENGTH: usize = 12;
+/// Out of lowercase, uppercase, digits and everything else
+const MIN_PASSWORD_CHAR_CLASSES: usize = 2;
+
+fn validate_username(username: &str) -> Result<(), &'static str> {
+    if username.is_empty() {
+        return Err("Username is required");
+    }
+
+    let Some((local, domain)) = username.split_once('@') else {
+        return Err("Username must be an email address");
+    };
+    let valid_domain = domain
+        .split_once('.')
+        .map(|(name, tld)| !name.is_empty() && !tld.is_empty())
+        .unwrap_or(false);
+    if local.is_empty() || !valid_domain || username.contains(char::is_whitespace) {
+        return Err("Username must be an email address");
+    }
+
+    Ok(())
+}
+
+fn validate_password(password: &str) -> Result<(), String> {
+    if password.is_empty() {
+        return Err("Password is required".into());
+    }
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!("Password must be at least {MIN_PASSWORD_LENGTH} characters"));
+    }
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|&&has| has).count() < MIN_PASSWORD_CHAR_CLASSES {
+        return Err(
+            "Password must mix at least two of lowercase, uppercase, digits and symbols".into(),
+        );
+    }
+    Ok(())
+}
+
 #[component]
 pub fn LoginForm<'a, G: Html>(cx: Scope<'a>, logged_in: &'a Signal<LoggedInState>) -> View<G> {
     let username = create_signal(cx, String::new());
@@ -24,6 +110,16 @@ pub fn LoginForm<'a, G: Html>(cx: Scope<'a>, logged_in: &'a Signal<LoggedInState
     let do_login = on_login(cx, username, password, problem, logged_in);
     let do_register = on_register(cx, username, password, problem, logged_in);
 
+    // Only complain about fields once something has been typed in them
+    let username_hint = create_memo(cx, || match validate_username(&username.get()) {
+        Err(err) if !username.get().is_empty() => err.to_string(),
+        _ => String::new(),
+    });
+    let password_hint = create_memo(cx, || match validate_password(&password.get()) {
+        Err(err) if !password.get().is_empty() => err,
+        _ => String::new(),
+    });
+
     view! { cx,
         div(class="login-form") {
             div {
@@ -32,6 +128,7 @@ pub fn LoginForm<'a, G: Html>(cx: Scope<'a>, logged_in: &'a Signal<LoggedInState
                     type="email",
                     placeholder="Username..."
                 )
+                div(class="login-hint") { (username_hint.get()) }
             }
             div {
                 input(
@@ -39,6 +136,7 @@ pub fn LoginForm<'a, G: Html>(cx: Scope<'a>, logged_in: &'a Signal<LoggedInState
                     type="password",
                     placeholder="Password..."
                 )
+                div(class="login-hint") { (password_hint.get()) }
             }
             div {
                 input(
@@ -71,6 +169,10 @@ fn on_login<'a>(
 
         let username = username.get();
         let password = password.get();
+        if username.is_empty() || password.is_empty() {
+            problem.set("Enter a username and password".into());
+            return;
+        }
 
         spawn_local_scoped(cx, async move {
             match login(&username, &password).await {
@@ -104,6 +206,14 @@ fn on_register<'a>(
 
         let username = username.get();
         let password = password.get();
+        if let Err(err) = validate_username(&username) {
+            problem.set(err.into());
+            return;
+        }
+        if let Err(err) = validate_password(&password) {
+            problem.set(err);
+            return;
+        }
 
         spawn_local_scoped(cx, async move {
             match register(&username, &password).await {
@@ -1,6 +1,7 @@
 use std::{rc::Rc, time::Duration};
 
 use gloo_timers::future::sleep;
+use models::atticfan::{AtticFanState, Fan};
 use reqwest::StatusCode;
 use sycamore::{prelude::*, futures::spawn_local_scoped};
 use web_sys::window;
@@ -24,7 +25,7 @@ pub fn AtticFan(cx: Scope) -> View<DomNode> {
         let new_state = !*big_succ_state.get();
         big_succ_state.set(new_state);
         spawn_local_scoped(cx, async move {
-            set_state(BIG_SUCC, new_state).await;
+            set_state(Fan::BigSucc, new_state).await;
         });
     };
 
@@ -32,7 +33,7 @@ pub fn AtticFan(cx: Scope) -> View<DomNode> {
         let new_state = !*roof_fan_state.get();
         roof_fan_state.set(new_state);
         spawn_local_scoped(cx, async move {
-            set_state(ROOF_FAN, new_state).await;
+            set_state(Fan::RoofFan, new_state).await;
         });
     };
 
@@ -78,28 +79,22 @@ fn indicator_value(state: Rc<bool>) -> &'static str {
     }
 }
 
-const BIG_SUCC: i32 = 1;
-const ROOF_FAN: i32 = 0;
-
-async fn get_state(fan: i32) -> bool {
+async fn get_state() -> Option<AtticFanState> {
     let base = window().unwrap().origin();
-    let Ok(response) = reqwest::Client::new()
-        .get(format!("{base}/api/atticfan/getstate/{fan}"))
+    let response = reqwest::Client::new()
+        .get(format!("{base}/api/atticfan/state"))
         .header("X-Auth", auth_token())
         .send()
-        .await else {
-            return false;
-        };
+        .await
+        .ok()?;
 
-    if response.status() == StatusCode::OK {
-        if let Ok(text) = response.text().await {
-            return text == "true";
-        }
+    if response.status() != StatusCode::OK {
+        return None;
     }
-    false
+    response.json::<AtticFanState>().await.ok()
 }
 
-async fn set_state(fan: i32, state: bool) {
+async fn set_state(fan: Fan, state: bool) {
     let base = window().unwrap().origin();
     let _ = reqwest::Client::new()
         .get(format!("{base}/api/atticfan/setstate/{fan}/{state}"))
@@ -111,8 +106,9 @@ async fn set_state(fan: i32, state: bool) {
 fn start_refresh_state_loop<'a>(cx: Scope<'a>, bs: &'a Signal<bool>, rf: &'a Signal<bool>) {
     spawn_local_scoped(cx, async move {
         loop {
-            bs.set(get_state(BIG_SUCC).await);
-            rf.set(get_state(ROOF_FAN).await);
+            let state = get_state().await.unwrap_or_default();
+            bs.set(state.big_succ);
+            rf.set(state.roof_fan);
             sleep(Duration::from_secs(10)).await;
         }
     })
@@ -0,0 +1,102 @@
+use std::{rc::Rc, time::Duration};
+
+use gloo_timers::future::sleep;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use sycamore::{futures::spawn_local_scoped, prelude::*};
+use web_sys::window;
+
+use crate::auth::auth_token;
+
+#[component]
+pub fn AwayMode(cx: Scope) -> View<DomNode> {
+    let away_state = create_signal(cx, false);
+
+    start_refresh_state_loop(cx, away_state);
+
+    let away_class = create_selector(cx, || indicator_class(away_state.get()));
+    let away_value = create_selector(cx, || indicator_value(away_state.get()));
+
+    let toggle_away = move |_| {
+        let new_state = !*away_state.get();
+        away_state.set(new_state);
+        spawn_local_scoped(cx, async move {
+            set_state(new_state).await;
+        });
+    };
+
+    view! { cx,
+        table(id="away-control") {
+            tr {
+                td { "Away" }
+            }
+            tr {
+                td {
+                    a(href="#/", on:click=toggle_away, class="link-button") {
+                        div(class=away_class) {
+                            (away_value.get())
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn indicator_class(state: Rc<bool>) -> &'static str {
+    if *state {
+        "status-on"
+    } else {
+        "status-off"
+    }
+}
+
+fn indicator_value(state: Rc<bool>) -> &'static str {
+    if *state {
+        "ON"
+    } else {
+        "OFF"
+    }
+}
+
+#[derive(Deserialize)]
+struct AwayModeState {
+    active: bool,
+}
+
+async fn get_state() -> bool {
+    let base = window().unwrap().origin();
+    let Ok(response) = reqwest::Client::new()
+        .get(format!("{base}/api/thermostat/away"))
+        .header("X-Auth", auth_token())
+        .send()
+        .await else {
+            return false;
+        };
+
+    if response.status() == StatusCode::OK {
+        if let Ok(state) = response.json::<AwayModeState>().await {
+            return state.active;
+        }
+    }
+    false
+}
+
+async fn set_state(state: bool) {
+    let base = window().unwrap().origin();
+    let _ = reqwest::Client::new()
+        .put(format!("{base}/api/thermostat/away"))
+        .header("X-Auth", auth_token())
+        .body(serde_json::to_string(&state).unwrap())
+        .send()
+        .await;
+}
+
+fn start_refresh_state_loop<'a>(cx: Scope<'a>, away: &'a Signal<bool>) {
+    spawn_local_scoped(cx, async move {
+        loop {
+            away.set(get_state().await);
+            sleep(Duration::from_secs(10)).await;
+        }
+    })
+}
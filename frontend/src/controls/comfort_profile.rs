@@ -0,0 +1,111 @@
+use std::{rc::Rc, time::Duration};
+
+use gloo_timers::future::sleep;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sycamore::{futures::spawn_local_scoped, prelude::*};
+use web_sys::window;
+
+use crate::auth::auth_token;
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ProfileName {
+    Comfort,
+    Eco,
+}
+
+#[component]
+pub fn ComfortProfile(cx: Scope) -> View<DomNode> {
+    let profile = create_signal(cx, ProfileName::Comfort);
+
+    start_refresh_state_loop(cx, profile);
+
+    let profile_class = create_selector(cx, || indicator_class(profile.get()));
+    let profile_value = create_selector(cx, || indicator_value(profile.get()));
+
+    let toggle_profile = move |_| {
+        let new_profile = match *profile.get() {
+            ProfileName::Comfort => ProfileName::Eco,
+            ProfileName::Eco => ProfileName::Comfort,
+        };
+        profile.set(new_profile);
+        spawn_local_scoped(cx, async move {
+            set_state(new_profile).await;
+        });
+    };
+
+    view! { cx,
+        table(id="comfort-profile-control") {
+            tr {
+                td { "Profile" }
+            }
+            tr {
+                td {
+                    a(href="#/", on:click=toggle_profile, class="link-button") {
+                        div(class=profile_class) {
+                            (profile_value.get())
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn indicator_class(profile: Rc<ProfileName>) -> &'static str {
+    match *profile {
+        ProfileName::Comfort => "status-on",
+        ProfileName::Eco => "status-off",
+    }
+}
+
+fn indicator_value(profile: Rc<ProfileName>) -> &'static str {
+    match *profile {
+        ProfileName::Comfort => "COMFORT",
+        ProfileName::Eco => "ECO",
+    }
+}
+
+#[derive(Deserialize)]
+struct ComfortProfileState {
+    active: ProfileName,
+}
+
+async fn get_state() -> Option<ProfileName> {
+    let base = window().unwrap().origin();
+    let Ok(response) = reqwest::Client::new()
+        .get(format!("{base}/api/thermostat/comfort_profile"))
+        .header("X-Auth", auth_token())
+        .send()
+        .await else {
+            return None;
+        };
+
+    if response.status() != StatusCode::OK {
+        return None;
+    }
+    let state = response.json::<ComfortProfileState>().await.ok()?;
+    Some(state.active)
+}
+
+async fn set_state(profile: ProfileName) {
+    let base = window().unwrap().origin();
+    let _ = reqwest::Client::new()
+        .put(format!("{base}/api/thermostat/comfort_profile"))
+        .header("X-Auth", auth_token())
+        .body(serde_json::to_string(&profile).unwrap())
+        .send()
+        .await;
+}
+
+fn start_refresh_state_loop<'a>(cx: Scope<'a>, profile: &'a Signal<ProfileName>) {
+    spawn_local_scoped(cx, async move {
+        loop {
+            if let Some(state) = get_state().await {
+                profile.set(state);
+            }
+            sleep(Duration::from_secs(10)).await;
+        }
+    })
+}
@@ -1,4 +1,10 @@
-pub use self::atticfan::AtticFan;
+pub use self::{
+    atticfan::AtticFan, away::AwayMode, comfort_profile::ComfortProfile,
+    system_status::SystemStatusPanel,
+};
 
 pub mod atticfan;
+pub mod away;
+pub mod comfort_profile;
+pub mod system_status;
 pub mod thermostat;
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use gloo_timers::future::sleep;
+use models::status::SystemStatus;
+use reqwest::StatusCode;
+use sycamore::{futures::spawn_local_scoped, prelude::*};
+use web_sys::window;
+
+use crate::auth::auth_token;
+
+#[component]
+pub fn SystemStatusPanel(cx: Scope) -> View<DomNode> {
+    let status = create_signal(cx, None::<SystemStatus>);
+
+    start_refresh_state_loop(cx, status);
+
+    let redis = create_selector(cx, || {
+        row((*status.get()).as_ref().map(|s| s.redis_ok), "OK", "Unreachable")
+    });
+    let mqtt = create_selector(cx, || {
+        row((*status.get()).as_ref().map(|s| s.mqtt_online), "Online", "Silent")
+    });
+    let probe = create_selector(cx, || match status.get().as_ref() {
+        None => ("status-unknown", "...".to_string()),
+        Some(s) => match s.probe_age_secs {
+            Some(age) if s.probe_fresh() => ("status-ok", format!("{age}s ago")),
+            Some(age) => ("status-warn", format!("{age}s ago")),
+            None => ("status-warn", "Never".to_string()),
+        },
+    });
+    let script = create_selector(cx, || match status.get().as_ref() {
+        None => ("status-unknown", "...".to_string()),
+        Some(s) if !s.script_loaded => ("status-warn", "Not loaded".to_string()),
+        Some(s) if s.script_issues > 0 => ("status-warn", format!("{} issues", s.script_issues)),
+        Some(_) => ("status-ok", "OK".to_string()),
+    });
+    let overrides = create_selector(cx, || match status.get().as_ref() {
+        None => ("status-unknown", "...".to_string()),
+        Some(s) => {
+            let active: Vec<&str> = [
+                (s.override_pulse_active, "Pulse"),
+                (s.oneshot_setpoint_active, "Oneshot"),
+            ]
+            .into_iter()
+            .filter_map(|(active, name)| active.then_some(name))
+            .collect();
+            if active.is_empty() {
+                ("status-ok", "None".to_string())
+            } else {
+                ("status-warn", active.join(", "))
+            }
+        }
+    });
+
+    view! { cx,
+        table(id="system-status") {
+            tr {
+                td { "Redis" }
+                td(class=redis.get().0) { (redis.get().1.clone()) }
+            }
+            tr {
+                td { "Thermostat" }
+                td(class=mqtt.get().0) { (mqtt.get().1.clone()) }
+            }
+            tr {
+                td { "Last probe update" }
+                td(class=probe.get().0) { (probe.get().1.clone()) }
+            }
+            tr {
+                td { "Script" }
+                td(class=script.get().0) { (script.get().1.clone()) }
+            }
+            tr {
+                td { "Overrides" }
+                td(class=overrides.get().0) { (overrides.get().1.clone()) }
+            }
+        }
+    }
+}
+
+fn row(ok: Option<bool>, good: &str, bad: &str) -> (&'static str, String) {
+    match ok {
+        None => ("status-unknown", "...".to_string()),
+        Some(true) => ("status-ok", good.to_string()),
+        Some(false) => ("status-warn", bad.to_string()),
+    }
+}
+
+async fn get_state() -> Option<SystemStatus> {
+    let base = window().unwrap().origin();
+    let response = reqwest::Client::new()
+        .get(format!("{base}/api/thermostat/status"))
+        .header("X-Auth", auth_token())
+        .send()
+        .await
+        .ok()?;
+
+    if response.status() != StatusCode::OK {
+        return None;
+    }
+    response.json::<SystemStatus>().await.ok()
+}
+
+fn start_refresh_state_loop<'a>(cx: Scope<'a>, status: &'a Signal<Option<SystemStatus>>) {
+    spawn_local_scoped(cx, async move {
+        loop {
+            status.set(get_state().await);
+            sleep(Duration::from_secs(15)).await;
+        }
+    })
+}
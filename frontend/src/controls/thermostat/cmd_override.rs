@@ -42,11 +42,8 @@ pub fn CommandOverride(cx: Scope) -> View<DomNode> {
 
     let send_cmd = move |e: Event| {
         e.prevent_default();
-        let selected_cmd = match &**selected_cmd.get().clone() {
-            "off" => HvacRequest::Off,
-            "heat" => HvacRequest::Heat,
-            "cool" => HvacRequest::Cool,
-            _ => return,
+        let Ok(selected_cmd) = selected_cmd.get().parse::<HvacRequest>() else {
+            return;
         };
 
         let Ok(selected_time) = selected_time.get().clone().parse::<i64>() else {
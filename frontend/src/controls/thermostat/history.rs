@@ -1,37 +1,119 @@
 use std::{
+    rc::Rc,
     sync::atomic::{AtomicBool, Ordering::SeqCst},
     time::Duration,
 };
 
 use anyhow::bail;
 use chrono::{DateTime, Utc};
+use models::PRIMARY_PROBE;
 use plotters_canvas::CanvasBackend;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sycamore::{futures::spawn_local_scoped, prelude::*};
 use wasm_bindgen::JsCast;
 use web_sys::{window, CanvasRenderingContext2d, HtmlCanvasElement};
 
-use crate::{auth::auth_token, models::Units};
+use crate::{
+    auth::auth_token,
+    helpers::create_saved_signal,
+    models::{DarkMode, ProbeList, Units},
+};
+
+use super::NO_PROBES_MESSAGE;
 
 #[component]
 pub fn TemperatureHistory<G: Html>(cx: Scope) -> View<G> {
+    let probe_list = use_context::<Signal<ProbeList>>(cx);
+    let no_probes = create_selector(cx, || probe_list.get().is_empty());
+
     view! { cx,
         h2 { "History" }
-        TemperatureGraph(probe = "primary".into())
+        (if *no_probes.get() {
+            view! { cx,
+                div(class="no-probes") { (NO_PROBES_MESSAGE) }
+            }
+        } else {
+            view! { cx,
+                TemperatureGraph()
+            }
+        })
     }
 }
 
-#[derive(Prop)]
-struct GraphParams {
-    probe: String,
+/// The probe historian records every 10 seconds
+const ENTRIES_PER_HOUR: i64 = 6 * 60;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+enum HistoryRange {
+    SixHours,
+    Day,
+    Week,
+}
+
+impl HistoryRange {
+    const ALL: [HistoryRange; 3] = [HistoryRange::SixHours, HistoryRange::Day, HistoryRange::Week];
+
+    fn label(self) -> &'static str {
+        match self {
+            HistoryRange::SixHours => "6h",
+            HistoryRange::Day => "24h",
+            HistoryRange::Week => "7d",
+        }
+    }
+
+    fn hours(self) -> f64 {
+        match self {
+            HistoryRange::SixHours => 6.0,
+            HistoryRange::Day => 24.0,
+            HistoryRange::Week => 24.0 * 7.0,
+        }
+    }
+
+    fn item_count(self) -> i64 {
+        self.hours() as i64 * ENTRIES_PER_HOUR
+    }
+
+    /// A week of raw entries is a huge payload, so let the server average it
+    fn bucket_secs(self) -> Option<i64> {
+        match self {
+            HistoryRange::Week => Some(10 * 60),
+            _ => None,
+        }
+    }
+
+    /// Hours per unit on the x axis, what that unit is called, and how many
+    /// labels to put on it
+    fn x_unit(self) -> (f64, &'static str, usize) {
+        match self {
+            HistoryRange::SixHours => (1.0, "Hours ago", 6),
+            HistoryRange::Day => (1.0, "Hours ago", 12),
+            HistoryRange::Week => (24.0, "Days ago", 7),
+        }
+    }
+}
+
+/// One history per selected probe
+struct HistoryData {
+    range: HistoryRange,
+    series: Vec<(String, Vec<(f64, f64)>)>,
 }
 
 #[component]
-async fn TemperatureGraph<G: Html>(cx: Scope<'_>, params: GraphParams) -> View<G> {
-    let data = create_signal(cx, vec![]);
+async fn TemperatureGraph<G: Html>(cx: Scope<'_>) -> View<G> {
+    let range = create_saved_signal(cx, "history-range", HistoryRange::Day);
+    let selected = create_saved_signal(cx, "history-probes", vec![PRIMARY_PROBE.to_string()]);
+    let data = create_signal(
+        cx,
+        HistoryData {
+            range: *range.get_untracked(),
+            series: vec![],
+        },
+    );
     let prepared = create_ref(cx, AtomicBool::new(false));
     let canvas_node = create_node_ref(cx);
     let units = use_context::<Signal<Units>>(cx);
+    let dark_mode = use_context::<Signal<DarkMode>>(cx);
+    let probe_list = use_context::<Signal<ProbeList>>(cx);
 
     create_effect(cx, move || {
         let data = data.get();
@@ -44,19 +126,94 @@ async fn TemperatureGraph<G: Html>(cx: Scope<'_>, params: GraphParams) -> View<G
             prepared.store(true, SeqCst);
         }
 
-        render_canvas(&canvas, &data, *units.get()).ok();
+        render_canvas(&canvas, data.range, &data.series, *units.get(), *dark_mode.get()).ok();
+    });
+
+    let refresh = move |requested: HistoryRange, probes: Rc<Vec<String>>| async move {
+        let mut series = Vec::with_capacity(probes.len());
+        for probe in probes.iter() {
+            if let Ok(history) = get_history(probe, requested).await {
+                series.push((probe.clone(), history));
+            }
+        }
+        // A slow response from before a switch mustn't overwrite the new one
+        if *range.get_untracked() == requested && *selected.get_untracked() == *probes {
+            data.set(HistoryData {
+                range: requested,
+                series,
+            });
+        }
+    };
+
+    // Fetch straight away whenever the range or selection changes
+    create_effect(cx, move || {
+        let requested = *range.get();
+        let probes = selected.get();
+        spawn_local_scoped(cx, refresh(requested, probes));
     });
 
     spawn_local_scoped(cx, async move {
         loop {
-            if let Ok(new_data) = get_day_history(&params.probe).await {
-                data.set(new_data);
-            }
             gloo_timers::future::sleep(Duration::from_secs(10)).await;
+            refresh(*range.get_untracked(), selected.get_untracked()).await;
         }
     });
 
+    let available_probes =
+        create_selector(cx, || probe_list.get().0.clone().unwrap_or_default());
+
+    let buttons = View::new_fragment(
+        HistoryRange::ALL
+            .into_iter()
+            .map(|option| {
+                let class = create_selector(cx, move || {
+                    if *range.get() == option {
+                        "history-range-button selected"
+                    } else {
+                        "history-range-button"
+                    }
+                });
+                view! { cx,
+                    a(href="#/", class=class, on:click=move |_| range.set(option)) {
+                        (option.label())
+                    }
+                }
+            })
+            .collect(),
+    );
+
     view! { cx,
+        div(class="history-range") { (buttons) }
+        div(class="history-probes") {
+            Keyed(
+                iterable=available_probes,
+                view=move |cx, probe: String| {
+                    let checked = create_selector(cx, {
+                        let probe = probe.clone();
+                        move || selected.get().contains(&probe)
+                    });
+                    let toggle = {
+                        let probe = probe.clone();
+                        move |_| {
+                            let mut probes = (*selected.get()).clone();
+                            if let Some(i) = probes.iter().position(|p| *p == probe) {
+                                probes.remove(i);
+                            } else {
+                                probes.push(probe.clone());
+                            }
+                            selected.set(probes);
+                        }
+                    };
+                    view! { cx,
+                        label(class="history-probe") {
+                            input(type="checkbox", checked=*checked.get(), on:change=toggle)
+                            (probe.clone())
+                        }
+                    }
+                },
+                key=|probe| probe.clone(),
+            )
+        }
         canvas(ref=canvas_node, style="width: 100%;")
     }
 }
@@ -98,13 +255,15 @@ fn prepare_canvas(canvas: &DomNode) {
     ctx.scale(display_factor, display_factor).unwrap();
 }
 
-async fn get_day_history(probe: &str) -> anyhow::Result<Vec<(f64, f64)>> {
-    let item_count = 6 * 60 * 24;
+async fn get_history(probe: &str, range: HistoryRange) -> anyhow::Result<Vec<(f64, f64)>> {
+    let item_count = range.item_count();
     let base = window().unwrap().origin();
+    let mut url = format!("{base}/api/thermostat/probes/{probe}/history?start=0&stop={item_count}");
+    if let Some(bucket) = range.bucket_secs() {
+        url.push_str(&format!("&bucket={bucket}"));
+    }
     let response = reqwest::Client::new()
-        .get(format!(
-            "{base}/api/thermostat/probes/{probe}/history?start=0&stop={item_count}"
-        ))
+        .get(url)
         .header("X-Auth", auth_token())
         .send()
         .await?;
@@ -112,6 +271,8 @@ async fn get_day_history(probe: &str) -> anyhow::Result<Vec<(f64, f64)>> {
     #[derive(Deserialize)]
     struct HistoryEntry {
         time: DateTime<Utc>,
+        /// Bucketed history has min/avg/max instead, the average is plotted
+        #[serde(alias = "avg")]
         temp: f64,
     }
 
@@ -131,9 +292,40 @@ async fn get_day_history(probe: &str) -> anyhow::Result<Vec<(f64, f64)>> {
     Ok(chart_history)
 }
 
-fn render_canvas(canvas: &DomNode, data: &[(f64, f64)], units: Units) -> anyhow::Result<()> {
+/// Series colors in selection order, wrapping around past the end
+const LIGHT_COLORS: [(u8, u8, u8); 6] = [
+    (255, 0, 0),
+    (0, 0, 255),
+    (0, 140, 0),
+    (200, 0, 200),
+    (0, 150, 150),
+    (230, 120, 0),
+];
+/// Lighter versions so the lines stay readable on the dark background
+const DARK_COLORS: [(u8, u8, u8); 6] = [
+    (255, 120, 120),
+    (130, 160, 255),
+    (120, 220, 120),
+    (240, 130, 240),
+    (110, 220, 220),
+    (255, 180, 90),
+];
+
+fn render_canvas(
+    canvas: &DomNode,
+    range: HistoryRange,
+    series: &[(String, Vec<(f64, f64)>)],
+    units: Units,
+    DarkMode(dark): DarkMode,
+) -> anyhow::Result<()> {
     use plotters::prelude::*;
 
+    let (colors, text_color) = if dark {
+        (DARK_COLORS, RGBColor(220, 220, 220))
+    } else {
+        (LIGHT_COLORS, BLACK)
+    };
+
     let Ok(canvas) = canvas.inner_element().dyn_into::<HtmlCanvasElement>() else {
         bail!("Couldn't convert canvas to HtmlCanvasElement");
     };
@@ -163,44 +355,76 @@ fn render_canvas(canvas: &DomNode, data: &[(f64, f64)], units: Units) -> anyhow:
     let root = root.shrink((0, 0), (w, h));
     let root = root.margin(0, 10, 0, 10);
 
-    let Some(temp_min) = data
-        .iter()
-        .map(|(_, t)| *t)
-        .min_by(|a, b| a.partial_cmp(b).unwrap()) else {
-            bail!("Empty data set")
-        };
-    let Some(temp_max) = data
-        .iter()
-        .map(|(_, t)| *t)
-        .max_by(|a, b| a.partial_cmp(b).unwrap()) else {
-            bail!("Empty data set")
-        };
+    // The y axis has to fit every selected probe
+    let all_temps = || series.iter().flat_map(|(_, data)| data.iter().map(|(_, t)| *t));
+    let Some(temp_min) = all_temps().min_by(|a, b| a.partial_cmp(b).unwrap()) else {
+        bail!("Empty data set")
+    };
+    let Some(temp_max) = all_temps().max_by(|a, b| a.partial_cmp(b).unwrap()) else {
+        bail!("Empty data set")
+    };
 
     let temp_min = unit_transform(temp_min);
     let temp_max = unit_transform(temp_max);
 
+    let (hours_per_unit, x_desc, x_labels) = range.x_unit();
+    let x_min = -range.hours() / hours_per_unit;
+
     root.fill(&TRANSPARENT)?;
     let mut chart = ChartBuilder::on(&root)
         .x_label_area_size(40)
         .y_label_area_size(40)
-        .build_cartesian_2d(-24.0f64..0.0, (temp_min - 1.0)..(temp_max + 1.0))?;
+        .build_cartesian_2d(x_min..0.0, (temp_min - 1.0)..(temp_max + 1.0))?;
 
     chart
         .configure_mesh()
-        .x_labels(12)
-        .x_desc("Hours ago")
+        .x_labels(x_labels)
+        .x_desc(x_desc)
         .x_label_formatter(&|x| format!("{:.0}", x.abs()))
         .y_labels(8)
         .y_label_formatter(&|x| format!("{:.1}", x))
+        .label_style(("sans-serif", 12).into_font().color(&text_color))
+        .axis_desc_style(("sans-serif", 12).into_font().color(&text_color))
+        .axis_style(text_color)
         .draw()?;
 
-    if data.is_empty() {
-        return Ok(());
+    for (i, (probe, data)) in series.iter().enumerate() {
+        if data.is_empty() {
+            continue;
+        }
+
+        let chart_data: Vec<(f64, f64)> = if range.bucket_secs().is_some() {
+            // Already averaged by the server
+            data.to_vec()
+        } else {
+            smooth(data)
+        };
+        let chart_data = chart_data
+            .into_iter()
+            .map(|(time, temp)| (time / hours_per_unit, unit_transform(temp)));
+
+        let (r, g, b) = colors[i % colors.len()];
+        let color = RGBColor(r, g, b);
+        chart
+            .draw_series(LineSeries::new(chart_data, &color))?
+            .label(probe.as_str())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
     }
 
+    chart
+        .configure_series_labels()
+        .label_font(("sans-serif", 12).into_font().color(&text_color))
+        .border_style(text_color)
+        .draw()?;
+
+    Ok(())
+}
+
+/// Rolling average, then thinned out by averaging chunks of the result
+fn smooth(data: &[(f64, f64)]) -> Vec<(f64, f64)> {
     const WINDOW_SIZE: usize = 48;
     const CHUNK_SIZE: usize = 18;
-    let chart_data = data
+    data
         // Iterate over rolling windows of the data
         .windows(WINDOW_SIZE)
         // Average the windows
@@ -228,10 +452,5 @@ fn render_canvas(canvas: &DomNode, data: &[(f64, f64)], units: Units) -> anyhow:
                 })
         })
         .map(|(acctime, acctemp, count)| (acctime / count as f64, acctemp / count as f64))
-        // Convert to Fahrenheit if that's selected
-        .map(|(time, temp)| (time, unit_transform(temp)));
-
-    chart.draw_series(LineSeries::new(chart_data, &RED))?;
-
-    Ok(())
+        .collect()
 }
@@ -2,3 +2,6 @@ pub mod cmd_override;
 pub mod history;
 pub mod oneshot_setpoint;
 pub mod temp_display;
+
+/// Shown in place of readings when the server has no probes at all
+pub const NO_PROBES_MESSAGE: &str = "No sensors configured \u{2014} add one in admin";
@@ -7,7 +7,7 @@ use web_sys::{window, Event};
 use crate::{
     auth::auth_token,
     helpers::{create_saved_signal, refresh_signal, start_signal_refresher},
-    models::{HvacMode, HvacRequest, OneshotOrdering, OneshotSetpointState, Temperature, Units},
+    models::{HvacRequest, OneshotOrdering, OneshotSetpointState, Temperature, Units},
 };
 
 const ENDPOINT: &str = "thermostat/oneshot_setpoint";
@@ -16,7 +16,7 @@ const ENDPOINT: &str = "thermostat/oneshot_setpoint";
 pub fn OneshotSetpoint(cx: Scope<'_>) -> View<DomNode> {
     let component_open = create_saved_signal(cx, "oneshot-setpoint-component-open", false);
     let panel_open = create_signal(cx, false);
-    let hvac_mode = use_context::<Signal<HvacMode>>(cx);
+    let hvac_mode = use_context::<Signal<HvacRequest>>(cx);
     let units = use_context::<Signal<Units>>(cx);
     let temperature = use_context::<Signal<Option<Temperature>>>(cx);
 
@@ -67,11 +67,8 @@ pub fn OneshotSetpoint(cx: Scope<'_>) -> View<DomNode> {
 
     let send_cmd = move |e: Event| {
         e.prevent_default();
-        let selected_cmd = match &**selected_cmd.get().clone() {
-            "off" => HvacRequest::Off,
-            "heat" => HvacRequest::Heat,
-            "cool" => HvacRequest::Cool,
-            _ => return,
+        let Ok(selected_cmd) = selected_cmd.get().parse::<HvacRequest>() else {
+            return;
         };
 
         let Ok(setpoint) = selected_setpoint.get().clone().parse::<f32>() else {
@@ -138,7 +135,7 @@ pub fn OneshotSetpoint(cx: Scope<'_>) -> View<DomNode> {
                     }
                 }
             })
-            (if *hvac_mode.get() != HvacMode::Off {
+            (if *hvac_mode.get() != HvacRequest::Off {
                 view! { cx,
                     a(href="#", class="link-button", on:click=toggle_command) {
                         span(class="link-button-bg") {
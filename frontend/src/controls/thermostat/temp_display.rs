@@ -1,12 +1,17 @@
 use sycamore::prelude::*;
 
-use crate::models::{HvacRequest, PinState, Temperature, Units};
+use crate::models::{HvacRequest, PinState, ProbeList, Staleness, Temperature, Units};
+
+use super::NO_PROBES_MESSAGE;
 
 #[component]
 pub fn TemperatureDisplay(cx: Scope) -> View<DomNode> {
     let units = use_context::<Signal<Units>>(cx);
     let temperature = use_context::<Signal<Option<Temperature>>>(cx);
     let pinstate = use_context::<Signal<PinState>>(cx);
+    let probe_list = use_context::<Signal<ProbeList>>(cx);
+    let staleness = use_context::<Signal<Staleness>>(cx);
+    let no_probes = create_selector(cx, || probe_list.get().is_empty());
 
     let temperature_display = create_selector(cx, || {
         match (temperature.get().map(|t| t.0), *units.get()) {
@@ -16,10 +21,15 @@ pub fn TemperatureDisplay(cx: Scope) -> View<DomNode> {
         }
     });
 
-    let temperature_status = create_selector(cx, || match pinstate.get().0 {
-        HvacRequest::Off => "thermostat-off",
-        HvacRequest::Heat => "thermostat-heat",
-        HvacRequest::Cool => "thermostat-cool",
+    let temperature_status = create_selector(cx, || {
+        if staleness.get().temperature {
+            return "thermostat-stale";
+        }
+        match pinstate.get().0 {
+            HvacRequest::Off => "thermostat-off",
+            HvacRequest::Heat => "thermostat-heat",
+            HvacRequest::Cool => "thermostat-cool",
+        }
     });
 
     let toggle_units = move |_| match *units.get() {
@@ -28,8 +38,21 @@ pub fn TemperatureDisplay(cx: Scope) -> View<DomNode> {
     };
 
     view! { cx,
-        div(id="thermostat-current-temp-wrapper", class=temperature_status, on:click=toggle_units) {
-            span { (temperature_display.get()) }
-        }
+        (if *no_probes.get() {
+            view! { cx,
+                div(class="no-probes") { (NO_PROBES_MESSAGE) }
+            }
+        } else {
+            view! { cx,
+                div(id="thermostat-current-temp-wrapper", class=temperature_status, on:click=toggle_units) {
+                    span { (temperature_display.get()) }
+                    (if staleness.get().temperature {
+                        view! { cx, span(class="stale-badge") { "stale" } }
+                    } else {
+                        view! { cx, }
+                    })
+                }
+            }
+        })
     }
 }
@@ -1,12 +1,17 @@
 use std::time::Duration;
 
+use futures_util::StreamExt;
+use gloo_net::websocket::{futures::WebSocket, Message};
 use gloo_timers::future::sleep;
 use reqwest::StatusCode;
 use serde::{de::DeserializeOwned, Serialize};
 use sycamore::{futures::spawn_local_scoped, prelude::*};
 use web_sys::window;
 
-use crate::auth::auth_token;
+use crate::{auth::auth_token, models::LiveUpdate};
+
+/// How long to wait before reopening a dropped live socket
+const LIVE_RETRY: Duration = Duration::from_secs(10);
 
 pub fn create_saved_signal<'a, T>(cx: Scope<'a>, name: &'static str, default: T) -> &'a Signal<T>
 where
@@ -81,3 +86,93 @@ pub fn start_signal_refresher<'a, T, J, F>(
         }
     });
 }
+
+/// The socket to `/api/thermostat/live`, kept open for as long as the page is
+pub struct LiveUpdates<'a> {
+    /// Whether updates are arriving, the refreshers poll while they aren't
+    pub connected: &'a Signal<bool>,
+    pub latest: &'a Signal<Option<LiveUpdate>>,
+}
+
+pub fn start_live_updates<'a>(cx: Scope<'a>) -> &'a LiveUpdates<'a> {
+    let live = create_ref(
+        cx,
+        LiveUpdates {
+            connected: create_signal(cx, false),
+            latest: create_signal(cx, None),
+        },
+    );
+
+    spawn_local_scoped(cx, async move {
+        loop {
+            if let Ok(mut socket) = WebSocket::open(&live_url()) {
+                while let Some(Ok(message)) = socket.next().await {
+                    let Message::Text(text) = message else { continue };
+                    let Ok(update) = serde_json::from_str::<LiveUpdate>(&text) else { continue };
+                    live.connected.set(true);
+                    live.latest.set(Some(update));
+                }
+            }
+            live.connected.set(false);
+            sleep(LIVE_RETRY).await;
+        }
+    });
+
+    live
+}
+
+/// A browser `WebSocket` can't send `X-Auth`, so the token goes in the query
+fn live_url() -> String {
+    let location = window().unwrap().location();
+    let scheme = match location.protocol().ok().as_deref() {
+        Some("https:") => "wss",
+        _ => "ws",
+    };
+    let host = location.host().unwrap_or_default();
+    format!("{scheme}://{host}/api/thermostat/live?token={}", auth_token())
+}
+
+/// `start_signal_refresher`, except the signal follows whatever `from_live`
+/// picks out of the live updates while the socket is up. Polling only happens
+/// while it's down.
+pub fn start_live_signal_refresher<'a, T, J, F, L>(
+    cx: Scope<'a>,
+    path: &'static str,
+    signal: &'a Signal<T>,
+    interval: Duration,
+    func: F,
+    live: &'a LiveUpdates<'a>,
+    from_live: L,
+) where
+    J: serde::de::DeserializeOwned,
+    F: Fn(J) -> T + 'a,
+    L: Fn(&LiveUpdate) -> Option<T> + 'a,
+{
+    create_effect(cx, move || {
+        if let Some(update) = &*live.latest.get() {
+            if let Some(value) = from_live(update) {
+                signal.set(value);
+            }
+        }
+    });
+
+    spawn_local_scoped(cx, async move {
+        loop {
+            if !*live.connected.get_untracked() {
+                refresh_signal(path, signal, &func).await;
+            }
+
+            sleep(interval).await;
+        }
+    });
+}
+
+/// Milliseconds since the epoch, by the browser's clock
+pub fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+/// True once more than `max_age` has passed since `seen_ms`
+pub fn seen_longer_ago_than(seen_ms: f64, max_age: Duration) -> bool {
+    now_ms() - seen_ms > max_age.as_millis() as f64
+}
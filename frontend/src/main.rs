@@ -3,9 +3,16 @@
 
 use std::time::Duration;
 use sycamore::{futures::spawn_local_scoped, prelude::*};
+use web_sys::window;
 
-use crate::helpers::{create_saved_signal, start_signal_refresher};
-use crate::models::{HvacMode, HvacModeState, HvacRequest, PinState, Temperature, Units};
+use crate::helpers::{
+    create_saved_signal, now_ms, seen_longer_ago_than, start_live_signal_refresher,
+    start_live_updates, start_signal_refresher,
+};
+use crate::models::{
+    DarkMode, HvacModeState, HvacRequest, LiveUpdate, PinState, ProbeList, Staleness,
+    Temperature, Units,
+};
 
 mod ace;
 mod auth;
@@ -15,6 +22,11 @@ mod tabs;
 mod helpers;
 mod models;
 
+/// Values refresh every few seconds, so missing several in a row means the
+/// backend has gone quiet and whatever is on screen is out of date
+const TEMPERATURE_STALE_AFTER: Duration = Duration::from_secs(30);
+const MODE_STALE_AFTER: Duration = Duration::from_secs(2 * 60);
+
 #[derive(Copy, Clone, PartialEq, Eq, Default)]
 pub struct LoggedInState {
     logged_in: Option<bool>,
@@ -28,6 +40,10 @@ fn main() {
         let units = create_saved_signal(cx, "thermostat-units", Units::Celcius);
         provide_context_ref(cx, units);
 
+        let dark_mode = create_saved_signal(cx, "dark-mode", DarkMode(false));
+        provide_context_ref(cx, dark_mode);
+        create_effect(cx, move || set_body_dark(*dark_mode.get()));
+
         let logged_in = create_signal(cx, LoggedInState::default());
         provide_context_ref(cx, logged_in);
 
@@ -36,24 +52,98 @@ fn main() {
             auth::check_logged_in(logged_in).await;
         });
     
-        let hvac_mode = create_saved_signal(cx, "cached-hvac-mode", HvacMode::Off);
+        // When each value last refreshed successfully. Starts at page load
+        // so cached values go stale too if the backend never answers.
+        let mode_seen = create_signal(cx, now_ms());
+        let mode_online = create_signal(cx, true);
+        let temperature_seen = create_signal(cx, now_ms());
+
+        // Pushes the values below as they change, they fall back to polling
+        // whenever it's down
+        let live = start_live_updates(cx);
+
+        let hvac_mode = create_saved_signal(cx, "cached-hvac-mode", HvacRequest::Off);
         provide_context_ref(cx, hvac_mode);
-        start_signal_refresher(
+        start_live_signal_refresher(
             cx,
             "thermostat/mode",
             hvac_mode,
             Duration::from_secs(30),
-            |ms: HvacModeState| ms.mode,
+            |ms: HvacModeState| {
+                mode_seen.set(now_ms());
+                mode_online.set(ms.online.unwrap_or(true));
+                ms.mode
+            },
+            live,
+            |update| match update {
+                LiveUpdate::Mode { mode } => {
+                    mode_seen.set(now_ms());
+                    Some(*mode)
+                }
+                _ => None,
+            },
         );
     
         let temperature = create_saved_signal(cx, "cached-temperature", None::<Temperature>);
         provide_context_ref(cx, temperature);
-        start_signal_refresher(
+        start_live_signal_refresher(
             cx,
             "thermostat/probes/primary/temperature",
             temperature,
             Duration::from_secs(3),
-            |x| Some(Temperature(x)),
+            |x| {
+                temperature_seen.set(now_ms());
+                Some(Temperature(x))
+            },
+            live,
+            |update| match update {
+                LiveUpdate::Temperature { probe, value } if probe == "primary" => {
+                    temperature_seen.set(now_ms());
+                    Some(Some(Temperature(*value)))
+                }
+                _ => None,
+            },
+        );
+
+        let staleness = create_signal(cx, Staleness::default());
+        provide_context_ref(cx, staleness);
+        {
+            let check = move || {
+                let current = Staleness {
+                    temperature: seen_longer_ago_than(
+                        *temperature_seen.get_untracked(),
+                        TEMPERATURE_STALE_AFTER,
+                    ),
+                    mode: !*mode_online.get_untracked()
+                        || seen_longer_ago_than(*mode_seen.get_untracked(), MODE_STALE_AFTER),
+                };
+                if *staleness.get_untracked() != current {
+                    staleness.set(current);
+                }
+            };
+            // Clear right away on a fresh value, and age out on a timer
+            create_effect(cx, move || {
+                temperature_seen.track();
+                mode_seen.track();
+                mode_online.track();
+                check();
+            });
+            spawn_local_scoped(cx, async move {
+                loop {
+                    gloo_timers::future::sleep(Duration::from_secs(5)).await;
+                    check();
+                }
+            });
+        }
+    
+        let probe_list = create_signal(cx, ProbeList::default());
+        provide_context_ref(cx, probe_list);
+        start_signal_refresher(
+            cx,
+            "thermostat/probes",
+            probe_list,
+            Duration::from_secs(30),
+            |probes: Vec<String>| ProbeList(Some(probes)),
         );
     
         let pinstate = create_saved_signal(cx, "cached-pinstate", PinState(HvacRequest::Off));
@@ -63,12 +153,17 @@ fn main() {
             struct HistoryEntry {
                 state: HvacRequest,
             }
-            start_signal_refresher(
+            start_live_signal_refresher(
                 cx,
                 "thermostat/pinstate/history?start=0&stop=0",
                 pinstate,
                 Duration::from_secs(3),
                 |x: Vec<HistoryEntry>| PinState(x.get(0).map(|e| e.state).unwrap_or(HvacRequest::Off)),
+                live,
+                |update| match update {
+                    LiveUpdate::Pinstate { state } => Some(PinState(*state)),
+                    _ => None,
+                },
             );
         }
 
@@ -78,12 +173,31 @@ fn main() {
     })
 }
 
+fn set_body_dark(DarkMode(dark): DarkMode) {
+    let Some(body) = window().and_then(|w| w.document()).and_then(|d| d.body()) else {
+        return;
+    };
+    let classes = body.class_list();
+    let _ = if dark {
+        classes.add_1("dark")
+    } else {
+        classes.remove_1("dark")
+    };
+}
+
 #[component]
 fn App(cx: Scope) -> View<DomNode> {
     let logged_in = use_context::<Signal<LoggedInState>>(cx);
+    let activity = |_: web_sys::Event| auth::record_activity();
 
     view! { cx,
-        div(class="main-body") {
+        div(
+            class="main-body",
+            on:click=activity,
+            on:keydown=activity,
+            on:mousemove=activity,
+            on:touchstart=activity
+        ) {
             (if logged_in.get().logged_in == Some(false) {
                 view! { cx, auth::LoginForm(logged_in) }
             } else if logged_in.get().logged_in == Some(true) {
@@ -103,10 +217,22 @@ fn Main<'a>(cx: Scope<'a>, logged_in: &'a Signal<LoggedInState>) -> View<DomNode
         })
     };
 
+    let auto_logout = create_saved_signal(cx, "auto-logout-minutes", "0".to_string());
+    auth::start_inactivity_logout(cx, logged_in, auto_logout);
+
     view! { cx,
         a(href="#/", on:click=logout, class="logout") {
             "Logout"
         }
+        label(class="auto-logout") {
+            "Auto Logout: "
+            select(bind:value=auto_logout) {
+                option(value="0", selected=*auto_logout.get()=="0") { "Never" }
+                option(value="5", selected=*auto_logout.get()=="5") { "5 min" }
+                option(value="15", selected=*auto_logout.get()=="15") { "15 min" }
+                option(value="60", selected=*auto_logout.get()=="60") { "1 hour" }
+            }
+        }
 
         tabs::TabRoot(is_admin = logged_in.get().logged_in == Some(true) && auth::is_auth_level(3))
     }
@@ -1,5 +1,3 @@
-use std::{fmt, str::FromStr};
-
 use serde::{Serialize, Deserialize};
 
 pub use models::hvac_request::HvacRequest;
@@ -10,43 +8,24 @@ pub enum Units {
     Fahrenheit,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum HvacMode {
-    Off,
-    Heat,
-    Cool,
-}
-
-impl fmt::Display for HvacMode {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            HvacMode::Off => f.write_str("Off"),
-            HvacMode::Heat => f.write_str("Heat"),
-            HvacMode::Cool => f.write_str("Cool"),
-        }
-    }
-}
-
-impl FromStr for HvacMode {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.eq_ignore_ascii_case("Off") {
-            Ok(HvacMode::Off)
-        } else if s.eq_ignore_ascii_case("Heat") {
-            Ok(HvacMode::Heat)
-        } else if s.eq_ignore_ascii_case("Cool") {
-            Ok(HvacMode::Cool)
-        } else {
-            Err(())
-        }
-    }
-}
+/// Saved as a plain bool, newtyped so it has its own context slot
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct DarkMode(pub bool);
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct HvacModeState {
-    pub mode: HvacMode,
+    pub mode: HvacRequest,
+    /// Only sent by the server, false once the unit has gone quiet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub online: Option<bool>,
+}
+
+/// Which of the refreshed values can no longer be trusted, either because the
+/// server says so or because refreshing has been failing for a while
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Staleness {
+    pub temperature: bool,
+    pub mode: bool,
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -73,4 +52,27 @@ pub struct Temperature(pub f32);
 #[serde(transparent)]
 pub struct PinState(pub HvacRequest);
 
+/// What `/api/thermostat/live` pushes, see `hvac::live` on the server. Only
+/// the kinds the page follows are read, the rest come through as `Other`.
+#[derive(Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LiveUpdate {
+    Temperature { probe: String, value: f32 },
+    Mode { mode: HvacRequest },
+    Pinstate { state: HvacRequest },
+    #[serde(other)]
+    Other,
+}
+
+/// `None` until the list has been fetched at least once
+#[derive(Clone, Default)]
+pub struct ProbeList(pub Option<Vec<String>>);
+
+impl ProbeList {
+    /// Only true once we know for sure, not while still loading
+    pub fn is_empty(&self) -> bool {
+        matches!(&self.0, Some(probes) if probes.is_empty())
+    }
+}
+
 
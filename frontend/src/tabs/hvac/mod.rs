@@ -1,16 +1,51 @@
+use serde::{Deserialize, Serialize};
 use sycamore::prelude::*;
+use web_sys::Event;
+
+use crate::helpers::create_saved_signal;
 
 mod mode;
 mod rules;
 
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+enum HvacView {
+    Mode,
+    Rules,
+}
+
 #[component]
 pub fn HvacConfigPage(cx: Scope<'_>) -> View<DomNode> {
+    let active_view = create_saved_signal(cx, "hvac-sub-view", HvacView::Mode);
+
+    let mode_class = create_selector(cx, || match *active_view.get() {
+        HvacView::Mode => "sub-tab-button highlighted",
+        _ => "sub-tab-button",
+    });
+    let rules_class = create_selector(cx, || match *active_view.get() {
+        HvacView::Rules => "sub-tab-button highlighted",
+        _ => "sub-tab-button",
+    });
+
+    let mode_click = move |_e: Event| {
+        active_view.set(HvacView::Mode)
+    };
+    let rules_click = move |_e: Event| {
+        active_view.set(HvacView::Rules)
+    };
+
     view! { cx,
         h2(class = "page-title") { "Hvac Config" }
-        mode::HvacMode()
-        
+
+        div(class = "sub-tab-bar") {
+            div(class = mode_class, on:click = mode_click) { "Mode" }
+            div(class = rules_class, on:click = rules_click) { "Rules" }
+        }
+
         hr {}
 
-        rules::RulesEditor()
+        (match *active_view.get() {
+            HvacView::Mode => view! { cx, mode::HvacMode() },
+            HvacView::Rules => view! { cx, rules::RulesEditor() },
+        })
     }
 }
@@ -5,12 +5,14 @@ use web_sys::{window, Event};
 
 use crate::{
     auth::auth_token,
-    models::{HvacMode, HvacModeState},
+    models::{HvacModeState, HvacRequest, Staleness},
 };
 
 #[component]
 pub fn HvacMode(cx: Scope<'_>) -> View<DomNode> {
-    let hvac_mode = use_context::<Signal<HvacMode>>(cx);
+    let hvac_mode = use_context::<Signal<HvacRequest>>(cx);
+    let staleness = use_context::<Signal<Staleness>>(cx);
+    let mode_class = create_selector(cx, || if staleness.get().mode { "stale" } else { "" });
 
     let new_mode_sig = create_signal(cx, String::new());
     create_effect(cx, || {
@@ -18,9 +20,7 @@ pub fn HvacMode(cx: Scope<'_>) -> View<DomNode> {
     });
 
     let submit_mode = move |_e: Event| {
-        let new_mode = new_mode_sig
-            .get()
-            .parse::<HvacMode>()
+        let new_mode = HvacRequest::from_payload(new_mode_sig.get().as_bytes())
             .expect("This will only fail if someone fucked with the page");
 
         spawn_local_scoped(cx, async move {
@@ -39,7 +39,14 @@ pub fn HvacMode(cx: Scope<'_>) -> View<DomNode> {
     view! { cx,
         div {
             "Current Mode: "
-            (hvac_mode.get())
+            span(class=mode_class) {
+                (hvac_mode.get())
+            }
+            (if staleness.get().mode {
+                view! { cx, span(class="stale-badge") { "stale" } }
+            } else {
+                view! { cx, }
+            })
         }
         div {
             label {
@@ -55,13 +62,16 @@ pub fn HvacMode(cx: Scope<'_>) -> View<DomNode> {
     }
 }
 
-async fn change_mode(new_mode: HvacMode) -> anyhow::Result<()> {
+async fn change_mode(new_mode: HvacRequest) -> anyhow::Result<()> {
     let window = window().unwrap();
     let base = window.origin();
     let result = reqwest::Client::new()
         .put(format!("{base}/api/thermostat/mode"))
         .header("X-Auth", auth_token())
-        .body(serde_json::to_string(&HvacModeState { mode: new_mode }).unwrap())
+        .body(serde_json::to_string(&HvacModeState {
+            mode: new_mode,
+            online: None,
+        }).unwrap())
         .send()
         .await?;
 
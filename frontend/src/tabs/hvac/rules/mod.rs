@@ -1,8 +1,9 @@
 use std::{collections::BTreeSet, time::Duration};
 
+use chrono::Local;
 use gloo_timers::future::sleep;
 use gloo_utils::format::JsValueSerdeExt;
-use models::hvac_request::HvacRequest;
+use models::{hvac_request::HvacRequest, script_log::ScriptLogLine};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use sycamore::{futures::spawn_local_scoped, prelude::*};
@@ -19,6 +20,7 @@ use crate::{
 pub async fn RulesEditor(cx: Scope<'_>) -> View<DomNode> {
     let lua_title = create_saved_signal(cx, "lua-editor-script-title", "configname".to_string());
     let lua_text = create_saved_signal(cx, "lua-editor-text", SAMPLE_LUA_CONFIG.to_string());
+    let lua_position = create_saved_signal(cx, "lua-editor-position", EditorPosition::default());
     let lua_edit_ref = create_node_ref(cx);
     let editor_ref = create_signal(cx, None);
     on_mount(cx, move || {
@@ -41,6 +43,11 @@ pub async fn RulesEditor(cx: Scope<'_>) -> View<DomNode> {
         editor.set_value(&*lua_text.get());
         editor.selection().clear_selection();
 
+        // Put the cursor and scroll back where they were before the tab switch
+        let position = *lua_position.get_untracked();
+        editor.move_cursor_to(position.row, position.column);
+        editor.session().set_scroll_top(position.scroll_top);
+
         spawn_local_scoped(cx, async move {
             let mut last_text = editor.get_value();
             loop {
@@ -50,6 +57,11 @@ pub async fn RulesEditor(cx: Scope<'_>) -> View<DomNode> {
                     lua_text.set(new_text.clone());
                     last_text = new_text;
                 }
+
+                let position = EditorPosition::of(&editor);
+                if position != *lua_position.get_untracked() {
+                    lua_position.set(position);
+                }
             }
         });
     });
@@ -59,6 +71,12 @@ pub async fn RulesEditor(cx: Scope<'_>) -> View<DomNode> {
         refresh_signal("thermostat/lua/scripts", script_list, |x: Vec<String>| x).await
     });
 
+    let active_name = create_signal(cx, None);
+    spawn_local_scoped(cx, async move {
+        refresh_signal("thermostat/lua/active_script/name", active_name, |x: Option<String>| x)
+            .await
+    });
+
     let save_error = create_signal(cx, String::new());
     let do_save = {
         move |_e: Event| {
@@ -86,6 +104,8 @@ pub async fn RulesEditor(cx: Scope<'_>) -> View<DomNode> {
             spawn_local_scoped(cx, async move {
                 save_script(&name, script, save_error).await;
                 refresh_signal("thermostat/lua/scripts", script_list, |x: Vec<String>| x).await;
+                refresh_signal("thermostat/lua/active_script/name", active_name, |x: Option<String>| x)
+                    .await;
             });
         }
     };
@@ -112,8 +132,11 @@ pub async fn RulesEditor(cx: Scope<'_>) -> View<DomNode> {
             let editor = editor_ref.get();
             let Some(editor) = (*editor).clone() else { return };
             let script_text = editor.get_value();
+            let name = Some((*lua_title.get()).clone()).filter(|name| !name.is_empty());
             spawn_local_scoped(cx, async move {
-                activate_script(script_text, validation_results).await;
+                activate_script(script_text, name, validation_results).await;
+                refresh_signal("thermostat/lua/active_script/name", active_name, |x: Option<String>| x)
+                    .await;
             })
         }
     };
@@ -142,6 +165,27 @@ pub async fn RulesEditor(cx: Scope<'_>) -> View<DomNode> {
         })
     };
 
+    let logs = create_signal(cx, String::new());
+    let get_logs = move |_e: Event| {
+        spawn_local_scoped(cx, async move {
+            refresh_signal(
+                "thermostat/lua/logs",
+                logs,
+                move |lines: Vec<ScriptLogLine>| {
+                    lines
+                        .iter()
+                        .map(|line| {
+                            let time = line.time.with_timezone(&Local).format("%H:%M:%S");
+                            format!("[{time}] {}", line.message)
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                },
+            )
+            .await;
+        })
+    };
+
     view! { cx,
         h3 { "Saved Scripts" }
         table {
@@ -166,12 +210,38 @@ pub async fn RulesEditor(cx: Scope<'_>) -> View<DomNode> {
                             })
                         }
                     };
+                    let do_delete = {
+                        let name = name.clone();
+                        move |_e: Event| {
+                            let window = window().unwrap();
+                            if !window.confirm_with_message(&format!("Delete the saved script {name}?")).unwrap() {
+                                return;
+                            }
+
+                            let name = name.clone();
+                            spawn_local_scoped(cx, async move {
+                                delete_script(&name, save_error).await;
+                                refresh_signal("thermostat/lua/scripts", script_list, |x: Vec<String>| x).await;
+                                refresh_signal("thermostat/lua/active_script/name", active_name, |x: Option<String>| x).await;
+                            })
+                        }
+                    };
+                    let is_active = {
+                        let name = name.clone();
+                        create_selector(cx, move || active_name.get().as_deref() == Some(&*name))
+                    };
                     view! { cx,
                         tr {
                             td { (name) }
+                            td {
+                                (if *is_active.get() { "(active)" } else { "" })
+                            }
                             td {
                                 input(type="button", value="Load", on:click=do_load)
                             }
+                            td {
+                                input(type="button", value="Delete", on:click=do_delete)
+                            }
                         }
                     }
                 }
@@ -208,6 +278,37 @@ pub async fn RulesEditor(cx: Scope<'_>) -> View<DomNode> {
         div {
             pre { (issues.get()) }
         }
+
+        div {
+            input(type="button", value="Get Logs", on:click=get_logs)
+        }
+        div {
+            pre { (logs.get()) }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct EditorPosition {
+    row: u32,
+    column: u32,
+    scroll_top: f64,
+}
+
+impl EditorPosition {
+    fn of(editor: &Editor) -> Self {
+        #[derive(Default, Deserialize)]
+        struct Cursor {
+            row: u32,
+            column: u32,
+        }
+
+        let cursor: Cursor = editor.get_cursor_position().into_serde().unwrap_or_default();
+        EditorPosition {
+            row: cursor.row,
+            column: cursor.column,
+            scroll_top: editor.session().get_scroll_top(),
+        }
     }
 }
 
@@ -216,12 +317,20 @@ struct ScriptBody {
     script: String,
 }
 
+#[derive(Clone, Serialize)]
+struct ActivateBody {
+    script: String,
+    name: Option<String>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 enum ValidationResponse {
     Error(String),
     Results {
         output: Option<HvacRequest>,
         issues: BTreeSet<String>,
+        #[serde(default)]
+        printed: Vec<String>,
     },
 }
 
@@ -241,6 +350,22 @@ async fn load_script(editor: Editor, name: &str) {
     editor.selection().clear_selection();
 }
 
+async fn delete_script(name: &str, error: &Signal<String>) {
+    let window = window().unwrap();
+    let base = window.origin();
+    let result = reqwest::Client::new()
+        .delete(format!("{base}/api/thermostat/lua/scripts/{name}"))
+        .header("X-Auth", auth_token())
+        .send()
+        .await;
+
+    match result {
+        Ok(response) if response.status() == StatusCode::OK => {}
+        Ok(response) => error.set(format!("Delete failed: HTTP {}", response.status())),
+        Err(e) => error.set(format!("Server Error: {e}")),
+    }
+}
+
 async fn save_script(name: &str, script: String, error: &Signal<String>) {
     if name.is_empty() {
         return;
@@ -342,12 +467,23 @@ async fn validate_script(script: String, results: &Signal<String>, is_good: &Sig
             message += "Validation Error\n";
             message += &error;
         }
-        ValidationResponse::Results { output, issues } => {
+        ValidationResponse::Results {
+            output,
+            issues,
+            printed,
+        } => {
             message += "Output: ";
             match output {
                 Some(output) => message += output.payload_str(),
                 None => message += "nil",
             }
+            if !printed.is_empty() {
+                message += "\n\nPrinted";
+                for line in printed {
+                    message += "\n";
+                    message += &line;
+                }
+            }
             if !issues.is_empty() {
                 message += &format!("\n\n{} Issues Found", issues.len());
                 for issue in issues {
@@ -363,11 +499,11 @@ async fn validate_script(script: String, results: &Signal<String>, is_good: &Sig
     results.set(message);
 }
 
-async fn activate_script(script: String, results: &Signal<String>) {
+async fn activate_script(script: String, name: Option<String>, results: &Signal<String>) {
     let window = window().unwrap();
     let base = window.origin();
 
-    let data = ScriptBody { script };
+    let data = ActivateBody { script, name };
 
     let result = reqwest::Client::new()
         .put(format!("{base}/api/thermostat/lua/active_script"))
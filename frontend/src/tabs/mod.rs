@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use sycamore::prelude::*;
 use web_sys::Event;
 
-use crate::helpers::create_saved_signal;
+use crate::{helpers::create_saved_signal, models::DarkMode};
 
 mod admin;
 mod data;
@@ -47,6 +47,11 @@ pub fn TabRoot(cx: Scope, params: TabRootParams) -> View<DomNode> {
         _ => "tab-button",
     });
 
+    let dark_mode = use_context::<Signal<DarkMode>>(cx);
+    let dark_mode_click = move |_e: Event| {
+        dark_mode.set(DarkMode(!dark_mode.get().0))
+    };
+
     let quick_click = move |_e: Event| {
         active_tab.set(ActiveTab::Quick)
     };
@@ -80,6 +85,9 @@ pub fn TabRoot(cx: Scope, params: TabRootParams) -> View<DomNode> {
             } else {
                 view! { cx, }
             })
+            div(class = "tab-button theme-toggle", on:click = dark_mode_click) {
+                (if dark_mode.get().0 { "☀️" } else { "🌙" })
+            }
         }
 
         div(class = "active-tab") {
@@ -1,6 +1,6 @@
 use sycamore::prelude::*;
 
-use crate::controls::{AtticFan, thermostat::{temp_display::TemperatureDisplay, cmd_override::CommandOverride, oneshot_setpoint::OneshotSetpoint}};
+use crate::controls::{AtticFan, AwayMode, ComfortProfile, SystemStatusPanel, thermostat::{temp_display::TemperatureDisplay, cmd_override::CommandOverride, oneshot_setpoint::OneshotSetpoint}};
 
 #[component]
 pub fn QuickAccessPage(cx: Scope<'_>) -> View<DomNode> {
@@ -15,11 +15,23 @@ pub fn QuickAccessPage(cx: Scope<'_>) -> View<DomNode> {
 
         hr {}
 
+        AwayMode()
+
+        hr {}
+
+        ComfortProfile()
+
+        hr {}
+
         // TODO: Replace this
         CommandOverride()
 
         hr {}
 
         OneshotSetpoint()
+
+        hr {}
+
+        SystemStatusPanel()
     }
 }
@@ -0,0 +1,104 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fan {
+    RoofFan,
+    BigSucc,
+}
+
+impl Fan {
+    pub const ALL: [Fan; 2] = [Fan::RoofFan, Fan::BigSucc];
+
+    /// The number the fan controller knows the fan by
+    pub fn to_index(self) -> usize {
+        match self {
+            Fan::RoofFan => 0,
+            Fan::BigSucc => 1,
+        }
+    }
+
+    pub fn from_index(index: usize) -> Option<Fan> {
+        Fan::ALL.get(index).copied()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UnknownFan(pub String);
+
+impl fmt::Display for UnknownFan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown fan {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFan {}
+
+/// Accepts the friendly names as well as the old numeric indices
+impl FromStr for Fan {
+    type Err = UnknownFan;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "roof" | "roof_fan" => Ok(Fan::RoofFan),
+            "succ" | "big_succ" => Ok(Fan::BigSucc),
+            _ => s
+                .parse()
+                .ok()
+                .and_then(Fan::from_index)
+                .ok_or_else(|| UnknownFan(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Fan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Fan::RoofFan => f.write_str("roof"),
+            Fan::BigSucc => f.write_str("succ"),
+        }
+    }
+}
+
+/// Both attic fans at once, as served by `/api/atticfan/state`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AtticFanState {
+    pub big_succ: bool,
+    pub roof_fan: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_numeric_indices() {
+        assert_eq!("0".parse::<Fan>().unwrap(), Fan::RoofFan);
+        assert_eq!("1".parse::<Fan>().unwrap(), Fan::BigSucc);
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!("roof".parse::<Fan>().unwrap(), Fan::RoofFan);
+        assert_eq!("roof_fan".parse::<Fan>().unwrap(), Fan::RoofFan);
+        assert_eq!("succ".parse::<Fan>().unwrap(), Fan::BigSucc);
+        assert_eq!("big_succ".parse::<Fan>().unwrap(), Fan::BigSucc);
+    }
+
+    #[test]
+    fn rejects_unknown_fans() {
+        for fan in ["2", "-1", "kitchen", "", "Roof"] {
+            assert!(fan.parse::<Fan>().is_err(), "{:?} parsed", fan);
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for fan in Fan::ALL {
+            assert_eq!(fan.to_string().parse::<Fan>().unwrap(), fan);
+            assert_eq!(Fan::from_index(fan.to_index()), Some(fan));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 use serde::{Serialize, Deserialize};
 
@@ -11,12 +11,14 @@ pub enum HvacRequest {
 }
 
 impl HvacRequest {
+    /// Either a full name like `FromStr`, or a single letter, which is all
+    /// some of the MQTT devices send
     pub fn from_payload(payload: &[u8]) -> Option<HvacRequest> {
-        match payload.get(0) {
-            Some(b'o' | b'O') => Some(HvacRequest::Off),
-            Some(b'h' | b'H') => Some(HvacRequest::Heat),
-            Some(b'c' | b'C') => Some(HvacRequest::Cool),
-            _ => None,
+        match payload {
+            [b'o' | b'O'] => Some(HvacRequest::Off),
+            [b'h' | b'H'] => Some(HvacRequest::Heat),
+            [b'c' | b'C'] => Some(HvacRequest::Cool),
+            _ => std::str::from_utf8(payload).ok()?.parse().ok(),
         }
     }
 
@@ -35,6 +37,31 @@ impl HvacRequest {
     pub fn payload(self) -> &'static [u8] {
         self.payload_str().as_bytes()
     }
+
+    /// Combine two calls where either one asking is enough, e.g. two zones
+    /// sharing a unit. Whichever call matches `mode` wins and everything else
+    /// counts as `Off`, since the unit can't do what it isn't set to. Unlike
+    /// `&`, which needs both calls to agree, this only needs one of them.
+    pub fn merge(self, other: HvacRequest, mode: HvacRequest) -> HvacRequest {
+        if mode != HvacRequest::Off && (self == mode || other == mode) {
+            mode
+        } else {
+            HvacRequest::Off
+        }
+    }
+}
+
+impl FromStr for HvacRequest {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(HvacRequest::Off),
+            "heat" => Ok(HvacRequest::Heat),
+            "cool" => Ok(HvacRequest::Cool),
+            _ => Err(()),
+        }
+    }
 }
 
 impl std::ops::BitAnd for HvacRequest {
@@ -63,3 +90,76 @@ impl Default for HvacRequest {
         HvacRequest::Off
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [HvacRequest; 3] = [HvacRequest::Off, HvacRequest::Heat, HvacRequest::Cool];
+
+    #[test]
+    fn serializes_as_the_payload() {
+        for request in ALL {
+            let json = serde_json::to_string(&request).unwrap();
+            assert_eq!(json, format!("\"{}\"", request.payload_str()));
+            assert_eq!(serde_json::from_str::<HvacRequest>(&json).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn full_names_parse_in_any_case() {
+        for request in ALL {
+            let name = request.payload_str();
+            assert_eq!(name.parse(), Ok(request));
+            assert_eq!(name.to_uppercase().parse(), Ok(request));
+            assert_eq!(HvacRequest::from_payload(name.as_bytes()), Some(request));
+        }
+        assert_eq!("Heat".parse(), Ok(HvacRequest::Heat));
+    }
+
+    #[test]
+    fn only_the_payload_accepts_a_single_letter() {
+        assert_eq!(HvacRequest::from_payload(b"h"), Some(HvacRequest::Heat));
+        assert_eq!(HvacRequest::from_payload(b"C"), Some(HvacRequest::Cool));
+        assert_eq!(HvacRequest::from_payload(b"o"), Some(HvacRequest::Off));
+        assert_eq!("h".parse::<HvacRequest>(), Err(()));
+    }
+
+    #[test]
+    fn a_matching_first_byte_isnt_enough() {
+        for junk in ["horse", "cold", "often", "heater", " heat", ""] {
+            assert_eq!(junk.parse::<HvacRequest>(), Err(()), "{:?}", junk);
+            assert_eq!(HvacRequest::from_payload(junk.as_bytes()), None, "{:?}", junk);
+        }
+        assert_eq!(HvacRequest::from_payload(&[0xff]), None);
+    }
+
+    #[test]
+    fn merge_takes_whichever_call_matches_the_mode() {
+        use HvacRequest::*;
+        // Rows and columns are the two calls, in the order of `ALL`
+        let tables = [
+            (Off, [[Off, Off, Off], [Off, Off, Off], [Off, Off, Off]]),
+            (Heat, [[Off, Heat, Off], [Heat, Heat, Heat], [Off, Heat, Off]]),
+            (Cool, [[Off, Off, Cool], [Off, Off, Cool], [Cool, Cool, Cool]]),
+        ];
+        for (mode, table) in tables {
+            for (a, row) in ALL.iter().zip(table) {
+                for (b, expected) in ALL.iter().zip(row) {
+                    assert_eq!(a.merge(*b, mode), expected, "{a:?} + {b:?} in {mode:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merge_needs_one_call_where_bitand_needs_both() {
+        use HvacRequest::*;
+        assert_eq!(Heat.merge(Off, Heat), Heat);
+        assert_eq!(Heat & Off, Off);
+        assert_eq!(Heat.merge(Heat, Heat), Heat & Heat);
+        // `&` doesn't know the mode, so it never has to drop a call
+        assert_eq!(Cool & Cool, Cool);
+        assert_eq!(Cool.merge(Cool, Heat), Off);
+    }
+}
@@ -0,0 +1,94 @@
+//! Every Redis key used by the server and thermostatd. Keeping them in one
+//! place turns a typo into a compile error instead of a silently empty read.
+
+pub const AUTH_PASSWORD: &str = "auth.password";
+pub const AUTH_LEVEL: &str = "auth.level";
+/// Newest first, capped by the server
+pub const AUTH_AUDIT: &str = "auth.audit";
+/// How many audit entries to keep
+pub const CONFIG_AUDIT_MAX_LEN: &str = "auth.config.audit_max_len";
+/// Seconds an audit entry is kept for, unset to keep them until they're
+/// pushed out by newer ones
+pub const CONFIG_AUDIT_MAX_AGE: &str = "auth.config.audit_max_age";
+
+/// Set to 1 to record every API request in `REQUEST_LOG`, read at startup
+pub const REQUEST_LOG_ENABLED: &str = "server.config.request_log";
+pub const REQUEST_LOG: &str = "server.request_log";
+/// Set of MQTT topics whose retained messages get cleared at startup
+pub const DEPRECATED_RETAINED_TOPICS: &str = "server.config.deprecated_retained_topics";
+
+pub const MIN_TOGGLE_INTERVAL_KEY: &str = "atticfan.config.min_toggle_interval";
+
+pub const PROBE_ENDPOINTS: &str = "thermostat.config.probe_endpoints";
+/// Hash of probe name to the minimum milliseconds between processed updates
+pub const PROBE_MIN_INTERVALS: &str = "thermostat.config.probe_min_intervals";
+/// Hash of probe name to the milliseconds without an update before it's stale
+pub const PROBE_STALE_AFTER: &str = "thermostat.config.probe_stale_after";
+pub const CONFIG_MODE: &str = "thermostat.config.mode";
+/// Milliseconds a mode change waits for the unit to confirm it
+pub const CONFIG_MODE_CONFIRM_TIMEOUT: &str = "thermostat.config.mode_confirm_timeout_ms";
+/// When set to 1, the remote state is only republished on change (plus a keepalive)
+pub const CONFIG_PUBLISH_ON_CHANGE: &str = "thermostat.config.publish_on_change";
+/// How many pinstate changes to keep, read at startup
+pub const CONFIG_PINSTATE_HISTORY_MAX_LEN: &str = "thermostat.config.pinstate_history_max_len";
+pub const CURRENT_RULESET_KEY: &str = "thermostat.config.timedruleset";
+pub const SAVED_RULES: &str = "thermostat.config.savedrules";
+pub const AWAY_MODE_KEY: &str = "thermostat.config.away";
+pub const COMFORT_PROFILE_KEY: &str = "thermostat.config.comfort_profile";
+pub const ONESHOT_BOUNDS_KEY: &str = "thermostat.config.oneshot_bounds";
+/// `latitude,longitude` for the Lua `sun` helpers, shared with thermostatd
+pub const CONFIG_LOCATION: &str = "thermostat.config.location";
+/// The running command override, so it outlives a restart
+pub const OVERRIDE_PULSE_KEY: &str = "thermostat.override_pulse";
+
+pub const LUA_SAVED_SCRIPTS: &str = "thermostat.lua.saved";
+pub const LUA_CURRENT_SCRIPT: &str = "thermostat.lua.current";
+/// Which saved script `LUA_CURRENT_SCRIPT` came from, unset for ad hoc scripts
+pub const LUA_CURRENT_NAME: &str = "thermostat.lua.current_name";
+/// JSON schedule of which saved script should be active when
+pub const LUA_SCRIPT_SCHEDULE: &str = "thermostat.lua.schedule";
+
+/// Prefix of the per-probe history lists, see `probe_history`
+pub const PROBE_HISTORY: &str = "thermostat.probes.history";
+/// The last compaction report for each probe, keyed by probe name
+pub const PROBE_HISTORY_REPORTS: &str = "thermostat.probes.history_reports";
+pub const PINSTATE_HISTORY: &str = "thermostat.pinstate.history";
+
+pub fn probe_history(probe: &str) -> String {
+    format!("{PROBE_HISTORY}:{probe}")
+}
+
+pub mod thermostatd {
+    use std::fmt::LowerHex;
+
+    pub const SAVED_SCRIPT: &str = "thermostatd.script";
+    /// Prefix of the per-script persisted tables, see `script_persist`
+    pub const SCRIPT_PERSIST: &str = "thermostatd.script_persist";
+    pub const TIMED_OVERRIDE: &str = "thermostatd.timed_override";
+    pub const ONESHOT_OVERRIDE: &str = "thermostatd.oneshot_override";
+    /// Not the same hash as the server's `PROBE_ENDPOINTS`
+    pub const PROBE_ENDPOINTS: &str = "thermostat.probes";
+
+    pub fn script_persist(script_hash: impl LowerHex) -> String {
+        format!("{SCRIPT_PERSIST}:{script_hash:x}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_history_keys() {
+        assert_eq!(probe_history("primary"), "thermostat.probes.history:primary");
+        assert_eq!(probe_history("attic"), "thermostat.probes.history:attic");
+    }
+
+    #[test]
+    fn script_persist_keys_are_lowercase_hex() {
+        assert_eq!(
+            thermostatd::script_persist(0xBEEFu32),
+            "thermostatd.script_persist:beef"
+        );
+    }
+}
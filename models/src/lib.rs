@@ -1,5 +1,15 @@
+pub mod atticfan;
 pub mod hvac_request;
+pub mod keys;
 pub mod mixer;
+pub mod script_log;
 pub mod set_point;
+pub mod status;
+pub mod sun;
 pub mod thermostatd;
 pub mod timed_rule;
+pub mod units;
+
+/// The probe the thermostat follows. It always exists on the server, and isn't
+/// part of the configured probe endpoints.
+pub const PRIMARY_PROBE: &str = "primary";
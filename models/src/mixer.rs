@@ -4,3 +4,21 @@ pub trait Mixer {
     fn mode(&self) -> HvacRequest;
     fn get_probe_temp(&self, probe: &str) -> impl std::future::Future<Output = Option<f32>> + Send;
 }
+
+/// Reports the same temperature for every probe
+#[cfg(test)]
+pub(crate) struct TestMixer {
+    pub mode: HvacRequest,
+    pub temp: Option<f32>,
+}
+
+#[cfg(test)]
+impl Mixer for TestMixer {
+    fn mode(&self) -> HvacRequest {
+        self.mode
+    }
+
+    async fn get_probe_temp(&self, _probe: &str) -> Option<f32> {
+        self.temp
+    }
+}
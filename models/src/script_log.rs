@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How many lines a script log keeps before dropping the oldest
+pub const SCRIPT_LOG_CAPACITY: usize = 200;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScriptLogLine {
+    pub time: DateTime<Utc>,
+    pub message: String,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ScriptLog {
+    lines: VecDeque<ScriptLogLine>,
+}
+
+impl ScriptLog {
+    pub const fn new() -> Self {
+        ScriptLog {
+            lines: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, message: String) {
+        while self.lines.len() >= SCRIPT_LOG_CAPACITY {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(ScriptLogLine {
+            time: Utc::now(),
+            message,
+        });
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &ScriptLogLine> {
+        self.lines.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(log: &ScriptLog) -> Vec<&str> {
+        log.lines().map(|line| line.message.as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_lines_in_order() {
+        let mut log = ScriptLog::new();
+        log.push("first".into());
+        log.push("second".into());
+        assert_eq!(messages(&log), ["first", "second"]);
+    }
+
+    #[test]
+    fn drops_the_oldest_lines_past_capacity() {
+        let mut log = ScriptLog::new();
+        for i in 0..SCRIPT_LOG_CAPACITY + 5 {
+            log.push(i.to_string());
+        }
+        let messages = messages(&log);
+        assert_eq!(messages.len(), SCRIPT_LOG_CAPACITY);
+        assert_eq!(messages[0], "5");
+        assert_eq!(messages.last().unwrap(), &(SCRIPT_LOG_CAPACITY + 4).to_string());
+    }
+}
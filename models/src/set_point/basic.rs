@@ -2,14 +2,16 @@ use serde::{Deserialize, Serialize};
 
 use crate::mixer::Mixer;
 
-use super::EMPTY_REQUEST;
+use super::{default_enabled, report_adjustment, EMPTY_REQUEST};
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(from = "UncheckedBasicSetPoint")]
 pub struct BasicSetPoint {
     pub probe: String,
     pub weight: f32,
     pub min_temp: f32,
     pub max_temp: f32,
+    pub enabled: bool,
 }
 
 impl BasicSetPoint {
@@ -29,3 +31,96 @@ impl BasicSetPoint {
         }
     }
 }
+
+/// Same fields as `BasicSetPoint`. A transparent wrapper around it would
+/// deserialize through `from` again and never return.
+#[derive(Deserialize)]
+struct UncheckedBasicSetPoint {
+    probe: String,
+    weight: f32,
+    min_temp: f32,
+    max_temp: f32,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+}
+
+impl From<UncheckedBasicSetPoint> for BasicSetPoint {
+    fn from(unchecked: UncheckedBasicSetPoint) -> Self {
+        let UncheckedBasicSetPoint {
+            probe,
+            weight,
+            mut min_temp,
+            mut max_temp,
+            mut enabled,
+        } = unchecked;
+
+        // Reversed bounds would push towards the wrong side
+        if min_temp > max_temp {
+            report_adjustment(format!(
+                "Set point on {probe} had min_temp {min_temp} above max_temp {max_temp}, swapped them"
+            ));
+            std::mem::swap(&mut min_temp, &mut max_temp);
+        }
+        // Nothing compares sensibly against NaN, so there's nothing to weigh
+        if enabled && (!min_temp.is_finite() || !max_temp.is_finite()) {
+            report_adjustment(format!(
+                "Set point on {probe} has a non-finite bound ({min_temp}, {max_temp}), disabled it"
+            ));
+            enabled = false;
+        }
+
+        BasicSetPoint {
+            probe,
+            weight,
+            min_temp,
+            max_temp,
+            enabled,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::set_point::take_adjustments;
+
+    fn parse(min_temp: &str, max_temp: &str) -> BasicSetPoint {
+        let json = format!(
+            r#"{{"probe":"primary","weight":1.0,"min_temp":{min_temp},"max_temp":{max_temp}}}"#
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn keeps_ordered_bounds() {
+        take_adjustments();
+        let set_point = parse("20.0", "22.0");
+        assert_eq!((set_point.min_temp, set_point.max_temp), (20.0, 22.0));
+        assert!(set_point.enabled);
+        assert!(take_adjustments().is_empty());
+    }
+
+    #[test]
+    fn swaps_reversed_bounds() {
+        take_adjustments();
+        let set_point = parse("23.0", "21.0");
+        assert_eq!((set_point.min_temp, set_point.max_temp), (21.0, 23.0));
+        assert!(set_point.enabled);
+        assert_eq!(take_adjustments().len(), 1);
+    }
+
+    #[test]
+    fn disables_non_finite_bounds() {
+        take_adjustments();
+        // JSON has no NaN, so go around the deserializer
+        let set_point = BasicSetPoint::from(UncheckedBasicSetPoint {
+            probe: "primary".into(),
+            weight: 1.0,
+            min_temp: f32::NAN,
+            max_temp: 22.0,
+            enabled: true,
+        });
+        assert!(!set_point.enabled);
+        assert_eq!(take_adjustments().len(), 1);
+    }
+}
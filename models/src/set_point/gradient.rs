@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::mixer::Mixer;
 
-use super::EMPTY_REQUEST;
+use super::{default_enabled, report_adjustment, EMPTY_REQUEST};
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 #[serde(from = "UnsortedGradientSetPoint")]
@@ -10,6 +10,7 @@ pub struct GradientSetPoint {
     pub probe: String,
     pub weight: f32,
     pub stop_points: Vec<StopPoint>,
+    pub enabled: bool,
 }
 
 impl GradientSetPoint {
@@ -59,9 +60,13 @@ impl GradientSetPoint {
 
     fn calculate(&self, temp: f32, left: &StopPoint, right: &StopPoint) -> (f32, f32) {
         let dt = right.temp - left.temp;
+        if dt == 0.0 {
+            return (left.heat_value * self.weight, left.cool_value * self.weight);
+        }
         let dh = right.heat_value - left.heat_value;
         let dc = right.cool_value - left.cool_value;
-        let t = (temp - left.temp) / dt;
+        // Hold the outermost values rather than extrapolating past them
+        let t = ((temp - left.temp) / dt).clamp(0.0, 1.0);
         (
             (left.heat_value + dh * t) * self.weight,
             (left.cool_value + dc * t) * self.weight,
@@ -76,21 +81,49 @@ pub struct StopPoint {
     pub cool_value: f32,
 }
 
+/// Same fields as `GradientSetPoint`, see `UncheckedBasicSetPoint` for why
+/// this can't simply wrap it
 #[derive(Deserialize)]
-#[serde(transparent)]
-struct UnsortedGradientSetPoint(GradientSetPoint);
+struct UnsortedGradientSetPoint {
+    probe: String,
+    weight: f32,
+    stop_points: Vec<StopPoint>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+}
 
 impl From<UnsortedGradientSetPoint> for GradientSetPoint {
-    fn from(mut unsorted: UnsortedGradientSetPoint) -> Self {
-        unsorted
-            .0
-            .stop_points
-            .retain(|point| point.temp.is_finite());
-        unsorted
-            .0
-            .stop_points
-            .sort_by_key(|point| cursed_float_sortable(point.temp));
-        unsorted.0
+    fn from(unsorted: UnsortedGradientSetPoint) -> Self {
+        let UnsortedGradientSetPoint {
+            probe,
+            weight,
+            mut stop_points,
+            enabled,
+        } = unsorted;
+
+        let given = stop_points.len();
+        stop_points.retain(|point| point.temp.is_finite());
+        stop_points.sort_by_key(|point| cursed_float_sortable(point.temp));
+
+        // Points sharing a temperature leave nothing to interpolate between,
+        // keep whichever came last
+        stop_points.reverse();
+        stop_points.dedup_by(|point, kept| point.temp == kept.temp);
+        stop_points.reverse();
+
+        if stop_points.len() < given {
+            report_adjustment(format!(
+                "Gradient set point on {probe} dropped {} non-finite or duplicate stop points",
+                given - stop_points.len()
+            ));
+        }
+
+        GradientSetPoint {
+            probe,
+            weight,
+            stop_points,
+            enabled,
+        }
     }
 }
 
@@ -99,3 +132,151 @@ fn cursed_float_sortable(f: f32) -> impl Ord {
     // xor the lower 31 bits by the value in the sign bit
     i ^ ((i >> 30) as u32 >> 1) as i32
 }
+
+#[cfg(test)]
+mod tests {
+    use futures_executor::block_on;
+
+    use super::*;
+    use crate::{
+        hvac_request::HvacRequest,
+        mixer::TestMixer,
+        set_point::SetPoint,
+        timed_rule::{DaySet, TimedRule, TimedRuleSet},
+    };
+
+    #[test]
+    fn deserializes_without_enabled() {
+        let set_point: GradientSetPoint = serde_json::from_str(
+            r#"{"probe":"primary","weight":1.0,"stop_points":[
+                {"temp":20.0,"heat_value":1.0,"cool_value":0.0},
+                {"temp":24.0,"heat_value":0.0,"cool_value":1.0}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(set_point.enabled);
+        assert_eq!(set_point.stop_points.len(), 2);
+    }
+
+    #[test]
+    fn round_trips_through_a_ruleset() {
+        let set_point = GradientSetPoint {
+            probe: "bedroom".into(),
+            weight: 0.5,
+            stop_points: vec![
+                StopPoint {
+                    temp: 19.0,
+                    heat_value: 1.0,
+                    cool_value: 0.0,
+                },
+                StopPoint {
+                    temp: 23.0,
+                    heat_value: 0.0,
+                    cool_value: 1.0,
+                },
+            ],
+            enabled: false,
+        };
+        let json = serde_json::to_string(&set_point).unwrap();
+        assert_eq!(serde_json::from_str::<GradientSetPoint>(&json).unwrap(), set_point);
+
+        // The way the API saves it and the way the mixer loads it back
+        let ruleset = TimedRuleSet::new(
+            vec![TimedRule {
+                set_points: vec![SetPoint::Gradient(set_point)],
+                start_time: "06:00:00".parse().unwrap(),
+                days_enabled: DaySet::all(),
+            }],
+            0.05,
+        );
+        let json = serde_json::to_string(&ruleset).unwrap();
+        let loaded: TimedRuleSet = serde_json::from_str(&json).unwrap();
+        assert!(loaded.rules[0] == ruleset.rules[0]);
+    }
+
+    fn point(temp: f32, heat_value: f32) -> StopPoint {
+        StopPoint {
+            temp,
+            heat_value,
+            cool_value: 0.0,
+        }
+    }
+
+    fn evaluate_at(set_point: &GradientSetPoint, temp: f32) -> (f32, f32) {
+        let mixer = TestMixer {
+            mode: HvacRequest::Heat,
+            temp: Some(temp),
+        };
+        block_on(set_point.evaluate(&mixer))
+    }
+
+    #[test]
+    fn sorts_and_dedups_stop_points() {
+        let set_point: GradientSetPoint = serde_json::from_str(
+            r#"{"probe":"primary","weight":1.0,"stop_points":[
+                {"temp":24.0,"heat_value":0.0,"cool_value":0.0},
+                {"temp":20.0,"heat_value":0.5,"cool_value":0.0},
+                {"temp":20.0,"heat_value":1.0,"cool_value":0.0}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(set_point.stop_points, vec![point(20.0, 1.0), point(24.0, 0.0)]);
+    }
+
+    #[test]
+    fn single_temperature_range() {
+        let set_point: GradientSetPoint = serde_json::from_str(
+            r#"{"probe":"primary","weight":1.0,"stop_points":[
+                {"temp":21.0,"heat_value":0.2,"cool_value":0.0},
+                {"temp":21.0,"heat_value":0.8,"cool_value":0.0}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(evaluate_at(&set_point, 15.0), (0.8, 0.0));
+    }
+
+    #[test]
+    fn zero_width_range_uses_left_point() {
+        // Built directly, so the dedup in deserialization never ran
+        let set_point = GradientSetPoint {
+            probe: "primary".into(),
+            weight: 1.0,
+            stop_points: vec![point(21.0, 0.2), point(21.0, 0.8)],
+            enabled: true,
+        };
+        let (heat, cool) = evaluate_at(&set_point, 25.0);
+        assert!(heat.is_finite() && cool.is_finite());
+        assert_eq!((heat, cool), (0.2, 0.0));
+    }
+
+    #[test]
+    fn holds_outside_the_stop_points() {
+        let set_point = GradientSetPoint {
+            probe: "primary".into(),
+            weight: 1.0,
+            stop_points: vec![point(20.0, 1.0), point(24.0, 0.0)],
+            enabled: true,
+        };
+        assert_eq!(evaluate_at(&set_point, 22.0), (0.5, 0.0));
+        assert_eq!(evaluate_at(&set_point, 10.0), (1.0, 0.0));
+        assert_eq!(evaluate_at(&set_point, 30.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn disabled_set_point_adds_no_weight() {
+        let rule: TimedRule = serde_json::from_str(
+            r#"{"start_time":"00:00:00","days_enabled":255,"set_points":[
+                {"type":"gradient","probe":"primary","weight":1.0,"enabled":false,"stop_points":[
+                    {"temp":20.0,"heat_value":1.0,"cool_value":0.0},
+                    {"temp":24.0,"heat_value":0.0,"cool_value":1.0}
+                ]}
+            ]}"#,
+        )
+        .unwrap();
+        let mixer = TestMixer {
+            mode: HvacRequest::Heat,
+            temp: Some(18.0),
+        };
+        assert_eq!(block_on(rule.weights(&mixer)), (0.0, 0.0));
+    }
+}
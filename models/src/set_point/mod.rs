@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+
 use serde::{Deserialize, Serialize};
 
 use crate::mixer::Mixer;
@@ -9,6 +11,24 @@ pub mod gradient;
 
 const EMPTY_REQUEST: (f32, f32) = (0.0, 0.0);
 
+fn default_enabled() -> bool {
+    true
+}
+
+thread_local! {
+    static ADJUSTMENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn report_adjustment(adjustment: String) {
+    ADJUSTMENTS.with(|adjustments| adjustments.borrow_mut().push(adjustment));
+}
+
+/// Whatever deserializing set points on this thread had to fix up since the
+/// last call, for the caller to report like any other script issue
+pub fn take_adjustments() -> Vec<String> {
+    ADJUSTMENTS.with(|adjustments| adjustments.take())
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 #[serde(tag = "type", from = "TryWithDefaultDeserializeSetPoint")]
 #[serde(rename_all = "snake_case")]
@@ -18,6 +38,14 @@ pub enum SetPoint {
 }
 
 impl SetPoint {
+    /// Disabled set points keep their configuration but are skipped entirely
+    pub fn enabled(&self) -> bool {
+        match self {
+            SetPoint::Basic(sp) => sp.enabled,
+            SetPoint::Gradient(sp) => sp.enabled,
+        }
+    }
+
     pub async fn evaluate(&self, state: &impl Mixer) -> (f32, f32) {
         match self {
             SetPoint::Basic(sp) => sp.evaluate(state).await,
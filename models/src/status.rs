@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// Everything the status panel shows, served by `/api/thermostat/status`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SystemStatus {
+    pub redis_ok: bool,
+    /// Whether the thermostat unit has answered over MQTT recently
+    pub mqtt_online: bool,
+    /// Seconds since the primary probe last reported, if it ever has
+    pub probe_age_secs: Option<i64>,
+    pub script_loaded: bool,
+    pub script_issues: usize,
+    pub override_pulse_active: bool,
+    pub oneshot_setpoint_active: bool,
+}
+
+impl SystemStatus {
+    /// The primary probe counts as stale after this long
+    pub const PROBE_STALE_SECS: i64 = 5 * 60;
+
+    pub fn probe_fresh(&self) -> bool {
+        matches!(self.probe_age_secs, Some(age) if age <= Self::PROBE_STALE_SECS)
+    }
+}
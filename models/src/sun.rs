@@ -0,0 +1,91 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use sunrise::{Coordinates, SolarDay, SolarEvent};
+
+/// Where the house is, stored as `latitude,longitude` in degrees
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl FromStr for Location {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (latitude, longitude) = s.split_once(',').ok_or(())?;
+        let latitude = f64::from_str(latitude.trim()).map_err(|_| ())?;
+        let longitude = f64::from_str(longitude.trim()).map_err(|_| ())?;
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(());
+        }
+        Ok(Location {
+            latitude,
+            longitude,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SunTimes {
+    pub sunrise: DateTime<Utc>,
+    pub sunset: DateTime<Utc>,
+}
+
+/// Sunrise and sunset on `date` at `location`, or `None` during polar day or
+/// night when the sun doesn't cross the horizon
+pub fn sun_times(location: Location, date: NaiveDate) -> Option<SunTimes> {
+    let coordinates = Coordinates::new(location.latitude, location.longitude)?;
+    let day = SolarDay::new(coordinates, date);
+    let sunrise = day.event_time(SolarEvent::Sunrise).timestamp();
+    let sunset = day.event_time(SolarEvent::Sunset).timestamp();
+
+    // With no crossing the hour angle is NaN, which comes back as timestamps
+    // nowhere near the requested day
+    let noon = date.and_hms_opt(12, 0, 0)?.and_utc().timestamp()
+        - (location.longitude / 15.0 * 3600.0) as i64;
+    let near_noon = |time: i64| (time - noon).abs() < 24 * 3600;
+    if !near_noon(sunrise) || !near_noon(sunset) || sunrise >= sunset {
+        return None;
+    }
+
+    Some(SunTimes {
+        sunrise: Utc.timestamp_opt(sunrise, 0).single()?,
+        sunset: Utc.timestamp_opt(sunset, 0).single()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: DateTime<Utc>, expected: &str) {
+        let expected: DateTime<Utc> = expected.parse().unwrap();
+        let off = (actual - expected).num_seconds().abs();
+        assert!(off <= 120, "{actual} is {off}s away from {expected}");
+    }
+
+    #[test]
+    fn toronto_new_year() {
+        // NOAA gives 07:51 and 16:51 EST for Toronto on 2016-01-01
+        let location: Location = "43.6532, -79.3832".parse().unwrap();
+        let times = sun_times(location, NaiveDate::from_ymd_opt(2016, 1, 1).unwrap()).unwrap();
+        assert_near(times.sunrise, "2016-01-01T12:51:00Z");
+        assert_near(times.sunset, "2016-01-01T21:51:00Z");
+    }
+
+    #[test]
+    fn polar_night() {
+        let location: Location = "78.22,15.65".parse().unwrap();
+        assert_eq!(sun_times(location, NaiveDate::from_ymd_opt(2023, 12, 21).unwrap()), None);
+        assert_eq!(sun_times(location, NaiveDate::from_ymd_opt(2023, 6, 21).unwrap()), None);
+    }
+
+    #[test]
+    fn location_bounds() {
+        assert!("91,0".parse::<Location>().is_err());
+        assert!("0,181".parse::<Location>().is_err());
+        assert!("12.5".parse::<Location>().is_err());
+    }
+}
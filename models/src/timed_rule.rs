@@ -1,3 +1,5 @@
+use std::fmt;
+
 use chrono::{Datelike, NaiveTime, Weekday};
 use serde::{Deserialize, Serialize};
 
@@ -31,34 +33,114 @@ impl TimedRuleSet {
         ruleset
     }*/
 
-    pub async fn evaluate(&self, state: &impl Mixer) -> Option<HvacRequest> {
-        let rule = self.find_applicable_rule()?;
+    /// Catch rulesets that would load fine but never do anything useful
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
 
-        let (mut on_weight, mut off_weight) = (0.0, 0.0);
-        let mut total_points = 0;
-        for set_point in &rule.set_points {
-            let (heat_weight, cool_weight) = set_point.evaluate(state).await;
-            total_points += 1;
-            match state.mode() {
-                HvacRequest::Off => off_weight += heat_weight + cool_weight, // lol
-                HvacRequest::Heat => {
-                    on_weight += heat_weight;
-                    off_weight += cool_weight;
-                }
-                HvacRequest::Cool => {
-                    on_weight += cool_weight;
-                    off_weight += heat_weight;
+        if !self.threshold.is_finite() || !(0.0..=1.0).contains(&self.threshold) {
+            problems.push(format!("threshold must be between 0 and 1, got {}", self.threshold));
+        }
+        if self.rules.is_empty() {
+            problems.push("ruleset has no rules".to_string());
+        }
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.days_enabled.is_empty() {
+                problems.push(format!("rule {i} ({}) has no enabled days", rule.start_time));
+            }
+            if rule.set_points.is_empty() {
+                problems.push(format!("rule {i} ({}) has no set points", rule.start_time));
+            }
+
+            for (j, other) in self.rules.iter().enumerate().skip(i + 1) {
+                if rule.start_time == other.start_time
+                    && rule.days_enabled.overlaps(other.days_enabled)
+                {
+                    problems.push(format!(
+                        "rules {i} and {j} both start at {} on the same day",
+                        rule.start_time
+                    ));
                 }
             }
         }
-        if total_points > 0 {
-            on_weight /= total_points as f32;
-            off_weight /= total_points as f32;
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
         }
+    }
+
+    /// Layer `overlay` on top of this ruleset. Every day that any overlay rule
+    /// is enabled on is taken over completely by the overlay's rules, and the
+    /// other days keep their rules from `self`. The threshold comes from
+    /// `self`.
+    pub fn overlay(&self, overlay: &TimedRuleSet) -> TimedRuleSet {
+        let taken_over = overlay
+            .rules
+            .iter()
+            .fold(DaySet::new(), |days, rule| days.union(rule.days_enabled));
 
-        if on_weight > off_weight && on_weight > self.threshold {
-            Some(state.mode())
-        } else if off_weight > on_weight && off_weight > self.threshold {
+        let base = self.rules.iter().filter_map(|rule| {
+            let days_enabled = rule.days_enabled.difference(taken_over);
+            (!days_enabled.is_empty()).then(|| TimedRule {
+                days_enabled,
+                ..rule.clone()
+            })
+        });
+
+        TimedRuleSet::new(base.chain(overlay.rules.iter().cloned()).collect(), self.threshold)
+    }
+
+    /// Make the rule at `index` run on `days` as well. Other rules starting at
+    /// the same time give those days up, and are dropped if that leaves them
+    /// with none. Returns false if there is no rule at `index`.
+    pub fn apply_to_days(&mut self, index: usize, days: DaySet) -> bool {
+        let Some(rule) = self.rules.get(index) else {
+            return false;
+        };
+        let start_time = rule.start_time;
+
+        let mut i = 0;
+        self.rules.retain_mut(|rule| {
+            let this = i;
+            i += 1;
+            if this == index {
+                rule.days_enabled = rule.days_enabled.union(days);
+            } else if rule.start_time == start_time {
+                rule.days_enabled = rule.days_enabled.difference(days);
+                return !rule.days_enabled.is_empty();
+            }
+            true
+        });
+        true
+    }
+
+    pub async fn evaluate(&self, state: &impl Mixer) -> Option<HvacRequest> {
+        self.evaluate_with_threshold(state, self.threshold).await
+    }
+
+    /// Evaluate against a threshold other than the one the ruleset was saved with
+    pub async fn evaluate_with_threshold(
+        &self,
+        state: &impl Mixer,
+        threshold: f32,
+    ) -> Option<HvacRequest> {
+        let rule = self.find_applicable_rule()?;
+        let (on_weight, off_weight) = rule.weights(state).await;
+        Self::decide(state.mode(), on_weight, off_weight, threshold)
+    }
+
+    /// Whichever side is heavier wins, as long as it clears the threshold
+    pub fn decide(
+        mode: HvacRequest,
+        on_weight: f32,
+        off_weight: f32,
+        threshold: f32,
+    ) -> Option<HvacRequest> {
+        if on_weight > off_weight && on_weight > threshold {
+            Some(mode)
+        } else if off_weight > on_weight && off_weight > threshold {
             Some(HvacRequest::Off)
         } else {
             None
@@ -67,9 +149,15 @@ impl TimedRuleSet {
 
     pub fn find_applicable_rule(&self) -> Option<&TimedRule> {
         let now = chrono::Local::now();
-        let time_of_day = now.time();
-        let today = now.weekday();
+        self.find_applicable_rule_at(now.weekday(), now.time())
+    }
 
+    /// The rule that would be active at `time_of_day` on `today`
+    pub fn find_applicable_rule_at(
+        &self,
+        today: Weekday,
+        time_of_day: NaiveTime,
+    ) -> Option<&TimedRule> {
         if let Some(index) = self.first_rule_index_for(today) {
             // If the first rule today doesn't begin until after now, use
             // the last rule from a previous day.
@@ -111,18 +199,17 @@ impl TimedRuleSet {
             .find(|rule| rule.days_enabled.enabled(day))
     }
 
+    /// Walks back as far as the same weekday a week ago, which is where a
+    /// ruleset with rules on a single day ends up before that day's first rule
     fn last_rule_before(&self, day: Weekday) -> Option<&TimedRule> {
         let mut curr = day;
-        loop {
+        for _ in 0..7 {
             curr = curr.pred();
-            if curr == day {
-                return None;
-            }
-
             if let Some(rule) = self.last_rule_for(curr) {
                 return Some(rule);
             }
         }
+        None
     }
 }
 
@@ -133,7 +220,36 @@ pub struct TimedRule {
     pub days_enabled: DaySet,
 }
 
-#[derive(Default, Copy, Clone, Serialize, Deserialize, PartialEq, Eq)]
+impl TimedRule {
+    /// The averaged (on, off) weights of the enabled set points for the
+    /// current mode
+    pub async fn weights(&self, state: &impl Mixer) -> (f32, f32) {
+        let (mut on_weight, mut off_weight) = (0.0, 0.0);
+        let mut total_points = 0;
+        for set_point in self.set_points.iter().filter(|sp| sp.enabled()) {
+            let (heat_weight, cool_weight) = set_point.evaluate(state).await;
+            total_points += 1;
+            match state.mode() {
+                HvacRequest::Off => off_weight += heat_weight + cool_weight, // lol
+                HvacRequest::Heat => {
+                    on_weight += heat_weight;
+                    off_weight += cool_weight;
+                }
+                HvacRequest::Cool => {
+                    on_weight += cool_weight;
+                    off_weight += heat_weight;
+                }
+            }
+        }
+        if total_points > 0 {
+            on_weight /= total_points as f32;
+            off_weight /= total_points as f32;
+        }
+        (on_weight, off_weight)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct DaySet(u8);
 
 impl DaySet {
@@ -145,10 +261,43 @@ impl DaySet {
         DaySet(u8::MAX)
     }
 
+    pub fn from_days(days: impl IntoIterator<Item = Weekday>) -> Self {
+        let mut set = DaySet::new();
+        for day in days {
+            set.enable(day);
+        }
+        set
+    }
+
+    /// The enabled days, Sunday first
+    pub fn iter(&self) -> impl Iterator<Item = Weekday> {
+        let set = *self;
+        std::iter::successors(Some(Weekday::Sun), |day| Some(day.succ()))
+            .take(7)
+            .filter(move |&day| set.enabled(day))
+    }
+
     pub fn enabled(&self, day: Weekday) -> bool {
         self.0 & Self::flag_for(day) != 0
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.0 & Self::all_days() == 0
+    }
+
+    pub fn overlaps(&self, other: DaySet) -> bool {
+        self.0 & other.0 & Self::all_days() != 0
+    }
+
+    pub fn union(&self, other: DaySet) -> DaySet {
+        DaySet(self.0 | other.0)
+    }
+
+    /// The days in `self` that aren't in `other`
+    pub fn difference(&self, other: DaySet) -> DaySet {
+        DaySet(self.0 & !other.0)
+    }
+
     pub fn enable(&mut self, day: Weekday) {
         self.0 |= Self::flag_for(day)
     }
@@ -160,4 +309,276 @@ impl DaySet {
     fn flag_for(day: Weekday) -> u8 {
         1u8 << day.num_days_from_sunday()
     }
+
+    /// `all()` sets the eighth bit too, which isn't a day
+    fn all_days() -> u8 {
+        0b0111_1111
+    }
+}
+
+/// Renders Monday first like `MTWTF--`
+impl fmt::Display for DaySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const LETTERS: [char; 7] = ['M', 'T', 'W', 'T', 'F', 'S', 'S'];
+        let mut day = Weekday::Mon;
+        for letter in LETTERS {
+            let c = if self.enabled(day) { letter } else { '-' };
+            write!(f, "{c}")?;
+            day = day.succ();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Weekday::*;
+
+    use super::*;
+    use crate::set_point::BasicSetPoint;
+
+    fn time(time: &str) -> NaiveTime {
+        time.parse().unwrap()
+    }
+
+    fn rule(start_time: &str, days: impl IntoIterator<Item = Weekday>) -> TimedRule {
+        TimedRule {
+            set_points: vec![SetPoint::Basic(BasicSetPoint {
+                probe: "primary".into(),
+                weight: 1.0,
+                min_temp: 20.0,
+                max_temp: 22.0,
+                enabled: true,
+            })],
+            start_time: time(start_time),
+            days_enabled: DaySet::from_days(days),
+        }
+    }
+
+    /// Mornings and evenings on weekdays, one rule for the weekend
+    fn week() -> TimedRuleSet {
+        let weekdays = [Mon, Tue, Wed, Thu, Fri];
+        TimedRuleSet::new(
+            vec![
+                rule("07:00:00", weekdays),
+                rule("18:00:00", weekdays),
+                rule("09:00:00", [Sat, Sun]),
+            ],
+            0.05,
+        )
+    }
+
+    /// When the rule active at `at` on `day` started
+    fn start_at(ruleset: &TimedRuleSet, day: Weekday, at: &str) -> Option<NaiveTime> {
+        Some(ruleset.find_applicable_rule_at(day, time(at))?.start_time)
+    }
+
+    #[test]
+    fn finds_the_rule_for_a_time() {
+        let ruleset = week();
+        assert_eq!(start_at(&ruleset, Tue, "07:00:00"), Some(time("07:00:00")));
+        assert_eq!(start_at(&ruleset, Tue, "12:30:00"), Some(time("07:00:00")));
+        assert_eq!(start_at(&ruleset, Tue, "23:59:59"), Some(time("18:00:00")));
+        assert_eq!(start_at(&ruleset, Sat, "10:00:00"), Some(time("09:00:00")));
+    }
+
+    #[test]
+    fn early_morning_keeps_last_nights_rule() {
+        let ruleset = week();
+        assert_eq!(start_at(&ruleset, Tue, "00:00:00"), Some(time("18:00:00")));
+        assert_eq!(start_at(&ruleset, Tue, "06:59:59"), Some(time("18:00:00")));
+        // Monday morning still runs Sunday's rule
+        assert_eq!(start_at(&ruleset, Mon, "03:00:00"), Some(time("09:00:00")));
+    }
+
+    #[test]
+    fn falls_back_across_days_without_rules() {
+        let ruleset = TimedRuleSet::new(vec![rule("20:00:00", [Wed])], 0.05);
+        assert_eq!(start_at(&ruleset, Sat, "12:00:00"), Some(time("20:00:00")));
+        // Even the day of the only rule, before it starts, wraps around the week
+        assert_eq!(start_at(&ruleset, Wed, "08:00:00"), Some(time("20:00:00")));
+    }
+
+    #[test]
+    fn valid_ruleset_passes() {
+        assert_eq!(week().validate(), Ok(()));
+    }
+
+    fn problems(ruleset: TimedRuleSet) -> Vec<String> {
+        ruleset.validate().unwrap_err()
+    }
+
+    #[test]
+    fn rejects_a_bad_threshold() {
+        for threshold in [-0.1, 1.5, f32::NAN] {
+            let ruleset = TimedRuleSet { threshold, ..week() };
+            let problems = problems(ruleset);
+            assert_eq!(problems.len(), 1);
+            assert!(problems[0].contains("threshold"), "{problems:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_an_empty_ruleset() {
+        let problems = problems(TimedRuleSet::new(vec![], 0.05));
+        assert_eq!(problems, ["ruleset has no rules"]);
+    }
+
+    #[test]
+    fn rejects_a_rule_without_days() {
+        let problems = problems(TimedRuleSet::new(vec![rule("07:00:00", [])], 0.05));
+        assert_eq!(problems, ["rule 0 (07:00:00) has no enabled days"]);
+    }
+
+    #[test]
+    fn rejects_a_rule_without_set_points() {
+        let mut empty = rule("07:00:00", [Mon]);
+        empty.set_points.clear();
+        let problems = problems(TimedRuleSet::new(vec![empty], 0.05));
+        assert_eq!(problems, ["rule 0 (07:00:00) has no set points"]);
+    }
+
+    #[test]
+    fn rejects_overlapping_rules() {
+        let ruleset = TimedRuleSet::new(
+            vec![rule("07:00:00", [Mon, Tue]), rule("07:00:00", [Tue, Wed])],
+            0.05,
+        );
+        assert_eq!(problems(ruleset), ["rules 0 and 1 both start at 07:00:00 on the same day"]);
+
+        // The same start time on different days is fine
+        let ruleset = TimedRuleSet::new(
+            vec![rule("07:00:00", [Mon]), rule("07:00:00", [Tue])],
+            0.05,
+        );
+        assert_eq!(ruleset.validate(), Ok(()));
+    }
+
+    #[test]
+    fn all_days() {
+        let days = DaySet::all();
+        assert_eq!(days.iter().collect::<Vec<_>>(), [Sun, Mon, Tue, Wed, Thu, Fri, Sat]);
+        assert_eq!(days.to_string(), "MTWTFSS");
+        assert!(!days.is_empty());
+    }
+
+    #[test]
+    fn no_days() {
+        let days = DaySet::new();
+        assert_eq!(days.iter().count(), 0);
+        assert_eq!(days.to_string(), "-------");
+        assert!(days.is_empty());
+    }
+
+    #[test]
+    fn scattered_days() {
+        let days = DaySet::from_days([Fri, Mon, Sun]);
+        assert_eq!(days.iter().collect::<Vec<_>>(), [Sun, Mon, Fri]);
+        assert_eq!(days.to_string(), "M---F-S");
+    }
+
+    #[test]
+    fn from_days_round_trips_through_iter() {
+        for days in [DaySet::new(), DaySet::from_days([Tue, Thu, Sat])] {
+            assert_eq!(DaySet::from_days(days.iter()), days);
+        }
+        // `all()` carries a bit that isn't a day, so compare what it enables
+        let all = DaySet::from_days(DaySet::all().iter());
+        assert_eq!(all.iter().count(), 7);
+    }
+
+    #[test]
+    fn empty_ruleset_has_no_rule() {
+        let ruleset = TimedRuleSet::default();
+        assert_eq!(start_at(&ruleset, Mon, "12:00:00"), None);
+    }
+
+    fn schedule(ruleset: &TimedRuleSet) -> Vec<(NaiveTime, DaySet)> {
+        ruleset
+            .rules
+            .iter()
+            .map(|rule| (rule.start_time, rule.days_enabled))
+            .collect()
+    }
+
+    #[test]
+    fn overlay_takes_over_its_days() {
+        let holiday = TimedRuleSet::new(vec![rule("10:00:00", [Fri, Sat])], 0.5);
+        let effective = week().overlay(&holiday);
+
+        let weekdays = DaySet::from_days([Mon, Tue, Wed, Thu]);
+        assert_eq!(
+            schedule(&effective),
+            [
+                (time("07:00:00"), weekdays),
+                (time("09:00:00"), DaySet::from_days([Sun])),
+                (time("10:00:00"), DaySet::from_days([Fri, Sat])),
+                (time("18:00:00"), weekdays),
+            ]
+        );
+        assert_eq!(effective.threshold, 0.05);
+
+        // Friday evening comes from the overlay now, Thursday's is untouched
+        assert_eq!(start_at(&effective, Fri, "19:00:00"), Some(time("10:00:00")));
+        assert_eq!(start_at(&effective, Thu, "19:00:00"), Some(time("18:00:00")));
+    }
+
+    #[test]
+    fn overlay_drops_rules_left_without_days() {
+        let weekend = TimedRuleSet::new(vec![rule("08:00:00", [Sat, Sun])], 0.05);
+        let effective = week().overlay(&weekend);
+        assert_eq!(
+            schedule(&effective),
+            [
+                (time("07:00:00"), DaySet::from_days([Mon, Tue, Wed, Thu, Fri])),
+                (time("08:00:00"), DaySet::from_days([Sat, Sun])),
+                (time("18:00:00"), DaySet::from_days([Mon, Tue, Wed, Thu, Fri])),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_overlay_changes_nothing() {
+        let effective = week().overlay(&TimedRuleSet::default());
+        assert_eq!(schedule(&effective), schedule(&week()));
+    }
+
+    #[test]
+    fn applying_across_weekdays() {
+        let mut ruleset = TimedRuleSet::new(
+            vec![
+                rule("07:00:00", [Mon]),
+                rule("07:00:00", [Fri, Sat]),
+                rule("18:00:00", [Mon]),
+            ],
+            0.05,
+        );
+        assert!(ruleset.apply_to_days(0, DaySet::from_days([Mon, Tue, Wed, Thu, Fri])));
+
+        // The Friday rule gives Friday up and keeps Saturday
+        assert_eq!(
+            schedule(&ruleset),
+            [
+                (time("07:00:00"), DaySet::from_days([Mon, Tue, Wed, Thu, Fri])),
+                (time("07:00:00"), DaySet::from_days([Sat])),
+                (time("18:00:00"), DaySet::from_days([Mon])),
+            ]
+        );
+        assert!(ruleset.validate().is_ok());
+    }
+
+    #[test]
+    fn applying_drops_rules_left_without_days() {
+        let rules = vec![rule("07:00:00", [Mon]), rule("07:00:00", [Tue])];
+        let mut ruleset = TimedRuleSet::new(rules, 0.05);
+        assert!(ruleset.apply_to_days(0, DaySet::from_days([Tue])));
+        assert_eq!(schedule(&ruleset), [(time("07:00:00"), DaySet::from_days([Mon, Tue]))]);
+    }
+
+    #[test]
+    fn applying_a_missing_rule_does_nothing() {
+        let mut ruleset = week();
+        assert!(!ruleset.apply_to_days(3, DaySet::all()));
+        assert_eq!(schedule(&ruleset), schedule(&week()));
+    }
 }
@@ -0,0 +1,56 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Everything is stored and computed in Celsius, this is only for display
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TempUnits {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl FromStr for TempUnits {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "c" | "celsius" => Ok(TempUnits::Celsius),
+            "f" | "fahrenheit" => Ok(TempUnits::Fahrenheit),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Convert a temperature in Celsius to `units`
+pub fn convert_temp(c: f64, units: TempUnits) -> f64 {
+    match units {
+        TempUnits::Celsius => c,
+        TempUnits::Fahrenheit => c * 9.0 / 5.0 + 32.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn celsius_is_left_alone() {
+        assert_eq!(convert_temp(21.5, TempUnits::Celsius), 21.5);
+    }
+
+    #[test]
+    fn converts_to_fahrenheit() {
+        assert_eq!(convert_temp(0.0, TempUnits::Fahrenheit), 32.0);
+        assert_eq!(convert_temp(100.0, TempUnits::Fahrenheit), 212.0);
+        assert_eq!(convert_temp(-40.0, TempUnits::Fahrenheit), -40.0);
+    }
+
+    #[test]
+    fn parses_unit_names() {
+        assert_eq!("f".parse(), Ok(TempUnits::Fahrenheit));
+        assert_eq!("Celsius".parse(), Ok(TempUnits::Celsius));
+        assert_eq!("k".parse::<TempUnits>(), Err(()));
+    }
+}
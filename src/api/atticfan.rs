@@ -1,9 +1,24 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use http::StatusCode;
+use models::{
+    atticfan::{AtticFanState, Fan},
+    keys::MIN_TOGGLE_INTERVAL_KEY,
+};
+use redis::AsyncCommands;
 use tokio::sync::RwLock;
-use warp::{filters::BoxedFilter, Filter, Reply};
+use warp::{
+    filters::{path, BoxedFilter},
+    reply, Filter, Reply,
+};
 
-use crate::StatePackage;
+use crate::{error::WebErrorExt, mqtt::MqttClient, StatePackage};
+
+/// Seconds that must pass between state changes of the same fan
+const DEFAULT_MIN_TOGGLE_INTERVAL: u64 = 30;
 
 #[derive(Clone, Default)]
 pub struct FanState {
@@ -12,18 +27,80 @@ pub struct FanState {
 
 impl FanState {
     pub async fn big_succ(&self) -> bool {
-        self.inner.read().await.fan1
+        self.inner.read().await.fan(Fan::BigSucc)
     }
 
     pub async fn roof_fan(&self) -> bool {
-        self.inner.read().await.fan0
+        self.inner.read().await.fan(Fan::RoofFan)
+    }
+
+    pub async fn get(&self) -> AtticFanState {
+        let state = self.inner.read().await;
+        AtticFanState {
+            big_succ: state.fan(Fan::BigSucc),
+            roof_fan: state.fan(Fan::RoofFan),
+        }
     }
 }
 
 #[derive(Default)]
 struct InnerFanState {
-    fan0: bool,
-    fan1: bool,
+    on: [bool; 2],
+    /// What each fan was last told to be, which `on` only catches up to once
+    /// the fan echoes it back
+    commanded: [Option<bool>; 2],
+    last_toggle: [Option<Instant>; 2],
+}
+
+impl InnerFanState {
+    fn fan(&self, fan: Fan) -> bool {
+        self.on[fan.to_index()]
+    }
+
+    /// The state `fan` is headed for, its reported one until it's been set
+    fn commanded(&self, fan: Fan) -> bool {
+        self.commanded[fan.to_index()].unwrap_or_else(|| self.fan(fan))
+    }
+
+    /// Seconds until `fan` may be switched to `val`, if it was toggled too
+    /// recently. Repeating the last command is always fine.
+    fn toggle_wait(&self, fan: Fan, val: bool, min_toggle_interval: Duration) -> Option<u64> {
+        if self.commanded(fan) == val {
+            return None;
+        }
+
+        let elapsed = self.last_toggle[fan.to_index()]?.elapsed();
+        (elapsed < min_toggle_interval).then(|| (min_toggle_interval - elapsed).as_secs() + 1)
+    }
+
+    fn record_toggle(&mut self, fan: Fan, val: bool) {
+        if self.commanded(fan) != val {
+            self.last_toggle[fan.to_index()] = Some(Instant::now());
+        }
+        self.commanded[fan.to_index()] = Some(val);
+    }
+}
+
+/// Payloads look like `0t` or `1f`, the fan index followed by its state
+fn parse_fan_payload(payload: &[u8]) -> Option<(Fan, bool)> {
+    let &[fan, val] = payload else {
+        return None;
+    };
+
+    let fan = Fan::from_index(char::from(fan).to_digit(10)? as usize)?;
+    Some((fan, val == b't'))
+}
+
+async fn publish_fan(mqtt: &MqttClient, fan: Fan, val: bool) {
+    let payload = format!("{}{}", fan.to_index(), if val { 't' } else { 'f' });
+    mqtt.publish("home/atticfan/setstate", payload.as_bytes()).await;
+}
+
+fn too_recent(wait: u64) -> reply::WithStatus<String> {
+    reply::with_status(
+        format!("Toggled too recently, try again in {wait}s"),
+        StatusCode::TOO_MANY_REQUESTS,
+    )
 }
 
 pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
@@ -34,65 +111,269 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
         state
             .mqtt
             .handle("home/atticfan/state", move |_topic, payload| {
-                if payload.len() != 2 {
+                let Some((fan, val)) = parse_fan_payload(payload) else {
                     return;
-                }
-
-                let fan = payload[0].wrapping_sub(b'0');
-                let val = payload[1] == b't';
+                };
 
                 let state = fan_state.clone();
                 tokio::task::spawn(async move {
-                    let mut state = state.inner.write().await;
-                    match fan {
-                        0 => state.fan0 = val,
-                        1 => state.fan1 = val,
-                        _ => (),
-                    }
+                    state.inner.write().await.on[fan.to_index()] = val;
                 });
             })
             .await;
 
         // Make sure we're fresh
-        state.mqtt.publish("home/atticfan/getstate", b"0").await;
-        state.mqtt.publish("home/atticfan/getstate", b"1").await;
+        for fan in Fan::ALL {
+            let index = fan.to_index().to_string();
+            state.mqtt.publish("home/atticfan/getstate", index.as_bytes()).await;
+        }
     }
 
     // Handle requests for the current known fan state
     let getstate = {
         let fan_state = state.fan.clone();
-        warp::path!("getstate" / i32).and_then(move |fan: i32| {
+        warp::path!("getstate" / Fan).and_then(move |fan: Fan| {
             let fan_state = fan_state.clone();
             async move {
-                let state = fan_state.inner.read().await;
-                let val = match fan {
-                    0 => state.fan0,
-                    1 => state.fan1,
-                    _ => return Err(warp::reject::not_found()),
-                };
-
-                Ok(val.to_string())
+                let val = fan_state.inner.read().await.fan(fan);
+                Ok::<_, warp::Rejection>(val.to_string())
             }
         })
     };
 
+    let min_toggle_interval = {
+        let mut redis = state.redis.get();
+        let secs: Option<u64> = redis.get(MIN_TOGGLE_INTERVAL_KEY).await.ok().flatten();
+        Duration::from_secs(secs.unwrap_or(DEFAULT_MIN_TOGGLE_INTERVAL))
+    };
+
     let setstate = {
         let mqtt = state.mqtt.clone();
-        warp::path!("setstate" / i32 / bool).and_then(move |fan, val| {
+        let fan_state = state.fan.clone();
+        warp::path!("setstate" / Fan / bool).and_then(move |fan: Fan, val| {
             let mqtt = mqtt.clone();
+            let fan_state = fan_state.clone();
             async move {
-                match fan {
-                    0 | 1 => {}
-                    _ => return Err(warp::reject::not_found()),
+                // Protect the relays from being flipped back and forth too quickly
+                {
+                    let mut state = fan_state.inner.write().await;
+                    if let Some(wait) = state.toggle_wait(fan, val, min_toggle_interval) {
+                        return Ok(too_recent(wait));
+                    }
+                    state.record_toggle(fan, val);
                 }
 
-                let payload = [b'0' + fan as u8, if val { b't' } else { b'f' }];
-                mqtt.publish("home/atticfan/setstate", &payload).await;
+                publish_fan(&mqtt, fan, val).await;
 
-                Ok("ok")
+                Ok::<_, warp::Rejection>(reply::with_status("ok".to_string(), StatusCode::OK))
             }
         })
     };
 
-    getstate.or(setstate).boxed()
+    getstate
+        .or(setstate)
+        .or(combined_state(
+            state.fan.clone(),
+            state.mqtt.clone(),
+            min_toggle_interval,
+        ))
+        .boxed()
+}
+
+/// `GET`/`PUT /state`, both fans at once
+fn combined_state(
+    fan_state: FanState,
+    mqtt: MqttClient,
+    min_toggle_interval: Duration,
+) -> BoxedFilter<(impl Reply,)> {
+    let get_combined = {
+        let fan_state = fan_state.clone();
+        warp::path("state")
+            .and(path::end())
+            .and(warp::get())
+            .and_then(move || {
+                let fan_state = fan_state.clone();
+                async move { serde_json::to_string(&fan_state.get().await).reject_err() }
+            })
+    };
+
+    let put_combined = {
+        let mqtt = mqtt.clone();
+        let fan_state = fan_state.clone();
+        warp::path("state")
+            .and(path::end())
+            .and(warp::put())
+            .and(warp::body::json::<AtticFanState>())
+            .and_then(move |new_state: AtticFanState| {
+                let mqtt = mqtt.clone();
+                let fan_state = fan_state.clone();
+                async move {
+                    let changes = [
+                        (Fan::RoofFan, new_state.roof_fan),
+                        (Fan::BigSucc, new_state.big_succ),
+                    ];
+
+                    // Either both fans change or neither does
+                    {
+                        let mut state = fan_state.inner.write().await;
+                        let wait = changes
+                            .iter()
+                            .filter_map(|&(fan, val)| {
+                                state.toggle_wait(fan, val, min_toggle_interval)
+                            })
+                            .max();
+                        if let Some(wait) = wait {
+                            return Ok(too_recent(wait));
+                        }
+                        for (fan, val) in changes {
+                            state.record_toggle(fan, val);
+                        }
+                    }
+
+                    for (fan, val) in changes {
+                        publish_fan(&mqtt, fan, val).await;
+                    }
+
+                    Ok::<_, warp::Rejection>(reply::with_status("ok".to_string(), StatusCode::OK))
+                }
+            })
+    };
+
+    get_combined.or(put_combined).boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(30);
+
+    type Published = Arc<std::sync::Mutex<Vec<String>>>;
+
+    /// The combined routes over a loopback client, and every payload they
+    /// publish to the fans
+    async fn combined() -> (FanState, BoxedFilter<(impl Reply,)>, Published) {
+        let mqtt = MqttClient::loopback(true);
+        let published = Published::default();
+        {
+            let published = published.clone();
+            mqtt.handle("home/atticfan/setstate", move |_topic, payload| {
+                let payload = String::from_utf8_lossy(payload).into_owned();
+                published.lock().unwrap().push(payload);
+            })
+            .await;
+        }
+
+        let fan_state = FanState::default();
+        let route = combined_state(fan_state.clone(), mqtt, INTERVAL);
+        (fan_state, route, published)
+    }
+
+    #[tokio::test]
+    async fn combined_get_reports_both_fans() {
+        let (fan_state, route, _) = combined().await;
+        fan_state.inner.write().await.on[Fan::BigSucc.to_index()] = true;
+
+        let reply = warp::test::request().path("/state").reply(&route).await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        let body: AtticFanState = serde_json::from_slice(reply.body()).unwrap();
+        assert!(body.big_succ);
+        assert!(!body.roof_fan);
+    }
+
+    #[tokio::test]
+    async fn combined_put_sets_both_fans() {
+        let (_, route, published) = combined().await;
+
+        let reply = warp::test::request()
+            .method("PUT")
+            .path("/state")
+            .json(&AtticFanState {
+                big_succ: true,
+                roof_fan: true,
+            })
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), StatusCode::OK);
+
+        // The loopback delivers publishes from its own task
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut published = published.lock().unwrap().clone();
+        published.sort();
+        assert_eq!(published, ["0t", "1t"]);
+    }
+
+    #[tokio::test]
+    async fn combined_put_is_all_or_nothing() {
+        let (fan_state, route, published) = combined().await;
+        {
+            let mut state = fan_state.inner.write().await;
+            state.last_toggle[Fan::BigSucc.to_index()] = Some(Instant::now());
+        }
+
+        let reply = warp::test::request()
+            .method("PUT")
+            .path("/state")
+            .json(&AtticFanState {
+                big_succ: true,
+                roof_fan: true,
+            })
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(published.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn on_then_off_before_the_echo_is_rejected() {
+        // Nothing handles `home/atticfan/state` here, so `on` never updates
+        let (_, route, published) = combined().await;
+        let put = |roof_fan| {
+            warp::test::request()
+                .method("PUT")
+                .path("/state")
+                .json(&AtticFanState {
+                    big_succ: false,
+                    roof_fan,
+                })
+                .reply(&route)
+        };
+
+        assert_eq!(put(true).await.status(), StatusCode::OK);
+        assert_eq!(put(false).await.status(), StatusCode::TOO_MANY_REQUESTS);
+        // Repeating the command is still fine
+        assert_eq!(put(true).await.status(), StatusCode::OK);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut published = published.lock().unwrap().clone();
+        published.sort();
+        assert_eq!(published, ["0t", "0t", "1f", "1f"]);
+    }
+
+    #[test]
+    fn first_toggle_is_allowed() {
+        let state = InnerFanState::default();
+        assert_eq!(state.toggle_wait(Fan::RoofFan, true, INTERVAL), None);
+    }
+
+    #[test]
+    fn too_fast_second_toggle_is_rejected() {
+        let mut state = InnerFanState::default();
+        state.record_toggle(Fan::RoofFan, true);
+        state.on[Fan::RoofFan.to_index()] = true;
+
+        assert_eq!(state.toggle_wait(Fan::RoofFan, false, INTERVAL), Some(30));
+        // Repeating the current state and switching the other fan don't count
+        assert_eq!(state.toggle_wait(Fan::RoofFan, true, INTERVAL), None);
+        assert_eq!(state.toggle_wait(Fan::BigSucc, true, INTERVAL), None);
+    }
+
+    #[test]
+    fn toggle_is_allowed_after_the_interval() {
+        let mut state = InnerFanState::default();
+        state.last_toggle[Fan::RoofFan.to_index()] = Instant::now().checked_sub(INTERVAL);
+        assert_eq!(state.toggle_wait(Fan::RoofFan, true, INTERVAL), None);
+    }
 }
@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use models::keys::{AUTH_AUDIT, CONFIG_AUDIT_MAX_AGE, CONFIG_AUDIT_MAX_LEN};
+use redis::{aio::ConnectionManager, AsyncCommands};
+use serde::{Deserialize, Serialize};
+
+use crate::RedisConn;
+
+/// Only the most recent actions are kept, unless `CONFIG_AUDIT_MAX_LEN` says
+/// otherwise
+const DEFAULT_MAX_ENTRIES: isize = 1000;
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    /// Who did it, from their verified token
+    pub actor: String,
+    pub action: String,
+    /// The account acted on, if it isn't the actor's own
+    pub target: Option<String>,
+}
+
+/// Failing to record is logged rather than failing the action itself
+pub async fn record(redis: &RedisConn, actor: &str, action: &str, target: Option<&str>) {
+    let entry = AuditEntry {
+        time: Utc::now(),
+        actor: actor.into(),
+        action: action.into(),
+        target: target.map(String::from),
+    };
+    let Ok(data) = serde_json::to_string(&entry) else {
+        return;
+    };
+
+    let mut redis = redis.get();
+    let (max_len, max_age) = limits(&mut redis).await;
+    let result: Result<(), _> = redis::pipe()
+        .lpush(AUTH_AUDIT, data)
+        .ignore()
+        .ltrim(AUTH_AUDIT, 0, max_len - 1)
+        .ignore()
+        .query_async(&mut redis)
+        .await;
+    if let Err(error) = result {
+        tracing::warn!(actor, action, ?error, "Failed to record audit entry");
+        return;
+    }
+
+    if let Some(max_age) = max_age {
+        let cutoff = entry.time - chrono::Duration::seconds(max_age);
+        if let Err(error) = drop_expired(&mut redis, cutoff).await {
+            tracing::warn!(?error, "Failed to expire audit entries");
+        }
+    }
+}
+
+async fn limits(redis: &mut ConnectionManager) -> (isize, Option<i64>) {
+    let (max_len, max_age): (Option<isize>, Option<i64>) = redis
+        .get((CONFIG_AUDIT_MAX_LEN, CONFIG_AUDIT_MAX_AGE))
+        .await
+        .unwrap_or_default();
+    (
+        max_len
+            .filter(|&len| len > 0)
+            .unwrap_or(DEFAULT_MAX_ENTRIES),
+        max_age.filter(|&age| age > 0),
+    )
+}
+
+/// Pops entries off the old end until one is newer than `cutoff`
+async fn drop_expired(redis: &mut ConnectionManager, cutoff: DateTime<Utc>) -> anyhow::Result<()> {
+    loop {
+        let oldest: Option<String> = redis.lindex(AUTH_AUDIT, -1).await?;
+        match oldest {
+            Some(oldest) if expired(&oldest, cutoff) => {
+                let _: Option<String> = redis.rpop(AUTH_AUDIT, None).await?;
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Entries too mangled to have a time are expired too, rather than keeping
+/// everything behind them around forever
+fn expired(entry: &str, cutoff: DateTime<Utc>) -> bool {
+    serde_json::from_str::<AuditEntry>(entry).map_or(true, |entry| entry.time < cutoff)
+}
+
+pub async fn recent(redis: &RedisConn) -> anyhow::Result<Vec<AuditEntry>> {
+    let mut redis = redis.get();
+    let entries: Vec<String> = redis.lrange(AUTH_AUDIT, 0, -1).await?;
+    Ok(entries
+        .iter()
+        .filter_map(|entry| serde_json::from_str(entry).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time: DateTime<Utc>) -> String {
+        serde_json::to_string(&AuditEntry {
+            time,
+            actor: "connie".into(),
+            action: "set_auth_level".into(),
+            target: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn entries_expire_past_the_cutoff() {
+        let now = Utc::now();
+        let day = chrono::Duration::days(1);
+        assert!(expired(&entry(now - day * 2), now - day));
+        assert!(!expired(&entry(now), now - day));
+        assert!(expired("garbage", now));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn log_is_trimmed_to_the_configured_length() {
+        let redis = RedisConn::scratch().await;
+        {
+            let mut redis = redis.get();
+            let () = redis::pipe()
+                .del(AUTH_AUDIT)
+                .ignore()
+                .set(CONFIG_AUDIT_MAX_LEN, 3)
+                .ignore()
+                .del(CONFIG_AUDIT_MAX_AGE)
+                .ignore()
+                .query_async(&mut redis)
+                .await
+                .unwrap();
+        }
+
+        for action in ["one", "two", "three", "four", "five"] {
+            record(&redis, "connie", action, None).await;
+        }
+
+        let actions: Vec<String> = recent(&redis)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(actions, ["five", "four", "three"]);
+
+        let mut redis = redis.get();
+        let () = redis::pipe()
+            .del(AUTH_AUDIT)
+            .ignore()
+            .del(CONFIG_AUDIT_MAX_LEN)
+            .ignore()
+            .query_async(&mut redis)
+            .await
+            .unwrap();
+    }
+}
@@ -4,6 +4,7 @@ use chrono::{DateTime, Duration, Utc};
 use digest::{Digest, KeyInit};
 use hmac::Hmac;
 use jwt::{Header, SignWithKey, Token, Verified, VerifyWithKey};
+use models::keys::{AUTH_LEVEL, AUTH_PASSWORD};
 use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Sha384};
@@ -15,8 +16,30 @@ use warp::{
 
 use crate::{error::WebErrorExt, RedisConn, StatePackage};
 
+use super::audit;
+
 const TOKEN_DAYS: i64 = 30;
 
+const MIN_PASSWORD_LEN: usize = 12;
+/// Out of lowercase, uppercase, digits and everything else
+const MIN_PASSWORD_CHAR_CLASSES: usize = 2;
+/// Long enough to pass the length check but guessed first anyway. Compared
+/// case-insensitively.
+const COMMON_PASSWORDS: &[&str] = &[
+    "1q2w3e4r5t6y",
+    "abc123456789",
+    "changeme1234",
+    "football1234",
+    "iloveyou1234",
+    "letmein12345",
+    "monkey123456",
+    "password1234",
+    "password123!",
+    "qwerty123456",
+    "qwertyuiop12",
+    "welcome12345",
+];
+
 pub const AUTH_LEVEL_READONLY: i32 = 0;
 pub const AUTH_LEVEL_QUICKACTION: i32 = 1;
 pub const AUTH_LEVEL_REPROGRAM: i32 = 2;
@@ -28,7 +51,8 @@ pub enum AuthFailed {
     InvalidToken,
     Expired,
     Permission,
-    WeakPassword,
+    /// Says what the password is missing
+    WeakPassword(&'static str),
     NotApproved,
     AccountExists,
 }
@@ -38,8 +62,30 @@ struct RedisError(redis::RedisError);
 impl Reject for RedisError {}
 
 pub fn with_auth(level: i32) -> BoxedFilter<()> {
+    with_auth_claims(level).map(|_| ()).untuple_one().boxed()
+}
+
+/// Same as `with_auth`, but hands the verified claims on to the handler
+pub fn with_auth_claims(level: i32) -> BoxedFilter<(Authentication,)> {
     warp::filters::header::header("X-Auth")
         .and_then(move |auth: String| validate_auth_token(auth, level))
+        .boxed()
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: String,
+}
+
+/// `with_auth` for routes a browser opens with `WebSocket` or `EventSource`.
+/// Neither can set `X-Auth`, so the token may come as `?token=` instead.
+pub fn with_stream_auth(level: i32) -> BoxedFilter<()> {
+    let query = warp::query::<TokenQuery>()
+        .and_then(move |query: TokenQuery| validate_auth_token(query.token, level));
+    with_auth_claims(level)
+        .or(query)
+        .unify()
+        .map(|_| ())
         .untuple_one()
         .boxed()
 }
@@ -56,7 +102,9 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
                 let redis = redis.clone();
                 async move {
                     if validate_credentials(&redis, &user, &pass).await? {
-                        Ok(generate_auth_token(&redis, &user).await?)
+                        let token = generate_auth_token(&redis, &user).await?;
+                        audit::record(&redis, &user, "login", None).await;
+                        Ok(token)
                     } else {
                         Err(warp::reject::custom(AuthFailed::Credentials))
                     }
@@ -101,8 +149,10 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
             .and(warp::put())
             .and(header("X-Username"))
             .and(header("X-AuthLevel"))
-            .and(with_auth(AUTH_LEVEL_ADMIN))
-            .and_then(move |user: String, level: i32| set_auth_level(redis.clone(), user, level))
+            .and(with_auth_claims(AUTH_LEVEL_ADMIN))
+            .and_then(move |user: String, level: i32, auth: Authentication| {
+                set_auth_level(redis.clone(), auth, user, level)
+            })
     };
 
     let reset_password = {
@@ -111,8 +161,10 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
             .and(path::end())
             .and(warp::put())
             .and(header("X-Username"))
-            .and(with_auth(AUTH_LEVEL_ADMIN))
-            .and_then(move |user: String| reset_password(redis.clone(), user))
+            .and(with_auth_claims(AUTH_LEVEL_ADMIN))
+            .and_then(move |user: String, auth: Authentication| {
+                reset_password(redis.clone(), auth, user)
+            })
     };
 
     let list_users = {
@@ -130,8 +182,25 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
             .and(path::end())
             .and(warp::delete())
             .and(header("X-Username"))
+            .and(with_auth_claims(AUTH_LEVEL_ADMIN))
+            .and_then(move |user: String, auth: Authentication| {
+                delete_user(redis.clone(), auth, user)
+            })
+    };
+
+    let audit_log = {
+        let redis = redis.clone();
+        warp::path("audit")
+            .and(path::end())
+            .and(warp::get())
             .and(with_auth(AUTH_LEVEL_ADMIN))
-            .and_then(move |user: String| delete_user(redis.clone(), user))
+            .and_then(move || {
+                let redis = redis.clone();
+                async move {
+                    let entries = audit::recent(&redis).await.reject_err()?;
+                    serde_json::to_string(&entries).reject_err()
+                }
+            })
     };
 
     login
@@ -142,6 +211,7 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
         .or(reset_password)
         .or(list_users)
         .or(delete_user)
+        .or(audit_log)
         .boxed()
 }
 
@@ -154,7 +224,7 @@ async fn validate_credentials(
 
     let saved_hash: String = {
         let mut redis = redis.get();
-        redis.hget("auth.password", user).await.reject_err()?
+        redis.hget(AUTH_PASSWORD, user).await.reject_err()?
     };
 
     Ok(hash.eq_ignore_ascii_case(&saved_hash))
@@ -163,15 +233,17 @@ async fn validate_credentials(
 async fn generate_auth_token(redis: &RedisConn, user: &str) -> Result<String, Rejection> {
     let auth_level: i32 = {
         let mut redis = redis.get();
-        redis.hget("auth.level", user).await.reject_err()?
+        redis.hget(AUTH_LEVEL, user).await.reject_err()?
     };
 
-    let claims = Authentication {
+    sign_auth_token(Authentication {
         user: user.into(),
         valid_until: Utc::now() + Duration::days(TOKEN_DAYS),
         auth_level,
-    };
+    })
+}
 
+fn sign_auth_token(claims: Authentication) -> Result<String, Rejection> {
     let header = Header {
         algorithm: jwt::AlgorithmType::Hs384,
         ..Default::default()
@@ -184,6 +256,17 @@ async fn generate_auth_token(redis: &RedisConn, user: &str) -> Result<String, Re
         .to_string())
 }
 
+/// A fresh token for `user`, without looking anything up in redis
+#[cfg(test)]
+pub fn test_token(user: &str, auth_level: i32) -> String {
+    sign_auth_token(Authentication {
+        user: user.into(),
+        valid_until: Utc::now() + Duration::days(TOKEN_DAYS),
+        auth_level,
+    })
+    .unwrap()
+}
+
 fn verify_auth_token(token: String) -> Result<Authentication, Rejection> {
     type VerifiedToken = Token<Header, Authentication, Verified>;
     let token: VerifiedToken = token
@@ -192,7 +275,13 @@ fn verify_auth_token(token: String) -> Result<Authentication, Rejection> {
     Ok(token.claims().clone())
 }
 
-async fn validate_auth_token(token: String, level: i32) -> Result<(), Rejection> {
+/// The user a still valid token belongs to
+pub fn token_user(token: &str) -> Option<String> {
+    let claims = verify_auth_token(token.to_string()).ok()?;
+    (claims.valid_until >= Utc::now()).then_some(claims.user)
+}
+
+async fn validate_auth_token(token: String, level: i32) -> Result<Authentication, Rejection> {
     let claims = verify_auth_token(token)?;
     if claims.valid_until < Utc::now() {
         return Err(AuthFailed::Expired.into());
@@ -200,14 +289,14 @@ async fn validate_auth_token(token: String, level: i32) -> Result<(), Rejection>
     if claims.auth_level < level {
         return Err(AuthFailed::Permission.into());
     }
-    Ok(())
+    Ok(claims)
 }
 
 async fn renew_auth_token(redis: RedisConn, token: String) -> Result<String, Rejection> {
     let mut claims = verify_auth_token(token)?;
 
     let mut redis = redis.get();
-    claims.auth_level = redis.hget("auth.level", &claims.user).await.reject_err()?;
+    claims.auth_level = redis.hget(AUTH_LEVEL, &claims.user).await.reject_err()?;
     claims.valid_until = Utc::now() + Duration::days(TOKEN_DAYS);
     let token = claims.sign_with_key(&jwt_key()).reject_err()?;
 
@@ -219,42 +308,83 @@ async fn change_password(
     token: String,
     new_password: String,
 ) -> Result<String, Rejection> {
-    if new_password.len() < 12 {
-        return Err(AuthFailed::WeakPassword.into());
-    }
+    validate_password_strength(&new_password)?;
 
     let auth = verify_auth_token(token)?;
     let hash = hex::encode(<Sha256 as Digest>::digest(new_password));
 
     let mut redis = redis.get();
     let () = redis
-        .hset("auth.password", &auth.user, hash)
+        .hset(AUTH_PASSWORD, &auth.user, hash)
         .await
         .reject_err()?;
 
     Ok("ok".into())
 }
 
-async fn set_auth_level(redis: RedisConn, user: String, level: i32) -> Result<String, Rejection> {
-    let mut redis = redis.get();
-    let () = redis.hset("auth.level", &user, level).await.reject_err()?;
+fn validate_password_strength(password: &str) -> Result<(), AuthFailed> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(AuthFailed::WeakPassword(
+            "Passwords must be at least 12 characters long",
+        ));
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|&&has| has).count() < MIN_PASSWORD_CHAR_CLASSES {
+        return Err(AuthFailed::WeakPassword(
+            "Passwords must mix at least two of lowercase, uppercase, digits and symbols",
+        ));
+    }
+
+    if COMMON_PASSWORDS
+        .iter()
+        .any(|common| common.eq_ignore_ascii_case(password))
+    {
+        return Err(AuthFailed::WeakPassword("That password is too common"));
+    }
+
+    Ok(())
+}
+
+async fn set_auth_level(
+    redis: RedisConn,
+    auth: Authentication,
+    user: String,
+    level: i32,
+) -> Result<String, Rejection> {
+    if level < AUTH_LEVEL_ADMIN {
+        check_removal(&auth, &user, &auth_levels(&redis).await?)?;
+    }
+
+    {
+        let mut redis = redis.get();
+        let () = redis.hset(AUTH_LEVEL, &user, level).await.reject_err()?;
+    }
+    let action = format!("set_auth_level:{level}");
+    audit::record(&redis, &auth.user, &action, Some(&user)).await;
     Ok("ok".into())
 }
 
 async fn register(redis: RedisConn, user: String, pass: String) -> Result<String, Rejection> {
+    validate_password_strength(&pass)?;
     let hash = hex::encode(<Sha256 as Digest>::digest(pass));
 
     {
         let mut redis = redis.get();
-        let Some(_): Option<i32> = redis.hget("auth.level", &user).await.reject_err()? else {
+        let Some(_): Option<i32> = redis.hget(AUTH_LEVEL, &user).await.reject_err()? else {
             return Err(AuthFailed::NotApproved.into());
         };
-        let None: Option<String> = redis.hget("auth.password", &user).await.reject_err()? else {
+        let None: Option<String> = redis.hget(AUTH_PASSWORD, &user).await.reject_err()? else {
             return Err(AuthFailed::AccountExists.into());
         };
 
         let () = redis
-            .hset("auth.password", &user, hash)
+            .hset(AUTH_PASSWORD, &user, hash)
             .await
             .reject_err()?;
     }
@@ -262,11 +392,16 @@ async fn register(redis: RedisConn, user: String, pass: String) -> Result<String
     generate_auth_token(&redis, &user).await
 }
 
-async fn reset_password(redis: RedisConn, user: String) -> Result<String, Rejection> {
+async fn reset_password(
+    redis: RedisConn,
+    auth: Authentication,
+    user: String,
+) -> Result<String, Rejection> {
     {
         let mut redis = redis.get();
-        let () = redis.hdel("auth.password", &user).await.reject_err()?;
+        let () = redis.hdel(AUTH_PASSWORD, &user).await.reject_err()?;
     }
+    audit::record(&redis, &auth.user, "reset_password", Some(&user)).await;
 
     Ok("ok".into())
 }
@@ -280,8 +415,8 @@ async fn list_users(redis: RedisConn) -> Result<String, Rejection> {
 
     let mut redis = redis.get();
 
-    let user_levels: BTreeMap<String, i32> = redis.hgetall("auth.level").await.reject_err()?;
-    let registered_users: BTreeSet<String> = redis.hkeys("auth.password").await.reject_err()?;
+    let user_levels: BTreeMap<String, i32> = redis.hgetall(AUTH_LEVEL).await.reject_err()?;
+    let registered_users: BTreeSet<String> = redis.hkeys(AUTH_PASSWORD).await.reject_err()?;
 
     Ok(serde_json::to_string(
         &user_levels
@@ -295,23 +430,234 @@ async fn list_users(redis: RedisConn) -> Result<String, Rejection> {
     .reject_err()?)
 }
 
-async fn delete_user(redis: RedisConn, user: String) -> Result<String, Rejection> {
-    let mut redis = redis.get();
+async fn delete_user(
+    redis: RedisConn,
+    auth: Authentication,
+    user: String,
+) -> Result<String, Rejection> {
+    check_removal(&auth, &user, &auth_levels(&redis).await?)?;
 
-    let () = redis.hdel("auth.password", &user).await.reject_err()?;
-    let () = redis.hdel("auth.level", &user).await.reject_err()?;
+    {
+        let mut redis = redis.get();
+        let () = redis.hdel(AUTH_PASSWORD, &user).await.reject_err()?;
+        let () = redis.hdel(AUTH_LEVEL, &user).await.reject_err()?;
+    }
+    audit::record(&redis, &auth.user, "delete_user", Some(&user)).await;
 
     Ok("ok".into())
 }
 
+async fn auth_levels(redis: &RedisConn) -> Result<BTreeMap<String, i32>, Rejection> {
+    let mut redis = redis.get();
+    redis.hgetall(AUTH_LEVEL).await.reject_err()
+}
+
+/// Whether `auth` may demote `user` below admin or delete them. Nobody can do
+/// it to themselves, and the last admin has to stay.
+fn check_removal(
+    auth: &Authentication,
+    user: &str,
+    levels: &BTreeMap<String, i32>,
+) -> Result<(), AuthFailed> {
+    if user == auth.user || is_last_admin(levels, user) {
+        return Err(AuthFailed::Permission);
+    }
+    Ok(())
+}
+
+/// Demoting or deleting `user` would leave nobody able to administer accounts
+fn is_last_admin(levels: &BTreeMap<String, i32>, user: &str) -> bool {
+    let mut admins = levels
+        .iter()
+        .filter(|(_, &level)| level >= AUTH_LEVEL_ADMIN)
+        .map(|(name, _)| name);
+    matches!((admins.next(), admins.next()), (Some(only), None) if only == user)
+}
+
 fn jwt_key() -> Hmac<Sha384> {
     const SECRET: &str = dotenv_codegen::dotenv!("JWT_SECRET");
     KeyInit::new_from_slice(SECRET.as_bytes()).unwrap()
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-struct Authentication {
-    user: String,
-    valid_until: DateTime<Utc>,
-    auth_level: i32,
+pub struct Authentication {
+    pub user: String,
+    pub valid_until: DateTime<Utc>,
+    pub auth_level: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weakness(password: &str) -> Option<&'static str> {
+        match validate_password_strength(password) {
+            Ok(()) => None,
+            Err(AuthFailed::WeakPassword(why)) => Some(why),
+            Err(other) => panic!("unexpected rejection {:?}", other),
+        }
+    }
+
+    fn claims(user: &str, auth_level: i32) -> Authentication {
+        Authentication {
+            user: user.into(),
+            valid_until: Utc::now() + Duration::days(1),
+            auth_level,
+        }
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn admin_actions_are_attributed_to_the_caller() {
+        let redis = RedisConn::scratch().await;
+        {
+            let mut redis = redis.get();
+            let () = redis::pipe()
+                .del(models::keys::AUTH_AUDIT)
+                .ignore()
+                .hset(AUTH_PASSWORD, "test_audit_target", "hash")
+                .ignore()
+                .query_async(&mut redis)
+                .await
+                .unwrap();
+        }
+
+        let admin = claims("test_audit_admin", AUTH_LEVEL_ADMIN);
+        reset_password(redis.clone(), admin, "test_audit_target".into())
+            .await
+            .unwrap();
+
+        let entries = audit::recent(&redis).await.unwrap();
+        let entry = &entries[0];
+        assert_eq!(entry.actor, "test_audit_admin");
+        assert_eq!(entry.action, "reset_password");
+        assert_eq!(entry.target.as_deref(), Some("test_audit_target"));
+        assert!(Utc::now() - entry.time < Duration::minutes(1));
+
+        let mut redis = redis.get();
+        let () = redis.del(models::keys::AUTH_AUDIT).await.unwrap();
+    }
+
+    fn levels(users: &[(&str, i32)]) -> BTreeMap<String, i32> {
+        users.iter().map(|&(user, level)| (user.to_string(), level)).collect()
+    }
+
+    #[test]
+    fn admins_cant_remove_themselves() {
+        let levels = levels(&[("connie", AUTH_LEVEL_ADMIN), ("sam", AUTH_LEVEL_ADMIN)]);
+        let connie = claims("connie", AUTH_LEVEL_ADMIN);
+        assert!(matches!(
+            check_removal(&connie, "connie", &levels),
+            Err(AuthFailed::Permission)
+        ));
+    }
+
+    #[test]
+    fn last_admin_stays() {
+        // Only reachable through a token issued before sam's demotion
+        let levels = levels(&[("connie", AUTH_LEVEL_ADMIN), ("sam", AUTH_LEVEL_REPROGRAM)]);
+        let sam = claims("sam", AUTH_LEVEL_ADMIN);
+        assert!(matches!(
+            check_removal(&sam, "connie", &levels),
+            Err(AuthFailed::Permission)
+        ));
+    }
+
+    #[test]
+    fn admins_can_demote_others() {
+        let levels = levels(&[
+            ("connie", AUTH_LEVEL_ADMIN),
+            ("sam", AUTH_LEVEL_ADMIN),
+            ("guest", AUTH_LEVEL_QUICKACTION),
+        ]);
+        let connie = claims("connie", AUTH_LEVEL_ADMIN);
+        assert!(check_removal(&connie, "sam", &levels).is_ok());
+        assert!(check_removal(&connie, "guest", &levels).is_ok());
+    }
+
+    fn whoami() -> BoxedFilter<(String,)> {
+        with_auth_claims(AUTH_LEVEL_REPROGRAM)
+            .map(|auth: Authentication| format!("{}:{}", auth.user, auth.auth_level))
+            .boxed()
+    }
+
+    #[tokio::test]
+    async fn handlers_get_the_callers_claims() {
+        let reply = warp::test::request()
+            .header("X-Auth", test_token("connie", AUTH_LEVEL_ADMIN))
+            .reply(&whoami())
+            .await;
+        assert_eq!(reply.body(), "connie:3");
+    }
+
+    #[tokio::test]
+    async fn claims_need_a_valid_token_at_the_level() {
+        let rejected = |token: String| async move {
+            warp::test::request()
+                .header("X-Auth", token)
+                .filter(&whoami())
+                .await
+                .is_err()
+        };
+        assert!(rejected(test_token("guest", AUTH_LEVEL_QUICKACTION)).await);
+        assert!(rejected("not a token".into()).await);
+
+        let mut expired = claims("connie", AUTH_LEVEL_ADMIN);
+        expired.valid_until = Utc::now() - Duration::minutes(1);
+        assert!(rejected(sign_auth_token(expired).unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn streams_take_the_token_from_the_query() {
+        let accepted = |request: warp::test::RequestBuilder| async move {
+            request.filter(&with_stream_auth(AUTH_LEVEL_QUICKACTION)).await.is_ok()
+        };
+        let token = test_token("kiosk", AUTH_LEVEL_QUICKACTION);
+
+        let query = warp::test::request().path(&format!("/live?token={}", token));
+        assert!(accepted(query).await);
+        let header = warp::test::request().path("/live").header("X-Auth", &token);
+        assert!(accepted(header).await);
+
+        assert!(!accepted(warp::test::request().path("/live")).await);
+        assert!(!accepted(warp::test::request().path("/live?token=nope")).await);
+        let readonly = test_token("guest", AUTH_LEVEL_READONLY);
+        let query = warp::test::request().path(&format!("/live?token={}", readonly));
+        assert!(!accepted(query).await);
+    }
+
+    #[test]
+    fn strong_passwords_are_accepted() {
+        assert_eq!(weakness("correct horse battery"), None);
+        assert_eq!(weakness("Thermostat2023"), None);
+        assert_eq!(weakness("ünïcödé-pässwörd"), None);
+    }
+
+    #[test]
+    fn short_passwords_are_rejected() {
+        assert!(weakness("Sh0rt!").unwrap().contains("12 characters"));
+        // Counted in characters, not bytes
+        assert!(weakness("ääääääBBBB1").unwrap().contains("12 characters"));
+    }
+
+    #[test]
+    fn single_class_passwords_are_rejected() {
+        for password in ["abcdefghijklmn", "ABCDEFGHIJKLMN", "12345678901234", "!@#$%^&*()_+-="] {
+            assert!(weakness(password).unwrap().contains("mix"), "{:?}", password);
+        }
+    }
+
+    #[test]
+    fn common_passwords_are_rejected() {
+        assert_eq!(weakness("Password1234"), Some("That password is too common"));
+        assert_eq!(weakness("QWERTY123456"), Some("That password is too common"));
+    }
+
+    #[test]
+    fn every_common_password_gets_past_the_other_checks() {
+        // Anything the length or class checks already reject would never be looked up
+        for common in COMMON_PASSWORDS {
+            assert_eq!(weakness(common), Some("That password is too common"), "{}", common);
+        }
+    }
 }
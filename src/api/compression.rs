@@ -0,0 +1,187 @@
+use std::io::Write;
+
+use flate2::{
+    write::{DeflateEncoder, GzEncoder},
+    Compression,
+};
+use http::{
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY},
+    HeaderValue,
+};
+use warp::{
+    filters::BoxedFilter,
+    hyper::{self, Body},
+    reply::Response,
+    Filter, Rejection, Reply,
+};
+
+use crate::error::WebErrorExt;
+
+/// Anything smaller than this isn't worth the CPU time
+const MIN_COMPRESS_SIZE: usize = 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn header_value(self) -> HeaderValue {
+        match self {
+            Encoding::Gzip => HeaderValue::from_static("gzip"),
+            Encoding::Deflate => HeaderValue::from_static("deflate"),
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Compress the replies of `filter` with whatever the client's
+/// `Accept-Encoding` allows, preferring gzip over deflate
+pub fn compressed<F, R>(filter: F) -> BoxedFilter<(Response,)>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::header::optional::<String>(ACCEPT_ENCODING.as_str())
+        .and(filter)
+        .and_then(|accept: Option<String>, reply: R| async move {
+            let resp = reply.into_response();
+            match accept.as_deref().and_then(negotiate) {
+                Some(encoding) => compress(encoding, resp).await,
+                None => Ok(resp),
+            }
+        })
+        .boxed()
+}
+
+fn negotiate(accept: &str) -> Option<Encoding> {
+    let mut deflate = false;
+    for entry in accept.split(',') {
+        let mut parts = entry.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let refused = parts
+            .filter_map(|param| param.strip_prefix("q="))
+            .any(|q| q.parse::<f32>().is_ok_and(|q| q <= 0.0));
+        if refused {
+            continue;
+        }
+
+        if name.eq_ignore_ascii_case("gzip") {
+            return Some(Encoding::Gzip);
+        } else if name.eq_ignore_ascii_case("deflate") {
+            deflate = true;
+        }
+    }
+
+    deflate.then_some(Encoding::Deflate)
+}
+
+fn is_compressible(resp: &Response) -> bool {
+    let Some(content_type) = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+
+    content_type.starts_with("application/json") || content_type.starts_with("text/")
+}
+
+async fn compress(encoding: Encoding, resp: Response) -> Result<Response, Rejection> {
+    if resp.headers().contains_key(CONTENT_ENCODING) || !is_compressible(&resp) {
+        return Ok(resp);
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    let data = hyper::body::to_bytes(body).await.reject_err()?;
+    if data.len() < MIN_COMPRESS_SIZE {
+        return Ok(Response::from_parts(parts, Body::from(data)));
+    }
+
+    let compressed = encoding.encode(&data).reject_err()?;
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.insert(CONTENT_ENCODING, encoding.header_value());
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+
+    Ok(Response::from_parts(parts, Body::from(compressed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn history(entries: usize) -> String {
+        let history: Vec<_> = (0..entries)
+            .map(|i| {
+                let time = 1690000000000u64 + i as u64 * 10_000;
+                serde_json::json!({ "time": time, "temp": 21.5 })
+            })
+            .collect();
+        serde_json::to_string(&history).unwrap()
+    }
+
+    fn route(body: String) -> BoxedFilter<(Response,)> {
+        compressed(warp::path::end().map(move || {
+            warp::reply::with_header(body.clone(), CONTENT_TYPE, "application/json")
+        }))
+    }
+
+    #[tokio::test]
+    async fn large_history_is_gzipped() {
+        let body = history(500);
+        let resp = warp::test::request()
+            .header("accept-encoding", "deflate, gzip")
+            .reply(&route(body.clone()))
+            .await;
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+        assert!(resp.body().len() < body.len());
+
+        let mut decompressed = String::new();
+        GzDecoder::new(&resp.body()[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+    }
+
+    #[tokio::test]
+    async fn small_or_unrequested_replies_are_left_alone() {
+        let resp = warp::test::request()
+            .header("accept-encoding", "gzip")
+            .reply(&route(history(1)))
+            .await;
+        assert!(!resp.headers().contains_key(CONTENT_ENCODING));
+
+        let resp = warp::test::request().reply(&route(history(500))).await;
+        assert!(!resp.headers().contains_key(CONTENT_ENCODING));
+    }
+
+    #[test]
+    fn negotiates_the_encoding() {
+        assert_eq!(negotiate("gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, gzip;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0, deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("br, identity"), None);
+    }
+}
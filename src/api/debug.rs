@@ -0,0 +1,270 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use models::keys::{
+    AWAY_MODE_KEY, COMFORT_PROFILE_KEY, CONFIG_LOCATION, CONFIG_MODE, CONFIG_MODE_CONFIRM_TIMEOUT,
+    CONFIG_PUBLISH_ON_CHANGE,
+    CURRENT_RULESET_KEY, ONESHOT_BOUNDS_KEY, PROBE_ENDPOINTS, PROBE_HISTORY_REPORTS,
+    PROBE_MIN_INTERVALS, PROBE_STALE_AFTER,
+};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+use warp::{
+    filters::{path, BoxedFilter},
+    Filter, Reply,
+};
+
+use crate::{
+    api::request_log, error::WebErrorExt, helpers::MissingOrInvalidParameter, hvac::history,
+    mqtt::MqttClient, StatePackage,
+};
+
+const MQTT_ECHO_DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const MQTT_ECHO_MAX_TIMEOUT: Duration = Duration::from_secs(30);
+
+enum ConfigKind {
+    String,
+    Hash,
+}
+
+/// The only keys that may be read back. Never add anything under `auth.`
+const READABLE_CONFIG: &[(&str, &str, ConfigKind)] = &[
+    ("ruleset", CURRENT_RULESET_KEY, ConfigKind::String),
+    ("probe_endpoints", PROBE_ENDPOINTS, ConfigKind::Hash),
+    ("probe_min_intervals", PROBE_MIN_INTERVALS, ConfigKind::Hash),
+    ("probe_stale_after", PROBE_STALE_AFTER, ConfigKind::Hash),
+    ("mode", CONFIG_MODE, ConfigKind::String),
+    ("mode_confirm_timeout", CONFIG_MODE_CONFIRM_TIMEOUT, ConfigKind::String),
+    ("publish_on_change", CONFIG_PUBLISH_ON_CHANGE, ConfigKind::String),
+    ("away", AWAY_MODE_KEY, ConfigKind::String),
+    ("comfort_profile", COMFORT_PROFILE_KEY, ConfigKind::String),
+    ("oneshot_bounds", ONESHOT_BOUNDS_KEY, ConfigKind::String),
+    ("location", CONFIG_LOCATION, ConfigKind::String),
+    ("probe_history_reports", PROBE_HISTORY_REPORTS, ConfigKind::Hash),
+];
+
+/// The Redis key behind an allowlisted section name
+fn readable_config(section: &str) -> Option<(&'static str, &'static ConfigKind)> {
+    READABLE_CONFIG
+        .iter()
+        .find(|(name, _, _)| *name == section)
+        .map(|(_, key, kind)| (*key, kind))
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ConfigValue {
+    String(Option<String>),
+    Hash(BTreeMap<String, String>),
+}
+
+#[derive(Serialize)]
+struct ConfigResponse {
+    key: &'static str,
+    value: ConfigValue,
+}
+
+pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    let config = {
+        let redis = state.redis.clone();
+        warp::path!("config" / String)
+            .and(path::end())
+            .and(warp::get())
+            .and_then(move |section: String| {
+                let redis = redis.clone();
+                async move {
+                    let Some((key, kind)) = readable_config(&section) else {
+                        return Err(warp::reject::not_found());
+                    };
+
+                    let mut redis = redis.get();
+                    let value = match kind {
+                        ConfigKind::String => {
+                            ConfigValue::String(redis.get(key).await.reject_err()?)
+                        }
+                        ConfigKind::Hash => {
+                            ConfigValue::Hash(redis.hgetall(key).await.reject_err()?)
+                        }
+                    };
+
+                    serde_json::to_string(&ConfigResponse { key, value }).reject_err()
+                }
+            })
+    };
+
+    let compact_history = {
+        let redis = state.redis.clone();
+        let probes = state.hvac.probes.clone();
+        warp::path!("probes" / String / "compact_history")
+            .and(warp::query::<CompactQuery>())
+            .and(path::end())
+            .and(warp::post())
+            .and_then(move |probe: String, query: CompactQuery| {
+                let redis = redis.clone();
+                let probes = probes.clone();
+                async move {
+                    if probes.get(&probe).await.is_none() {
+                        return Err(warp::reject::not_found());
+                    }
+
+                    let report = history::compact_probe_history(&redis, &probe, query.rewrite)
+                        .await
+                        .reject_err()?;
+                    serde_json::to_string(&report).reject_err()
+                }
+            })
+    };
+
+    let mqtt_echo = {
+        let mqtt = state.mqtt.clone();
+        warp::path("mqtt_echo")
+            .and(path::end())
+            .and(warp::post())
+            .and(warp::body::json::<MqttEchoRequest>())
+            .and_then(move |request: MqttEchoRequest| {
+                let mqtt = mqtt.clone();
+                async move {
+                    if request.topic.is_empty()
+                        || request.topic.contains(['+', '#', '*'])
+                    {
+                        return Err(warp::reject::custom(MissingOrInvalidParameter("topic")));
+                    }
+
+                    let timeout = request
+                        .timeout_ms
+                        .map(Duration::from_millis)
+                        .unwrap_or(MQTT_ECHO_DEFAULT_TIMEOUT)
+                        .min(MQTT_ECHO_MAX_TIMEOUT);
+
+                    let result = mqtt_echo(&mqtt, &request.topic, timeout).await;
+                    serde_json::to_string(&result).reject_err()
+                }
+            })
+    };
+
+    let request_log = {
+        let redis = state.redis.clone();
+        warp::path("request_log")
+            .and(path::end())
+            .and(warp::get())
+            .and_then(move || {
+                let redis = redis.clone();
+                async move {
+                    let entries = request_log::recent(&redis).await.reject_err()?;
+                    serde_json::to_string(&entries).reject_err()
+                }
+            })
+    };
+
+    config
+        .or(compact_history)
+        .or(mqtt_echo)
+        .or(request_log)
+        .boxed()
+}
+
+#[derive(Deserialize)]
+struct MqttEchoRequest {
+    topic: String,
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct MqttEchoResult {
+    topic: String,
+    payload: String,
+    success: bool,
+    latency_ms: Option<f64>,
+}
+
+/// Publish a unique payload and wait for the broker to hand it back. The
+/// subscription is left in place since other handlers may share the topic.
+async fn mqtt_echo(mqtt: &MqttClient, topic: &str, timeout: Duration) -> MqttEchoResult {
+    let payload = Uuid::new_v4().to_string();
+    let (tx, rx) = oneshot::channel();
+    let tx = Mutex::new(Some(tx));
+
+    let handler = {
+        let payload = payload.clone();
+        mqtt.handle(topic, move |_topic, received| {
+            if received == payload.as_bytes() {
+                if let Some(tx) = tx.lock().unwrap().take() {
+                    let _ = tx.send(Instant::now());
+                }
+            }
+        })
+        .await
+    };
+    mqtt.subscribe(topic).await;
+
+    let sent = Instant::now();
+    mqtt.publish(topic, payload.as_bytes()).await;
+    let received = tokio::time::timeout(timeout, rx).await;
+
+    mqtt.unhandle(topic, handler).await;
+
+    let latency = match received {
+        Ok(Ok(received)) => Some(received.duration_since(sent)),
+        _ => None,
+    };
+
+    MqttEchoResult {
+        topic: topic.into(),
+        payload,
+        success: latency.is_some(),
+        latency_ms: latency.map(|latency| latency.as_secs_f64() * 1000.0),
+    }
+}
+
+#[derive(Deserialize)]
+struct CompactQuery {
+    /// Without this the history is only checked, not modified
+    #[serde(default)]
+    rewrite: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mqtt_echo_round_trip() {
+        let mqtt = MqttClient::loopback(true);
+        let result = mqtt_echo(&mqtt, "test/echo", Duration::from_secs(1)).await;
+        assert!(result.success);
+        assert!(result.latency_ms.is_some());
+        assert_eq!(result.topic, "test/echo");
+    }
+
+    #[tokio::test]
+    async fn mqtt_echo_times_out() {
+        let mqtt = MqttClient::loopback(false);
+        let result = mqtt_echo(&mqtt, "test/echo", Duration::from_millis(50)).await;
+        assert!(!result.success);
+        assert_eq!(result.latency_ms, None);
+    }
+
+    #[test]
+    fn allowlisted_sections_map_to_their_keys() {
+        let (key, kind) = readable_config("ruleset").unwrap();
+        assert_eq!(key, CURRENT_RULESET_KEY);
+        assert!(matches!(kind, ConfigKind::String));
+
+        let (key, kind) = readable_config("probe_endpoints").unwrap();
+        assert_eq!(key, PROBE_ENDPOINTS);
+        assert!(matches!(kind, ConfigKind::Hash));
+    }
+
+    #[test]
+    fn other_keys_are_refused() {
+        assert!(readable_config(models::keys::AUTH_PASSWORD).is_none());
+        assert!(readable_config(CURRENT_RULESET_KEY).is_none());
+        assert!(readable_config("").is_none());
+        assert!(READABLE_CONFIG.iter().all(|(_, key, _)| !key.starts_with("auth.")));
+    }
+}
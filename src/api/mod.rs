@@ -1,15 +1,29 @@
-use http::StatusCode;
-use warp::{filters::BoxedFilter, reply, Filter, Rejection, Reply};
+use std::convert::Infallible;
 
-use crate::StatePackage;
+use http::{Method, StatusCode};
+use warp::{
+    filters::BoxedFilter,
+    reply::{self, Response},
+    Filter, Rejection, Reply,
+};
+
+use crate::{error::json_rejection, StatePackage};
 
 use self::auth::AuthFailed;
 
 pub mod atticfan;
+pub mod audit;
 pub mod auth;
+pub mod compression;
+pub mod debug;
+pub mod request_log;
 pub mod thermostat;
 
-pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+/// Comma separated list of origins allowed to make cross-origin requests.
+/// CORS stays off entirely when this isn't set.
+const CORS_ORIGINS_VAR: &str = "HOME_SERVER_CORS_ORIGINS";
+
+pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(Response,)> {
     let auth = warp::path("auth").and(auth::routes(state).await);
 
     let atticfan = warp::path("atticfan")
@@ -18,17 +32,100 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
     let thermostat = warp::path("thermostat")
         .and(auth::with_auth(1))
         .and(thermostat::routes(state).await);
+    let thermostat_streams = warp::path("thermostat")
+        .and(auth::with_stream_auth(1))
+        .and(thermostat::stream_routes(state).await);
+    let debug = warp::path("debug")
+        .and(auth::with_auth(auth::AUTH_LEVEL_ADMIN))
+        .and(debug::routes(state).await);
 
-    let authed_routes = atticfan.or(thermostat);
-    auth.or(authed_routes)
+    let authed_routes = atticfan.or(thermostat).or(thermostat_streams).or(debug);
+    let routes = auth
+        .or(authed_routes)
         .recover(|rejection: Rejection| async move {
             if let Some(fail) = rejection.find::<AuthFailed>() {
                 let mut resp = reply::json(fail).into_response();
                 *resp.status_mut() = StatusCode::FORBIDDEN;
-                Ok(resp)
+                Ok::<_, Infallible>(resp)
             } else {
-                Err(rejection)
+                Ok(json_rejection(&rejection))
             }
         })
-        .boxed()
+        .with(request_log::layer(state.redis).await);
+
+    match cors_origins() {
+        Some(origins) => routes
+            .with(cors(&origins))
+            .map(Reply::into_response)
+            .boxed(),
+        None => routes.map(Reply::into_response).boxed(),
+    }
+}
+
+fn cors_origins() -> Option<Vec<String>> {
+    let origins: Vec<String> = std::env::var(CORS_ORIGINS_VAR)
+        .ok()?
+        .split(',')
+        .map(|origin| origin.trim().to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+
+    (!origins.is_empty()).then_some(origins)
+}
+
+fn cors(origins: &[String]) -> warp::cors::Builder {
+    warp::cors()
+        .allow_origins(origins.iter().map(String::as_str))
+        .allow_headers([
+            "content-type",
+            "x-auth",
+            "x-username",
+            "x-password",
+            "x-authlevel",
+        ])
+        .allow_methods([Method::GET, Method::PUT, Method::POST, Method::DELETE])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALLOWED: &str = "https://dashboard.example";
+
+    fn route() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().map(|| "ok").with(cors(&[ALLOWED.to_string()]))
+    }
+
+    #[tokio::test]
+    async fn allowed_origin_gets_the_header() {
+        let resp = warp::test::request()
+            .header("origin", ALLOWED)
+            .reply(&route())
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["access-control-allow-origin"], ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn other_origins_do_not() {
+        let resp = warp::test::request()
+            .header("origin", "https://elsewhere.example")
+            .reply(&route())
+            .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(!resp.headers().contains_key("access-control-allow-origin"));
+    }
+
+    #[tokio::test]
+    async fn preflight_allows_the_auth_headers() {
+        let resp = warp::test::request()
+            .method("OPTIONS")
+            .header("origin", ALLOWED)
+            .header("access-control-request-method", "PUT")
+            .header("access-control-request-headers", "x-auth")
+            .reply(&route())
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["access-control-allow-origin"], ALLOWED);
+    }
 }
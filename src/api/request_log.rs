@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use models::keys::{REQUEST_LOG, REQUEST_LOG_ENABLED};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use warp::log::{Info, Log};
+
+use crate::RedisConn;
+
+use super::auth;
+
+/// Only the most recent requests are kept
+const MAX_ENTRIES: isize = 1000;
+
+#[derive(Serialize, Deserialize)]
+pub struct RequestLogEntry {
+    pub time: DateTime<Utc>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub latency_ms: f64,
+    pub user: Option<String>,
+}
+
+/// Appends every request to the `REQUEST_LOG` list when `REQUEST_LOG_ENABLED`
+/// was set to 1 at startup, and does nothing otherwise
+pub async fn layer(redis: &RedisConn) -> Log<impl Fn(Info<'_>) + Clone + Send + Sync> {
+    let enabled = {
+        let mut redis = redis.get();
+        redis
+            .get::<_, Option<u8>>(REQUEST_LOG_ENABLED)
+            .await
+            .ok()
+            .flatten()
+            == Some(1)
+    };
+    let redis = enabled.then(|| redis.clone());
+
+    log_with(move |entry| {
+        let Some(redis) = redis.clone() else {
+            return;
+        };
+        let Ok(data) = serde_json::to_string(&entry) else {
+            return;
+        };
+
+        crate::spawn("request_log", async move {
+            let mut redis = redis.get();
+            let _: Result<(), _> = redis::pipe()
+                .lpush(REQUEST_LOG, data)
+                .ignore()
+                .ltrim(REQUEST_LOG, 0, MAX_ENTRIES - 1)
+                .ignore()
+                .query_async(&mut redis)
+                .await;
+        });
+    })
+}
+
+/// Hands an entry for every request to `sink`
+fn log_with(
+    sink: impl Fn(RequestLogEntry) + Clone + Send + Sync,
+) -> Log<impl Fn(Info<'_>) + Clone + Send + Sync> {
+    warp::log::custom(move |info| {
+        let user = info
+            .request_headers()
+            .get("X-Auth")
+            .and_then(|token| token.to_str().ok())
+            .and_then(auth::token_user);
+        let entry = RequestLogEntry {
+            time: Utc::now(),
+            method: info.method().to_string(),
+            path: info.path().to_string(),
+            status: info.status().as_u16(),
+            latency_ms: info.elapsed().as_secs_f64() * 1000.0,
+            user,
+        };
+        sink(entry);
+    })
+}
+
+pub async fn recent(redis: &RedisConn) -> anyhow::Result<Vec<RequestLogEntry>> {
+    let mut redis = redis.get();
+    let entries: Vec<String> = redis.lrange(REQUEST_LOG, 0, MAX_ENTRIES - 1).await?;
+    Ok(entries
+        .iter()
+        .filter_map(|entry| serde_json::from_str(entry).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use warp::Filter;
+
+    use super::*;
+
+    async fn logged(request: warp::test::RequestBuilder) -> RequestLogEntry {
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let entries = entries.clone();
+            move |entry| entries.lock().unwrap().push(entry)
+        };
+        let route = warp::path!("api" / "thermostat" / "mode")
+            .map(|| "off")
+            .with(log_with(sink));
+
+        request.reply(&route).await;
+        let mut entries = entries.lock().unwrap();
+        assert_eq!(entries.len(), 1);
+        entries.pop().unwrap()
+    }
+
+    #[tokio::test]
+    async fn request_produces_an_entry() {
+        let entry = logged(warp::test::request().path("/api/thermostat/mode")).await;
+        assert_eq!(entry.method, "GET");
+        assert_eq!(entry.path, "/api/thermostat/mode");
+        assert_eq!(entry.status, 200);
+        assert!(entry.latency_ms >= 0.0);
+        assert_eq!(entry.user, None);
+    }
+
+    #[tokio::test]
+    async fn entry_names_the_authenticated_user() {
+        let token = auth::test_token("connie", 1);
+        let request = warp::test::request()
+            .method("PUT")
+            .path("/api/unknown")
+            .header("X-Auth", token);
+        let entry = logged(request).await;
+        assert_eq!(entry.method, "PUT");
+        assert_eq!(entry.status, 404);
+        assert_eq!(entry.user.as_deref(), Some("connie"));
+    }
+}
@@ -0,0 +1,67 @@
+use std::future::ready;
+
+use warp::{
+    filters::{path, BoxedFilter},
+    Filter, Rejection, Reply,
+};
+
+use crate::{
+    api::auth::{with_auth, AUTH_LEVEL_REPROGRAM},
+    error::WebErrorExt,
+    hvac::mixer::away_mode::{AwayBand, AwayModeState},
+    StatePackage,
+};
+
+pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    let index = {
+        let hvac = state.hvac.clone();
+        path::end().and(warp::get()).and_then(move || {
+            let state = hvac.mixer.state().away_mode.get();
+            ready(serde_json::to_string(&state).reject_err())
+        })
+    };
+
+    let put = {
+        let hvac = state.hvac.clone();
+        let redis = state.redis.clone();
+        path::end()
+            .and(warp::put())
+            .and(warp::body::json::<bool>())
+            .and_then(move |active| {
+                let state = hvac.mixer.state();
+                let redis = redis.clone();
+                async move {
+                    let new_state = AwayModeState {
+                        active,
+                        ..state.away_mode.get()
+                    };
+                    state.away_mode.set(&redis, new_state).await.reject_err()?;
+                    Ok::<_, Rejection>("ok".to_string())
+                }
+            })
+    };
+
+    let put_band = {
+        let hvac = state.hvac.clone();
+        let redis = state.redis.clone();
+        warp::path("band")
+            .and(path::end())
+            .and(warp::put())
+            .and(warp::body::json::<AwayBand>())
+            .and(with_auth(AUTH_LEVEL_REPROGRAM))
+            .and_then(move |band| {
+                let state = hvac.mixer.state();
+                let redis = redis.clone();
+                async move {
+                    let new_state = AwayModeState {
+                        band,
+                        ..state.away_mode.get()
+                    };
+                    state.away_mode.set(&redis, new_state).await.reject_err()?;
+                    Ok::<_, Rejection>("ok".to_string())
+                }
+            })
+    };
+
+    index.or(put).or(put_band).boxed()
+}
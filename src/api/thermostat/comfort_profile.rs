@@ -0,0 +1,76 @@
+use std::future::ready;
+
+use warp::{
+    filters::{path, BoxedFilter},
+    Filter, Rejection, Reply,
+};
+
+use crate::{
+    api::auth::{with_auth, AUTH_LEVEL_REPROGRAM},
+    error::WebErrorExt,
+    hvac::mixer::comfort_profile::{ComfortProfileState, ProfileName, ThresholdProfile},
+    StatePackage,
+};
+
+pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    let index = {
+        let hvac = state.hvac.clone();
+        path::end().and(warp::get()).and_then(move || {
+            let state = hvac.mixer.state().comfort_profiles.get();
+            ready(serde_json::to_string(&state).reject_err())
+        })
+    };
+
+    let put = {
+        let hvac = state.hvac.clone();
+        let redis = state.redis.clone();
+        path::end()
+            .and(warp::put())
+            .and(warp::body::json::<ProfileName>())
+            .and_then(move |active| {
+                let state = hvac.mixer.state();
+                let redis = redis.clone();
+                async move {
+                    let new_state = ComfortProfileState {
+                        active,
+                        ..state.comfort_profiles.get()
+                    };
+                    state
+                        .comfort_profiles
+                        .set(&redis, new_state)
+                        .await
+                        .reject_err()?;
+                    Ok::<_, Rejection>("ok".to_string())
+                }
+            })
+    };
+
+    let put_profile = {
+        let hvac = state.hvac.clone();
+        let redis = state.redis.clone();
+        warp::path!(ProfileName)
+            .and(path::end())
+            .and(warp::put())
+            .and(warp::body::json::<ThresholdProfile>())
+            .and(with_auth(AUTH_LEVEL_REPROGRAM))
+            .and_then(move |name, profile| {
+                let state = hvac.mixer.state();
+                let redis = redis.clone();
+                async move {
+                    let mut new_state = state.comfort_profiles.get();
+                    match name {
+                        ProfileName::Comfort => new_state.comfort = profile,
+                        ProfileName::Eco => new_state.eco = profile,
+                    }
+                    state
+                        .comfort_profiles
+                        .set(&redis, new_state)
+                        .await
+                        .reject_err()?;
+                    Ok::<_, Rejection>("ok".to_string())
+                }
+            })
+    };
+
+    index.or(put).or(put_profile).boxed()
+}
@@ -0,0 +1,145 @@
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::broadcast::{self, error::RecvError};
+use warp::{
+    filters::{
+        path,
+        ws::{Message, WebSocket, Ws},
+        BoxedFilter,
+    },
+    Filter, Reply,
+};
+
+use crate::{
+    hvac::{live::LiveUpdate, HvacState},
+    StatePackage,
+};
+
+/// Pushes a `LiveUpdate` (see `hvac::live` for the schema) every time the
+/// thermostat state changes, starting with a snapshot of the current state.
+pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    let hvac = state.hvac.clone();
+    path::end()
+        .and(warp::get())
+        .and(warp::ws())
+        .map(move |ws: Ws| {
+            let hvac = hvac.clone();
+            ws.on_upgrade(move |socket| run_socket(hvac, socket))
+        })
+        .boxed()
+}
+
+async fn run_socket(hvac: HvacState, socket: WebSocket) {
+    // Subscribe before taking the snapshot so nothing falls in between
+    let updates = hvac.live.subscribe();
+    let snapshot = snapshot(&hvac).await;
+    forward_updates(socket, snapshot, updates).await;
+}
+
+async fn forward_updates(
+    socket: WebSocket,
+    snapshot: Vec<LiveUpdate>,
+    mut updates: broadcast::Receiver<LiveUpdate>,
+) {
+    let (mut tx, mut rx) = socket.split();
+
+    for update in snapshot {
+        if send_update(&mut tx, &update).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(update) => {
+                    if send_update(&mut tx, &update).await.is_err() {
+                        break;
+                    }
+                }
+                // A slow client just misses a few, the next update catches it up
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            msg = rx.next() => match msg {
+                Some(Ok(msg)) if msg.is_close() => break,
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => break,
+            },
+        }
+    }
+
+    tx.close().await.ok();
+}
+
+async fn snapshot(hvac: &HvacState) -> Vec<LiveUpdate> {
+    let mixer = hvac.mixer.state();
+    let mut updates = vec![
+        LiveUpdate::Mode {
+            mode: hvac.hvac_mode.load(),
+        },
+        LiveUpdate::PulseOverride {
+            state: mixer.override_pulse.get(),
+        },
+        LiveUpdate::OneshotSetpoint {
+            state: mixer.oneshot_setpoint.get(),
+        },
+    ];
+
+    for name in hvac.probes.keys().await {
+        let Some(probe) = hvac.probes.get(&name).await else {
+            continue;
+        };
+        updates.push(LiveUpdate::Temperature {
+            probe: name,
+            value: probe.value(),
+            time: probe.last_update(),
+        });
+    }
+
+    updates
+}
+
+async fn send_update(
+    tx: &mut futures_util::stream::SplitSink<WebSocket, Message>,
+    update: &LiveUpdate,
+) -> Result<(), warp::Error> {
+    let data = serde_json::to_string(update).expect("LiveUpdate always serializes");
+    tx.send(Message::text(data)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hvac::{live::LiveUpdates, mixer::HvacRequest};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn pushes_probe_updates() {
+        let live = LiveUpdates::new();
+        let route = {
+            let live = live.clone();
+            warp::ws().map(move |ws: Ws| {
+                let live = live.clone();
+                ws.on_upgrade(move |socket| {
+                    let snapshot = vec![LiveUpdate::Mode { mode: HvacRequest::Heat }];
+                    forward_updates(socket, snapshot, live.subscribe())
+                })
+            })
+        };
+
+        let mut client = warp::test::ws().handshake(route).await.unwrap();
+        let snapshot = client.recv().await.unwrap();
+        assert_eq!(snapshot.to_str().unwrap(), r#"{"kind":"mode","mode":"heat"}"#);
+
+        live.send(LiveUpdate::Temperature {
+            probe: "primary".into(),
+            value: 21.5,
+            time: 1690000000000,
+        });
+        let update = client.recv().await.unwrap();
+        assert_eq!(
+            update.to_str().unwrap(),
+            r#"{"kind":"temperature","probe":"primary","value":21.5,"time":1690000000000}"#
+        );
+    }
+}
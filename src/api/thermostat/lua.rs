@@ -1,30 +1,63 @@
 use std::collections::BTreeSet;
 
 use futures_util::future;
-use models::hvac_request::HvacRequest;
+use http::StatusCode;
+use models::{
+    hvac_request::HvacRequest,
+    keys::{LUA_CURRENT_NAME, LUA_CURRENT_SCRIPT, LUA_SAVED_SCRIPTS},
+};
 use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 use warp::{filters::BoxedFilter, path, Filter, Rejection, Reply};
 
-use crate::{error::WebErrorExt, hvac::mixer::lua_controller::issues, StatePackage};
+use crate::{
+    api::auth::{with_auth, AUTH_LEVEL_REPROGRAM},
+    error::{json_error, WebErrorExt},
+    hvac::mixer::{
+        lua_controller::{issues, script_log, Explanations},
+        script_schedule::ScriptSchedule,
+    },
+    RedisConn, StatePackage,
+};
 
 #[derive(Clone, Serialize, Deserialize)]
 struct ScriptBody {
     script: String,
 }
 
+#[derive(Deserialize)]
+struct ActivateBody {
+    script: String,
+    /// The saved script this came from. Only kept if it still matches.
+    #[serde(default)]
+    name: Option<String>,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 enum ValidationResponse {
     Error(String),
     Results {
         output: Option<HvacRequest>,
         issues: BTreeSet<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        explained: Option<Explanations>,
+        /// Whatever the script passed to `print`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        printed: Vec<String>,
     },
 }
 
-pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+#[derive(Deserialize)]
+struct ValidateQuery {
+    /// Collect the values the script passes to `explain`
+    #[serde(default)]
+    explain: bool,
+}
+
+/// The scripts saved in `LUA_SAVED_SCRIPTS`, which are only run once activated
+fn saved_scripts(redis: RedisConn) -> BoxedFilter<(impl Reply,)> {
     let scripts = { // GET /api/thermostat/lua/scripts
-        let redis = state.redis.clone();
+        let redis = redis.clone();
         warp::path("scripts")
             .and(path::end())
             .and(warp::get())
@@ -33,7 +66,7 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
                 async move {
                     let keys: Vec<String> = {
                         let mut redis = redis.get();
-                        redis.hkeys("thermostat.lua.saved").await.reject_err()?
+                        redis.hkeys(LUA_SAVED_SCRIPTS).await.reject_err()?
                     };
                     serde_json::to_string(&keys).reject_err()
                 }
@@ -41,7 +74,7 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
     };
 
     let get_script = { // GET /api/thermostat/lua/scripts/<name>
-        let redis = state.redis.clone();
+        let redis = redis.clone();
         warp::path("scripts")
             .and(path::param())
             .and(path::end())
@@ -49,20 +82,23 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
             .and_then(move |name: String| {
                 let redis = redis.clone();
                 async move {
-                    let script: String = {
+                    let script: Option<String> = {
                         let mut redis = redis.get();
                         redis
-                            .hget("thermostat.lua.saved", name)
+                            .hget(LUA_SAVED_SCRIPTS, name)
                             .await
                             .reject_err()?
                     };
+                    let Some(script) = script else {
+                        return Err(warp::reject::not_found());
+                    };
                     serde_json::to_string(&ScriptBody { script }).reject_err()
                 }
             })
     };
 
     let put_script = {
-        let redis = state.redis.clone();
+        let redis = redis.clone();
         warp::path("scripts")
             .and(path::param())
             .and(path::end())
@@ -73,14 +109,75 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
                 async move {
                     let mut redis = redis.get();
                     let () = redis
-                        .hset("thermostat.lua.saved", name, body.script)
+                        .hset(LUA_SAVED_SCRIPTS, name, body.script)
+                        .await
+                        .reject_err()?;
+                    Ok::<_, Rejection>("ok".to_string())
+                }
+            })
+    };
+
+    let delete_script = {
+        let redis = redis.clone();
+        warp::path("scripts")
+            .and(path::param())
+            .and(path::end())
+            .and(warp::delete())
+            .and(with_auth(AUTH_LEVEL_REPROGRAM))
+            .and_then(move |name: String| {
+                let redis = redis.clone();
+                async move {
+                    let mut redis = redis.get();
+                    let removed: u32 = redis
+                        .hdel(LUA_SAVED_SCRIPTS, name)
                         .await
                         .reject_err()?;
+                    if removed == 0 {
+                        return Err(warp::reject::not_found());
+                    }
                     Ok::<_, Rejection>("ok".to_string())
                 }
             })
     };
 
+    scripts.or(get_script).or(put_script).or(delete_script).boxed()
+}
+
+/// Which saved script is active, null once it's been edited or deleted or
+/// when the active script was never saved
+fn active_script_name(redis: RedisConn) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("active_script" / "name")
+        .and(path::end())
+        .and(warp::get())
+        .and_then(move || {
+            let redis = redis.clone();
+            async move {
+                let mut redis = redis.get();
+                let (name, current): (Option<String>, Option<String>) = redis::pipe()
+                    .get(LUA_CURRENT_NAME)
+                    .get(LUA_CURRENT_SCRIPT)
+                    .query_async(&mut redis)
+                    .await
+                    .reject_err()?;
+
+                // The saved copy may have been edited or deleted since
+                let name = match name {
+                    Some(name) => {
+                        let saved: Option<String> =
+                            redis.hget(LUA_SAVED_SCRIPTS, &name).await.reject_err()?;
+                        (saved.is_some() && saved == current).then_some(name)
+                    }
+                    None => None,
+                };
+                serde_json::to_string(&name).reject_err()
+            }
+        })
+        .boxed()
+}
+
+pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    let scripts = saved_scripts(state.redis.clone());
+
     let get_active_script = {
         let redis = state.redis.clone();
         warp::path("active_script")
@@ -89,9 +186,12 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
         .and_then(move || {
             let redis = redis.clone();
             async move {
-                let script: String = {
+                let script: Option<String> = {
                     let mut redis = redis.get();
-                    redis.get("thermostat.lua.current").await.reject_err()?
+                    redis.get(LUA_CURRENT_SCRIPT).await.reject_err()?
+                };
+                let Some(script) = script else {
+                    return Err(warp::reject::not_found());
                 };
                 serde_json::to_string(&ScriptBody { script }).reject_err()
             }
@@ -105,15 +205,92 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
             .and(path::end())
             .and(warp::put())
             .and(warp::body::json())
-            .and_then(move |body: ScriptBody| {
+            .and_then(move |body: ActivateBody| {
                 let redis = redis.clone();
                 let mixer = mixer.clone();
                 async move {
-                    {
-                        let mut redis = redis.get();
-                        let () = redis.set("thermostat.lua.current", &body.script).await.reject_err()?;
+                    // Only saved once it's known to load, so a broken script
+                    // doesn't come back after a restart
+                    let loaded = mixer
+                        .state()
+                        .set_active_lua_script(body.script.clone())
+                        .await;
+                    if let Err(error) = loaded {
+                        return Ok::<_, Rejection>(json_error(
+                            StatusCode::BAD_REQUEST,
+                            "script_rejected",
+                            error.to_string(),
+                        ));
                     }
-                    mixer.state().set_active_lua_script(body.script).await.reject_err()?;
+
+                    let mut redis = redis.get();
+                    let mut pipe = redis::pipe();
+                    pipe.atomic().set(LUA_CURRENT_SCRIPT, &body.script).ignore();
+                    match &body.name {
+                        Some(name) => pipe.set(LUA_CURRENT_NAME, name).ignore(),
+                        None => pipe.del(LUA_CURRENT_NAME).ignore(),
+                    };
+                    let () = pipe.query_async(&mut redis).await.reject_err()?;
+                    Ok("ok".into_response())
+                }
+            })
+    };
+
+    let delete_active_script = {
+        let redis = state.redis.clone();
+        let mixer = state.hvac.mixer.clone();
+        warp::path("active_script")
+            .and(path::end())
+            .and(warp::delete())
+            .and(with_auth(AUTH_LEVEL_REPROGRAM))
+            .and_then(move || {
+                let redis = redis.clone();
+                let mixer = mixer.clone();
+                async move {
+                    // Forgotten first, so a restart can't bring it back
+                    let mut redis = redis.get();
+                    let () = redis::pipe()
+                        .atomic()
+                        .del(LUA_CURRENT_SCRIPT)
+                        .ignore()
+                        .del(LUA_CURRENT_NAME)
+                        .ignore()
+                        .query_async(&mut redis)
+                        .await
+                        .reject_err()?;
+
+                    mixer.state().clear_active_lua_script().await.reject_err()?;
+                    Ok::<_, Rejection>("ok")
+                }
+            })
+    };
+
+    let get_active_name = active_script_name(state.redis.clone());
+
+    let get_schedule = { // GET /api/thermostat/lua/schedule
+        let redis = state.redis.clone();
+        warp::path("schedule")
+            .and(path::end())
+            .and(warp::get())
+            .and_then(move || {
+                let redis = redis.clone();
+                async move {
+                    let schedule = ScriptSchedule::load(&redis).await.reject_err()?;
+                    serde_json::to_string(&schedule).reject_err()
+                }
+            })
+    };
+
+    let put_schedule = { // PUT /api/thermostat/lua/schedule
+        let redis = state.redis.clone();
+        warp::path("schedule")
+            .and(path::end())
+            .and(warp::put())
+            .and(warp::body::json())
+            .and_then(move |schedule: ScriptSchedule| {
+                let redis = redis.clone();
+                async move {
+                    schedule.save(&redis).await.reject_err()?;
                     Ok::<_, Rejection>("ok".to_string())
                 }
             })
@@ -122,14 +299,23 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
     let validate = {
         let mixer = state.hvac.mixer.clone();
         warp::path("validate")
+            .and(warp::query::<ValidateQuery>())
             .and(path::end())
             .and(warp::post())
             .and(warp::body::json())
-            .and_then(move |body: ScriptBody| {
+            .and_then(move |query: ValidateQuery, body: ScriptBody| {
                 let mixer_state = mixer.state();
                 async move {
-                    let response = match mixer_state.validate_lua_script(body.script).await {
-                        Ok((output, issues)) => ValidationResponse::Results { output, issues },
+                    let validation = mixer_state
+                        .validate_lua_script(body.script, query.explain)
+                        .await;
+                    let response = match validation {
+                        Ok(validation) => ValidationResponse::Results {
+                            output: validation.output,
+                            issues: validation.issues,
+                            explained: validation.explained,
+                            printed: validation.printed,
+                        },
                         Err(e) => ValidationResponse::Error(e.to_string()),
                     };
 
@@ -146,12 +332,129 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
             future::ready(serde_json::to_string(&issues).reject_err())
         });
 
+    let logs = warp::path("logs")
+        .and(path::end())
+        .and(warp::get())
+        .and_then(move || {
+            let log = script_log();
+            future::ready(serde_json::to_string(&log).reject_err())
+        });
+
     scripts
-        .or(get_script)
-        .or(put_script)
         .or(get_active_script)
         .or(put_active_script)
+        .or(delete_active_script)
+        .or(get_active_name)
+        .or(get_schedule)
+        .or(put_schedule)
         .or(validate)
         .or(issues)
+        .or(logs)
         .boxed()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::api::auth;
+
+    use super::*;
+
+    #[tokio::test]
+    #[ignore]
+    async fn saved_scripts_are_found_or_404() {
+        let redis = RedisConn::scratch().await;
+        let routes = saved_scripts(redis.clone());
+        let script = "function evaluate(state) return 'off' end";
+        let () = redis
+            .get()
+            .hset(LUA_SAVED_SCRIPTS, "test_present", script)
+            .await
+            .unwrap();
+        let () = redis.get().hdel(LUA_SAVED_SCRIPTS, "test_absent").await.unwrap();
+
+        let found = warp::test::request()
+            .path("/scripts/test_present")
+            .reply(&routes)
+            .await;
+        assert_eq!(found.status(), StatusCode::OK);
+        let body: ScriptBody = serde_json::from_slice(found.body()).unwrap();
+        assert_eq!(body.script, script);
+
+        let missing = warp::test::request()
+            .path("/scripts/test_absent")
+            .reply(&routes)
+            .await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        let () = redis.get().hdel(LUA_SAVED_SCRIPTS, "test_present").await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn deleting_a_saved_script() {
+        let redis = RedisConn::scratch().await;
+        let routes = saved_scripts(redis.clone());
+        let () = redis.get().hset(LUA_SAVED_SCRIPTS, "test_delete", "x = 1").await.unwrap();
+        let delete = |name: &str| {
+            warp::test::request()
+                .method("DELETE")
+                .path(&format!("/scripts/{}", name))
+                .header("X-Auth", auth::test_token("connie", AUTH_LEVEL_REPROGRAM))
+                .reply(&routes)
+        };
+
+        assert_eq!(delete("test_delete").await.status(), StatusCode::OK);
+        let left: bool = redis.get().hexists(LUA_SAVED_SCRIPTS, "test_delete").await.unwrap();
+        assert!(!left);
+        // Gone now, so a second delete has nothing to remove
+        assert_eq!(delete("test_delete").await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn named_and_anonymous_activations() {
+        let redis = RedisConn::scratch().await;
+        let routes = active_script_name(redis.clone());
+        let saved = "function evaluate(state) return 'heat' end";
+        let active_name = || async {
+            let response = warp::test::request()
+                .path("/active_script/name")
+                .reply(&routes)
+                .await;
+            serde_json::from_slice::<Option<String>>(response.body()).unwrap()
+        };
+        let activate = |name: Option<&str>, script: &str| {
+            let mut pipe = redis::pipe();
+            pipe.hset(LUA_SAVED_SCRIPTS, "test_named", saved)
+                .ignore()
+                .set(LUA_CURRENT_SCRIPT, script)
+                .ignore();
+            match name {
+                Some(name) => pipe.set(LUA_CURRENT_NAME, name).ignore(),
+                None => pipe.del(LUA_CURRENT_NAME).ignore(),
+            };
+            let mut redis = redis.get();
+            async move { pipe.query_async::<_, ()>(&mut redis).await.unwrap() }
+        };
+
+        activate(Some("test_named"), saved).await;
+        assert_eq!(active_name().await.as_deref(), Some("test_named"));
+
+        activate(None, saved).await;
+        assert_eq!(active_name().await, None);
+
+        // Edited after activating from the saved copy
+        activate(Some("test_named"), "function evaluate(state) return 'off' end").await;
+        assert_eq!(active_name().await, None);
+
+        let mut redis = redis.get();
+        let () = redis::pipe()
+            .hdel(LUA_SAVED_SCRIPTS, "test_named")
+            .ignore()
+            .del(LUA_CURRENT_NAME)
+            .ignore()
+            .query_async(&mut redis)
+            .await
+            .unwrap();
+    }
+}
@@ -1,28 +1,43 @@
-use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
-};
+use std::{collections::HashMap, future::ready, time::Duration};
 
-use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use chrono::{DateTime, FixedOffset, Utc};
+use http::StatusCode;
+use models::{
+    keys::{CONFIG_MODE_CONFIRM_TIMEOUT, PINSTATE_HISTORY},
+    status::SystemStatus,
+};
 use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 use warp::{
     filters::{path, BoxedFilter},
-    Filter, Reply,
+    Filter, Rejection, Reply,
 };
 
 use crate::{
-    error::WebErrorExt,
-    helpers::extract_redis_history_params,
-    hvac::{mixer::HvacRequest, PINSTATE_HISTORY},
-    StatePackage,
+    api::compression::compressed,
+    error::{json_error_with, WebErrorExt},
+    helpers::extract_history_range,
+    hvac::{
+        mixer::{lua_controller::issues, HvacRequest},
+        MODE_STALE_AFTER,
+    },
+    RedisConn, StatePackage,
 };
 
+/// How long a mode change waits for the unit to echo it back, unless
+/// `CONFIG_MODE_CONFIRM_TIMEOUT` says otherwise
+const DEFAULT_MODE_CONFIRM_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub mod away;
+pub mod comfort_profile;
+pub mod live;
 pub mod lua;
 pub mod oneshot_setpoint;
 pub mod probes;
 pub mod pulse_override;
 pub mod rules;
+pub mod timed_override;
 
 pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
     let oneshot_setpoint =
@@ -31,9 +46,16 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
     let rules = warp::path("rules").and(rules::routes(state).await);
     let pulse_override = warp::path("pulse_override").and(pulse_override::routes(state).await);
     let lua = warp::path("lua").and(lua::routes(state).await);
+    let away = warp::path("away").and(away::routes(state).await);
+    let comfort_profile =
+        warp::path("comfort_profile").and(comfort_profile::routes(state).await);
+    let timed_override = warp::path("timed_override").and(timed_override::routes(state).await);
 
     let pinstate_history = pinstate_history(state);
     let mode = mode(state);
+    let sync_status = sync_status(state);
+    let system_status = system_status(state);
+    let evaluate_trace = evaluate_trace(state);
 
     oneshot_setpoint
         .or(probes)
@@ -41,13 +63,29 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
         .or(pulse_override)
         .or(pinstate_history)
         .or(mode)
+        .or(sync_status)
+        .or(system_status)
+        .or(evaluate_trace)
         .or(lua)
+        .or(away)
+        .or(comfort_profile)
+        .or(timed_override)
         .boxed()
 }
 
+/// The pushed updates, which browsers open with `WebSocket` and `EventSource`
+/// and so can't send `X-Auth`. Mounted behind `auth::with_stream_auth`
+/// instead of the header check the rest of `routes` sits behind.
+pub async fn stream_routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    let live = warp::path("live").and(live::routes(state).await);
+    let probes = warp::path("probes").and(probes::stream_routes(state).await);
+
+    live.or(probes).boxed()
+}
+
 fn pinstate_history(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
     let redis = state.redis.clone();
-    warp::path("pinstate")
+    let history = warp::path("pinstate")
         .and(warp::path("history"))
         .and(warp::query::<HashMap<String, String>>())
         .and(path::end())
@@ -55,9 +93,13 @@ fn pinstate_history(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
         .and_then(move |query| {
             let redis = redis.clone();
             async move {
-                let (start, stop, offset) = extract_redis_history_params(&query).await?;
-
                 let mut redis = redis.get();
+                let (start, stop, offset) =
+                    extract_history_range(&mut redis, PINSTATE_HISTORY, &query, |entry| {
+                        entry.split(':').nth(1)?.parse().ok()
+                    })
+                    .await?;
+
                 let history: Vec<String> = redis
                     .lrange(PINSTATE_HISTORY, start, stop)
                     .await
@@ -78,13 +120,8 @@ fn pinstate_history(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
                             .and_then(|s| HvacRequest::from_payload(s.as_bytes()))?;
                         let time_i = split.next().and_then(|s| i64::from_str_radix(s, 10).ok())?;
                         Some(HistoryEntry {
-                            time: DateTime::from_utc(
-                                NaiveDateTime::from_timestamp_opt(
-                                    time_i / 1000,
-                                    (time_i % 1000) as u32 * 1_000_000,
-                                )?,
-                                offset,
-                            ),
+                            time: DateTime::from_timestamp_millis(time_i)?
+                                .with_timezone(&offset),
                             state,
                         })
                     })
@@ -92,6 +129,76 @@ fn pinstate_history(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
 
                 serde_json::to_string(&history).reject_err()
             }
+        });
+
+    compressed(history)
+}
+
+fn sync_status(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    let sync = state.hvac.sync.clone();
+    warp::path("sync_status")
+        .and(path::end())
+        .and(warp::get())
+        .and_then(move || {
+            let status = sync.status();
+            ready(serde_json::to_string(&status).reject_err())
+        })
+        .boxed()
+}
+
+/// What `query` would decide right now and why, without recording anything
+fn evaluate_trace(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    let hvac = state.hvac.clone();
+    warp::path!("debug" / "evaluate")
+        .and(path::end())
+        .and(warp::get())
+        .and_then(move || {
+            let hvac = hvac.clone();
+            async move {
+                let trace = hvac.mixer.state().trace().await;
+                serde_json::to_string(&trace).reject_err()
+            }
+        })
+        .boxed()
+}
+
+fn system_status(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    let hvac = state.hvac.clone();
+    let redis = state.redis.clone();
+    warp::path("status")
+        .and(path::end())
+        .and(warp::get())
+        .and_then(move || {
+            let hvac = hvac.clone();
+            let redis = redis.clone();
+            async move {
+                let redis_ok = {
+                    let mut redis = redis.get();
+                    redis::cmd("PING")
+                        .query_async::<_, String>(&mut redis)
+                        .await
+                        .is_ok()
+                };
+
+                // A probe that has never reported is still NaN
+                let probe_age_secs = hvac
+                    .probes
+                    .primary()
+                    .await
+                    .map(|probe| (Utc::now().timestamp_millis() - probe.last_update()) / 1000);
+
+                let mixer = hvac.mixer.state();
+                let status = SystemStatus {
+                    redis_ok,
+                    mqtt_online: hvac.mode_last_seen.within(MODE_STALE_AFTER),
+                    probe_age_secs,
+                    script_loaded: mixer.lua.is_loaded().await,
+                    script_issues: issues().len(),
+                    override_pulse_active: mixer.override_pulse.active().is_some(),
+                    oneshot_setpoint_active: mixer.oneshot_setpoint.get().is_some(),
+                };
+                serde_json::to_string(&status).reject_err()
+            }
         })
         .boxed()
 }
@@ -101,36 +208,127 @@ struct HvacModeState {
     mode: HvacRequest,
 }
 
+#[derive(Serialize)]
+struct ModeUnconfirmed {
+    /// The last mode the unit reported
+    mode: HvacRequest,
+}
+
+async fn mode_confirm_timeout(redis: &RedisConn) -> Duration {
+    let mut redis = redis.get();
+    redis
+        .get::<_, Option<u64>>(CONFIG_MODE_CONFIRM_TIMEOUT)
+        .await
+        .ok()
+        .flatten()
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_MODE_CONFIRM_TIMEOUT)
+}
+
+#[derive(Serialize)]
+struct HvacModeStatus {
+    mode: HvacRequest,
+    last_seen: Option<DateTime<Utc>>,
+    /// False when the unit hasn't reported its mode for a few poll intervals
+    online: bool,
+}
+
 fn mode(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
     let mode = state.hvac.hvac_mode.clone();
+    let last_seen = state.hvac.mode_last_seen.clone();
     let get = warp::get().and_then(move || {
-        let mode = mode.clone();
-        async move { serde_json::to_string(&HvacModeState { mode: mode.load() }).reject_err() }
+        let status = HvacModeStatus {
+            mode: mode.load(),
+            last_seen: last_seen.get(),
+            online: last_seen.within(MODE_STALE_AFTER),
+        };
+        ready(serde_json::to_string(&status).reject_err())
     });
 
     let mode = state.hvac.hvac_mode.clone();
+    let mode_changes = state.hvac.mode_changes.clone();
     let mqtt = state.mqtt.clone();
+    let redis = state.redis.clone();
     let set = warp::put()
         .and(warp::body::json::<HvacModeState>())
         .and_then(move |new_state: HvacModeState| {
             let mode = mode.clone();
+            let mut mode_changes = mode_changes.clone();
             let mqtt = mqtt.clone();
+            let redis = redis.clone();
             async move {
+                let timeout = mode_confirm_timeout(&redis).await;
+
+                mode_changes.borrow_and_update();
                 mqtt.publish("home/thermostat/hvac/mode/set", new_state.mode.payload())
                     .await;
-                let begin = Instant::now();
-                const MAX_TIME: Duration = Duration::from_secs(5);
-                while mode.load() != new_state.mode {
-                    tokio::time::sleep(Duration::from_millis(100)).await;
-
-                    if Instant::now() - begin > MAX_TIME {
-                        return Err(anyhow::anyhow!("timeout")).reject_err();
-                    }
+
+                if !wait_for_mode(&mut mode_changes, new_state.mode, timeout).await {
+                    return Ok(json_error_with(
+                        StatusCode::GATEWAY_TIMEOUT,
+                        "mode_unconfirmed",
+                        "Timed out waiting for the thermostat to confirm the mode",
+                        ModeUnconfirmed { mode: mode.load() },
+                    ));
                 }
 
-                serde_json::to_string(&new_state).reject_err()
+                let body = serde_json::to_string(&new_state).reject_err()?;
+                Ok::<_, Rejection>(body.into_response())
             }
         });
 
     warp::path("mode").and(path::end()).and(get.or(set)).boxed()
 }
+
+/// Wakes as soon as the thermostat echoes `wanted`, false if it doesn't within
+/// `timeout`
+async fn wait_for_mode(
+    mode_changes: &mut watch::Receiver<HvacRequest>,
+    wanted: HvacRequest,
+    timeout: Duration,
+) -> bool {
+    let confirm = async {
+        loop {
+            if *mode_changes.borrow_and_update() == wanted {
+                return true;
+            }
+            if mode_changes.changed().await.is_err() {
+                return false;
+            }
+        }
+    };
+    tokio::time::timeout(timeout, confirm)
+        .await
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn confirmation_wakes_the_request() {
+        let (mode_tx, mut mode_changes) = watch::channel(HvacRequest::Off);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            mode_tx.send(HvacRequest::Heat).unwrap();
+            // Keep the channel open so only the confirmation can end the wait
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        let start = std::time::Instant::now();
+        let timeout = Duration::from_secs(30);
+        assert!(wait_for_mode(&mut mode_changes, HvacRequest::Heat, timeout).await);
+        assert!(start.elapsed() < timeout);
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_mode_times_out() {
+        let (mode_tx, mut mode_changes) = watch::channel(HvacRequest::Off);
+        mode_tx.send(HvacRequest::Cool).unwrap();
+
+        let timeout = Duration::from_millis(50);
+        assert!(!wait_for_mode(&mut mode_changes, HvacRequest::Heat, timeout).await);
+        drop(mode_tx);
+    }
+}
@@ -1,33 +1,135 @@
 use std::future::ready;
 
+use http::StatusCode;
 use warp::{filters::BoxedFilter, path, Filter, Rejection, Reply};
 
 use crate::{
-    error::WebErrorExt, hvac::mixer::oneshot_setpoint::OneshotSetpointState, StatePackage,
+    error::{json_error_with, WebErrorExt},
+    helpers::MissingOrInvalidParameter,
+    hvac::{
+        live::LiveUpdate,
+        mixer::{
+            oneshot_setpoint::{OneshotBounds, OneshotSetpointState},
+            Mixer,
+        },
+        Probes,
+    },
+    RedisConn, StatePackage,
 };
 
 pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    oneshot_routes(
+        state.hvac.mixer.clone(),
+        state.hvac.probes.clone(),
+        state.redis.clone(),
+    )
+}
+
+fn oneshot_routes(mixer: Mixer, probes: Probes, redis: RedisConn) -> BoxedFilter<(impl Reply,)> {
     let index = {
-        let hvac = state.hvac.clone();
+        let mixer = mixer.clone();
         path::end().and(warp::get()).and_then(move || {
-            let state = hvac.mixer.state().oneshot_setpoint.get();
+            let state = mixer.state().oneshot_setpoint.get();
             ready(serde_json::to_string(&state).reject_err())
         })
     };
 
     let put = {
-        let hvac = state.hvac.clone();
         path::end()
             .and(warp::put())
             .and(warp::body::json::<Option<OneshotSetpointState>>())
-            .and_then(move |new_state| {
-                let state = hvac.mixer.state();
+            .and_then(move |new_state: Option<OneshotSetpointState>| {
+                let state = mixer.state();
+                let probes = probes.clone();
+                let redis = redis.clone();
                 async move {
-                    state.oneshot_setpoint.set(new_state);
-                    Ok::<_, Rejection>("ok".to_string())
+                    if let Some(new_state) = &new_state {
+                        if probes.get(&new_state.probe).await.is_none() {
+                            return Err(warp::reject::custom(MissingOrInvalidParameter("probe")));
+                        }
+
+                        let bounds = OneshotBounds::load(&redis).await;
+                        if !bounds.contains(new_state.setpoint) {
+                            let detail = format!(
+                                "Setpoint {} is outside {} to {}",
+                                new_state.setpoint, bounds.min, bounds.max
+                            );
+                            return Ok(json_error_with(
+                                StatusCode::BAD_REQUEST,
+                                "setpoint_out_of_bounds",
+                                detail,
+                                bounds,
+                            ));
+                        }
+                    }
+
+                    state.oneshot_setpoint.set(new_state.clone());
+                    state.live.send(LiveUpdate::OneshotSetpoint { state: new_state });
+                    Ok::<_, Rejection>("ok".into_response())
                 }
             })
     };
 
     index.or(put).boxed()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use models::{keys::ONESHOT_BOUNDS_KEY, PRIMARY_PROBE};
+    use redis::AsyncCommands;
+
+    use super::*;
+    use crate::{
+        api::atticfan::FanState,
+        hvac::{
+            live::LiveUpdates,
+            mixer::{AtomicHvacRequest, MixerState},
+        },
+        mqtt::MqttClient,
+    };
+
+    #[tokio::test]
+    #[ignore]
+    async fn put_checks_the_probe_and_bounds() {
+        let redis = RedisConn::scratch().await;
+        // Back to the default 18 to 25
+        let () = redis.get().del(ONESHOT_BOUNDS_KEY).await.unwrap();
+        let probes = Probes::unfed(&[PRIMARY_PROBE]).await;
+        let mixer = MixerState::new(
+            &redis,
+            &MqttClient::loopback(false),
+            probes.clone(),
+            Arc::new(AtomicHvacRequest::new()),
+            FanState::default(),
+            LiveUpdates::new(),
+        )
+        .await;
+        let mixer = Mixer::new(mixer);
+        let routes = oneshot_routes(mixer.clone(), probes, redis);
+        let put = |probe: &str, setpoint: f32| {
+            warp::test::request().method("PUT").path("/").json(&serde_json::json!({
+                "setpoint": setpoint,
+                "comparison": "greater",
+                "action": "heat",
+                "probe": probe,
+            }))
+        };
+
+        let response = put(PRIMARY_PROBE, 40.0).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"], "setpoint_out_of_bounds");
+        assert!(mixer.state().oneshot_setpoint.get().is_none());
+
+        let rejection = put("missing", 21.0).filter(&routes).await.err().unwrap();
+        let missing = rejection.find::<MissingOrInvalidParameter>().unwrap();
+        assert_eq!(missing.0, "probe");
+
+        let response = put(PRIMARY_PROBE, 21.0).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let set = mixer.state().oneshot_setpoint.get().unwrap();
+        assert_eq!(set.setpoint, 21.0);
+    }
+}
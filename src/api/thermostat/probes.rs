@@ -1,17 +1,30 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, convert::Infallible, str::FromStr};
 
-use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use chrono::{DateTime, FixedOffset, Utc};
+use futures_util::{stream, Stream};
+use models::{
+    keys,
+    units::{convert_temp, TempUnits},
+};
+use http::StatusCode;
 use redis::AsyncCommands;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
 use warp::{
-    filters::{path, BoxedFilter},
+    filters::{path, sse, BoxedFilter},
     Filter, Rejection, Reply,
 };
 
 use crate::{
-    error::WebErrorExt,
-    helpers::extract_redis_history_params,
-    hvac::{PROBE_HISTORY}, StatePackage,
+    api::{
+        auth::{with_auth, AUTH_LEVEL_REPROGRAM},
+        compression::compressed,
+    },
+    error::{json_error, WebErrorExt},
+    helpers::{extract_history_range, first_index_older_than, MissingOrInvalidParameter},
+    hvac::{live::LiveUpdate, valid_probe_name, validate_endpoint, Probes, PRIMARY_PROBE},
+    mqtt::MqttClient,
+    RedisConn, StatePackage,
 };
 
 pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
@@ -23,23 +36,7 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
         })
     };
 
-    let temperature = {
-        let probes = state.hvac.probes.clone();
-        warp::path!(String / "temperature")
-            .and(path::end())
-            .and(warp::get())
-            .and_then(move |probe: String| {
-                let probes = probes.clone();
-                async move {
-                    let probe = probes
-                        .get(&probe)
-                        .await
-                        .ok_or_else(|| warp::reject::not_found())?;
-
-                    Ok::<_, Rejection>(probe.value().to_string())
-                }
-            })
-    };
+    let temperature = temperature(state.hvac.probes.clone());
 
     let history = {
         let redis = state.redis.clone();
@@ -50,35 +47,53 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
             .and_then(move |probe: String, query| {
                 let redis = redis.clone();
                 async move {
-                    let (start, stop, offset) = extract_redis_history_params(&query).await?;
-
+                    let units = extract_units(&query)?;
+                    let history_key = keys::probe_history(&probe);
                     let mut redis = redis.get();
+                    let (start, stop, offset) =
+                        extract_history_range(&mut redis, &history_key, &query, |entry| {
+                            entry.split(':').next()?.parse().ok()
+                        })
+                        .await?;
+
                     let history: Vec<String> = redis
-                        .lrange(format!("{PROBE_HISTORY}:{probe}"), start, stop)
+                        .lrange(&history_key, start, stop)
                         .await
                         .reject_err()?;
 
+                    let entries = history_entries(&history, units);
+
+                    if let Some(bucket) = query.get("bucket") {
+                        let bucket = i64::from_str(bucket)
+                            .ok()
+                            .filter(|&bucket| bucket > 0)
+                            .ok_or_else(|| {
+                                warp::reject::custom(MissingOrInvalidParameter("bucket"))
+                            })?;
+                        let history: Vec<_> = bucket_entries(entries, bucket * 1000)
+                            .into_iter()
+                            .filter_map(|bucket| {
+                                Some(BucketEntry {
+                                    time: utc_millis(bucket.start, offset)?,
+                                    min: bucket.min,
+                                    avg: bucket.sum / bucket.count as f64,
+                                    max: bucket.max,
+                                })
+                            })
+                            .collect();
+                        return serde_json::to_string(&history).reject_err();
+                    }
+
                     #[derive(Serialize)]
                     struct HistoryEntry {
                         time: DateTime<FixedOffset>,
                         temp: f64,
                     }
 
-                    let history: Vec<_> = history
-                        .into_iter()
-                        .filter_map(|s| {
-                            let mut split = s.split(':');
-                            let time_i =
-                                split.next().and_then(|s| i64::from_str_radix(s, 10).ok())?;
-                            let temp = split.next().and_then(|s| f64::from_str(s).ok())?;
+                    let history: Vec<_> = entries
+                        .filter_map(|(time_i, temp)| {
                             Some(HistoryEntry {
-                                time: DateTime::from_utc(
-                                    NaiveDateTime::from_timestamp_opt(
-                                        time_i / 1000,
-                                        (time_i % 1000) as u32 * 1_000_000,
-                                    )?,
-                                    offset,
-                                ),
+                                time: utc_millis(time_i, offset)?,
                                 temp,
                             })
                         })
@@ -89,5 +104,589 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
             })
     };
 
-    index.or(temperature).or(history).boxed()
+    let stats = {
+        let probes = state.hvac.probes.clone();
+        let redis = state.redis.clone();
+        warp::path!(String / "stats")
+            .and(warp::query::<HashMap<String, String>>())
+            .and(path::end())
+            .and(warp::get())
+            .and_then(move |name: String, query: HashMap<String, String>| {
+                let probes = probes.clone();
+                let redis = redis.clone();
+                async move {
+                    let units = extract_units(&query)?;
+                    let hours = match query.get("hours") {
+                        Some(hours) => f64::from_str(hours)
+                            .ok()
+                            .filter(|&hours| hours.is_finite() && hours > 0.0)
+                            .ok_or_else(|| {
+                                warp::reject::custom(MissingOrInvalidParameter("hours"))
+                            })?,
+                        None => DEFAULT_STATS_HOURS,
+                    };
+                    let probe = probes.get(&name).await.ok_or_else(warp::reject::not_found)?;
+
+                    let history_key = keys::probe_history(&name);
+                    let cutoff = Utc::now().timestamp_millis() - (hours * 3_600_000.0) as i64;
+                    let mut redis = redis.get();
+                    let end = first_index_older_than(&mut redis, &history_key, history_time, cutoff)
+                        .await?;
+                    let history: Vec<String> = if end > 0 {
+                        redis
+                            .lrange(&history_key, 0, end - 1)
+                            .await
+                            .reject_err()?
+                    } else {
+                        Vec::new()
+                    };
+
+                    let mut stats = summarize(&history, units);
+                    let current = probe.value() as f64;
+                    stats.current = current.is_finite().then(|| convert_temp(current, units));
+
+                    serde_json::to_string(&stats).reject_err()
+                }
+            })
+    };
+
+    let rename = rename(
+        state.hvac.probes.clone(),
+        state.redis.clone(),
+        state.mqtt.clone(),
+    );
+
+    let config = config_routes(
+        state.hvac.probes.clone(),
+        state.redis.clone(),
+        state.mqtt.clone(),
+    );
+
+    index
+        .or(config)
+        .or(temperature)
+        .or(compressed(history))
+        .or(stats)
+        .or(rename)
+        .boxed()
+}
+
+/// `<name>/stream`, mounted apart from `routes` since an `EventSource` has to
+/// authenticate with `auth::with_stream_auth`
+pub async fn stream_routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    let probes = state.hvac.probes.clone();
+    let live = state.hvac.live.clone();
+    warp::path!(String / "stream")
+        .and(path::end())
+        .and(warp::get())
+        .and_then(move |probe: String| {
+            let probes = probes.clone();
+            let updates = live.subscribe();
+            async move {
+                if probes.get(&probe).await.is_none() {
+                    return Err(warp::reject::not_found());
+                }
+
+                let events = probe_events(probe, updates);
+
+                Ok::<_, Rejection>(sse::reply(sse::keep_alive().stream(events)))
+            }
+        })
+        .boxed()
+}
+
+/// The primary probe's temperature falls back to another probe while it has
+/// no reading, a 503 means no probe has one
+fn temperature(probes: Probes) -> BoxedFilter<(impl Reply,)> {
+    warp::path!(String / "temperature")
+        .and(warp::query::<HashMap<String, String>>())
+        .and(path::end())
+        .and(warp::get())
+        .and_then(move |probe: String, query: HashMap<String, String>| {
+            let probes = probes.clone();
+            async move {
+                let units = extract_units(&query)?;
+                let probe = if probe == PRIMARY_PROBE {
+                    // Falls back like the mixer does, see `Probes::primary`
+                    probes.primary().await
+                } else {
+                    let probe = probes.get(&probe).await.ok_or_else(warp::reject::not_found)?;
+                    Some(probe).filter(|probe| !probe.value().is_nan())
+                };
+                let Some(probe) = probe else {
+                    return Ok(json_error(StatusCode::SERVICE_UNAVAILABLE, "no_reading", NO_READING));
+                };
+
+                let value = convert_temp(probe.value() as f64, units) as f32;
+                Ok::<_, Rejection>(value.to_string().into_response())
+            }
+        })
+        .boxed()
+}
+
+/// `<name>/rename`, moving the probe's config and history to a new name
+fn rename(probes: Probes, redis: RedisConn, mqtt: MqttClient) -> BoxedFilter<(impl Reply,)> {
+    warp::path!(String / "rename")
+        .and(path::end())
+        .and(warp::put())
+        .and(with_auth(AUTH_LEVEL_REPROGRAM))
+        .and(warp::body::json::<RenameBody>())
+        .and_then(move |old: String, body: RenameBody| {
+            let probes = probes.clone();
+            let redis = redis.clone();
+            let mqtt = mqtt.clone();
+            async move {
+                if probes.get(&old).await.is_none() {
+                    return Err(warp::reject::not_found());
+                }
+                // `valid_probe_name` turns away the primary probe's name too, so
+                // `rename_source` never gets to refuse it with a 500
+                if old == PRIMARY_PROBE || !valid_probe_name(&body.name) {
+                    return Err(warp::reject::custom(MissingOrInvalidParameter("name")));
+                }
+                if probes.get(&body.name).await.is_some() {
+                    return Ok(json_error(StatusCode::CONFLICT, "name_taken", NAME_TAKEN));
+                }
+
+                let renamed = probes
+                    .rename_probe(&redis, &mqtt, &old, &body.name)
+                    .await
+                    .reject_err()?;
+                if !renamed {
+                    return Ok(json_error(StatusCode::CONFLICT, "history_taken", HISTORY_TAKEN));
+                }
+                Ok("ok".into_response())
+            }
+        })
+        .boxed()
+}
+
+/// Listing, adding and removing probes, the new ones are subscribed right away
+fn config_routes(probes: Probes, redis: RedisConn, mqtt: MqttClient) -> BoxedFilter<(impl Reply,)> {
+    let config = {
+        let probes = probes.clone();
+        warp::path("config")
+            .and(path::end())
+            .and(warp::get())
+            .and(with_auth(AUTH_LEVEL_REPROGRAM))
+            .and_then(move || {
+                let probes = probes.clone();
+                async move { serde_json::to_string(&probes.endpoints().await).reject_err() }
+            })
+    };
+
+    let create = {
+        let probes = probes.clone();
+        let redis = redis.clone();
+        let mqtt = mqtt.clone();
+        warp::path("config")
+            .and(path::end())
+            .and(warp::post())
+            .and(with_auth(AUTH_LEVEL_REPROGRAM))
+            .and(warp::body::json::<CreateBody>())
+            .and_then(move |body: CreateBody| {
+                let probes = probes.clone();
+                let redis = redis.clone();
+                let mqtt = mqtt.clone();
+                async move {
+                    if !valid_probe_name(&body.name) {
+                        return Err(warp::reject::custom(MissingOrInvalidParameter("name")));
+                    }
+                    if let Err(problem) = validate_endpoint(&body.endpoint) {
+                        return Ok(json_error(StatusCode::BAD_REQUEST, "invalid_endpoint", problem));
+                    }
+                    if probes.get(&body.name).await.is_some() {
+                        return Ok(json_error(StatusCode::CONFLICT, "name_taken", NAME_TAKEN));
+                    }
+
+                    probes
+                        .create_probe(&redis, &mqtt, &body.name, &body.endpoint)
+                        .await
+                        .reject_err()?;
+                    Ok("ok".into_response())
+                }
+            })
+    };
+
+    let delete = {
+        let probes = probes.clone();
+        let redis = redis.clone();
+        let mqtt = mqtt.clone();
+        warp::path!(String / "config")
+            .and(path::end())
+            .and(warp::delete())
+            .and(with_auth(AUTH_LEVEL_REPROGRAM))
+            .and_then(move |name: String| {
+                let probes = probes.clone();
+                let redis = redis.clone();
+                let mqtt = mqtt.clone();
+                async move {
+                    if name == PRIMARY_PROBE {
+                        return Err(warp::reject::custom(MissingOrInvalidParameter("name")));
+                    }
+                    if probes.get(&name).await.is_none() {
+                        return Err(warp::reject::not_found());
+                    }
+
+                    probes
+                        .delete_probe(&redis, &mqtt, &name)
+                        .await
+                        .reject_err()?;
+                    Ok::<_, Rejection>("ok".to_string())
+                }
+            })
+    };
+
+    config.or(create).or(delete).boxed()
+}
+
+/// How far back `/probes/<name>/stats` looks without an `hours` parameter
+const DEFAULT_STATS_HOURS: f64 = 24.0;
+
+/// Summary of a probe's recent history. Everything but `count` is null when
+/// there is nothing to summarize.
+#[derive(Default, Serialize)]
+struct ProbeStats {
+    current: Option<f64>,
+    min: Option<f64>,
+    max: Option<f64>,
+    mean: Option<f64>,
+    count: usize,
+}
+
+/// Aggregate `time:value` history entries, skipping anything unparseable or
+/// NaN. `current` is left for the caller.
+fn summarize(history: &[String], units: TempUnits) -> ProbeStats {
+    let mut stats = ProbeStats::default();
+    let mut sum = 0.0;
+    for temp in history
+        .iter()
+        .filter_map(|entry| f64::from_str(entry.split_once(':')?.1).ok())
+        .filter(|temp| temp.is_finite())
+        .map(|temp| convert_temp(temp, units))
+    {
+        stats.min = Some(stats.min.map_or(temp, |min: f64| min.min(temp)));
+        stats.max = Some(stats.max.map_or(temp, |max: f64| max.max(temp)));
+        sum += temp;
+        stats.count += 1;
+    }
+    if stats.count > 0 {
+        stats.mean = Some(sum / stats.count as f64);
+    }
+    stats
+}
+
+/// `(time, temp)` for every entry that parses, converted to `units`
+fn history_entries(
+    history: &[String],
+    units: TempUnits,
+) -> impl Iterator<Item = (i64, f64)> + '_ {
+    history.iter().filter_map(move |s| {
+        let mut split = s.split(':');
+        let time_i = split.next().and_then(|s| i64::from_str(s).ok())?;
+        let temp = split.next().and_then(|s| f64::from_str(s).ok())?;
+        Some((time_i, convert_temp(temp, units)))
+    })
+}
+
+fn history_time(entry: &str) -> Option<i64> {
+    entry.split(':').next()?.parse().ok()
+}
+
+/// The probe's new name
+#[derive(Deserialize)]
+struct RenameBody {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct CreateBody {
+    name: String,
+    /// An MQTT topic or an HTTP URL
+    endpoint: String,
+}
+
+const NAME_TAKEN: &str = "A probe with that name already exists";
+const HISTORY_TAKEN: &str = "A deleted probe's history still uses that name";
+const NO_READING: &str = "No probe has a reading yet";
+
+
+
+/// `units=f` for Fahrenheit, Celsius otherwise
+fn extract_units(query: &HashMap<String, String>) -> Result<TempUnits, Rejection> {
+    match query.get("units") {
+        Some(units) => TempUnits::from_str(units)
+            .map_err(|_| warp::reject::custom(MissingOrInvalidParameter("units"))),
+        None => Ok(TempUnits::Celsius),
+    }
+}
+
+/// The `temperature` events for one probe out of the live updates
+fn probe_events(
+    probe: String,
+    updates: broadcast::Receiver<LiveUpdate>,
+) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+    stream::unfold(updates, move |mut updates| {
+        let probe = probe.clone();
+        async move {
+            loop {
+                match updates.recv().await {
+                    Ok(LiveUpdate::Temperature {
+                        probe: name,
+                        value,
+                        time,
+                    }) if name == probe => {
+                        let event = sse::Event::default()
+                            .event("temperature")
+                            .json_data(ProbeEvent { time, temp: value });
+                        let Ok(event) = event else {
+                            continue;
+                        };
+                        return Some((Ok(event), updates));
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    })
+}
+
+/// One `temperature` event on `/probes/<name>/stream`
+#[derive(Serialize)]
+struct ProbeEvent {
+    /// Milliseconds since the epoch
+    time: i64,
+    temp: f32,
+}
+
+#[derive(Serialize)]
+struct BucketEntry {
+    /// The start of the bucket
+    time: DateTime<FixedOffset>,
+    min: f64,
+    avg: f64,
+    max: f64,
+}
+
+struct Bucket {
+    start: i64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: usize,
+}
+
+/// Group `(time_ms, temp)` entries into buckets `bucket_ms` wide, aligned to
+/// the epoch. Entries come out of redis newest first, and so do the buckets.
+fn bucket_entries(entries: impl Iterator<Item = (i64, f64)>, bucket_ms: i64) -> Vec<Bucket> {
+    let mut buckets: Vec<Bucket> = Vec::new();
+    for (time, temp) in entries {
+        let start = time - time.rem_euclid(bucket_ms);
+        match buckets.last_mut() {
+            Some(bucket) if bucket.start == start => {
+                bucket.min = bucket.min.min(temp);
+                bucket.max = bucket.max.max(temp);
+                bucket.sum += temp;
+                bucket.count += 1;
+            }
+            _ => buckets.push(Bucket {
+                start,
+                min: temp,
+                max: temp,
+                sum: temp,
+                count: 1,
+            }),
+        }
+    }
+    buckets
+}
+
+fn utc_millis(time: i64, offset: FixedOffset) -> Option<DateTime<FixedOffset>> {
+    Some(DateTime::from_timestamp_millis(time)?.with_timezone(&offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use crate::{api::auth, hvac::live::LiveUpdates};
+
+    use super::*;
+
+    #[test]
+    fn buckets_split_on_their_boundaries() {
+        // Newest first, like the history list
+        let entries = [(20_500, 23.0), (19_999, 22.0), (10_000, 20.0), (12_000, 21.0)];
+        let buckets = bucket_entries(entries.iter().copied(), 10_000);
+
+        let summary: Vec<_> = buckets
+            .iter()
+            .map(|b| (b.start, b.min, b.max, b.sum / b.count as f64))
+            .collect();
+        assert_eq!(summary, [(20_000, 23.0, 23.0, 23.0), (10_000, 20.0, 22.0, 21.0)]);
+    }
+
+    #[test]
+    fn buckets_align_before_the_epoch() {
+        let buckets = bucket_entries(std::iter::once((-1, 20.0)), 10_000);
+        assert_eq!(buckets[0].start, -10_000);
+    }
+
+    #[test]
+    fn history_is_converted_to_the_requested_units() {
+        let history = vec!["2000:100".to_string(), "1000:-40".to_string()];
+        let celsius: Vec<_> = history_entries(&history, TempUnits::Celsius).collect();
+        assert_eq!(celsius, [(2000, 100.0), (1000, -40.0)]);
+        let fahrenheit: Vec<_> = history_entries(&history, TempUnits::Fahrenheit).collect();
+        assert_eq!(fahrenheit, [(2000, 212.0), (1000, -40.0)]);
+    }
+
+    #[test]
+    fn units_default_to_celsius() {
+        let query = |units: &str| HashMap::from([("units".to_string(), units.to_string())]);
+        assert_eq!(extract_units(&HashMap::new()).unwrap(), TempUnits::Celsius);
+        assert_eq!(extract_units(&query("F")).unwrap(), TempUnits::Fahrenheit);
+        assert_eq!(extract_units(&query("c")).unwrap(), TempUnits::Celsius);
+        assert!(extract_units(&query("kelvin")).is_err());
+    }
+
+    #[test]
+    fn summarizes_a_known_series() {
+        let history: Vec<String> = ["4000:22.0", "3000:NaN", "2000:18.0", "garbage", "1000:21.5"]
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect();
+        let stats = summarize(&history, TempUnits::Celsius);
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.min, Some(18.0));
+        assert_eq!(stats.max, Some(22.0));
+        assert_eq!(stats.mean, Some(20.5));
+        assert_eq!(stats.current, None);
+
+        let stats = summarize(&history, TempUnits::Fahrenheit);
+        assert_eq!(stats.min, Some(64.4));
+        assert_eq!(stats.max, Some(71.6));
+    }
+
+    #[test]
+    fn empty_history_has_no_aggregates() {
+        let stats = summarize(&[], TempUnits::Celsius);
+        assert_eq!(stats.count, 0);
+        assert_eq!((stats.min, stats.max, stats.mean), (None, None, None));
+    }
+
+    #[tokio::test]
+    async fn streams_only_the_requested_probe() {
+        let live = LiveUpdates::new();
+        let events = probe_events("attic".into(), live.subscribe());
+        futures_util::pin_mut!(events);
+
+        for (probe, value) in [("primary", 21.0), ("attic", 35.5)] {
+            live.send(LiveUpdate::Temperature {
+                probe: probe.into(),
+                value,
+                time: 1690000000000,
+            });
+        }
+
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(
+            event.to_string(),
+            "event:temperature\ndata:{\"time\":1690000000000,\"temp\":35.5}\n\n"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn config_round_trip() {
+        let redis = RedisConn::scratch().await;
+        let probes = Probes::new(LiveUpdates::new());
+        let routes = config_routes(probes.clone(), redis.clone(), MqttClient::loopback(false));
+        let token = auth::test_token("connie", AUTH_LEVEL_REPROGRAM);
+        let request = |method: &str, path: &str| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .header("X-Auth", &token)
+        };
+        let body = serde_json::json!({ "name": "test_config", "endpoint": "home/test/temp" });
+
+        let created = request("POST", "/config").json(&body).reply(&routes).await;
+        assert_eq!(created.status(), StatusCode::OK);
+        assert!(probes.get("test_config").await.is_some());
+        let taken = request("POST", "/config").json(&body).reply(&routes).await;
+        assert_eq!(taken.status(), StatusCode::CONFLICT);
+
+        let listed = request("GET", "/config").reply(&routes).await;
+        let endpoints: HashMap<String, String> = serde_json::from_slice(listed.body()).unwrap();
+        assert_eq!(endpoints["test_config"], "home/test/temp");
+
+        let deleted = request("DELETE", "/test_config/config").reply(&routes).await;
+        assert_eq!(deleted.status(), StatusCode::OK);
+        assert!(probes.get("test_config").await.is_none());
+        let listed = request("GET", "/config").reply(&routes).await;
+        let endpoints: HashMap<String, String> = serde_json::from_slice(listed.body()).unwrap();
+        assert!(!endpoints.contains_key("test_config"));
+        let endpoint: Option<String> =
+            redis.get().hget(keys::PROBE_ENDPOINTS, "test_config").await.unwrap();
+        assert_eq!(endpoint, None);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn config_needs_reprogram_auth() {
+        let redis = RedisConn::scratch().await;
+        let probes = Probes::new(LiveUpdates::new());
+        let routes = config_routes(probes, redis, MqttClient::loopback(false));
+        let listed = warp::test::request()
+            .path("/config")
+            .header("X-Auth", auth::test_token("guest", auth::AUTH_LEVEL_QUICKACTION))
+            .reply(&routes)
+            .await;
+        assert_ne!(listed.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn renames_involving_the_primary_probe_are_bad_requests() {
+        let redis = RedisConn::scratch().await;
+        let probes = Probes::unfed(&[PRIMARY_PROBE, "bedroom"]).await;
+        let routes = rename(probes, redis, MqttClient::loopback(false));
+        let token = auth::test_token("connie", AUTH_LEVEL_REPROGRAM);
+        let rename = |path: &str, name: &str| {
+            warp::test::request()
+                .method("PUT")
+                .path(path)
+                .header("X-Auth", &token)
+                .json(&serde_json::json!({ "name": name }))
+                .filter(&routes)
+        };
+
+        for (path, name) in [("/bedroom/rename", PRIMARY_PROBE), ("/primary/rename", "loft")] {
+            let rejection = rename(path, name).await.err().unwrap();
+            let invalid = rejection.find::<MissingOrInvalidParameter>().unwrap();
+            assert_eq!(invalid.0, "name");
+        }
+    }
+
+    #[tokio::test]
+    async fn primary_temperature_falls_back_to_another_probe() {
+        let probes = Probes::unfed(&[PRIMARY_PROBE, "attic", "bedroom"]).await;
+        let routes = temperature(probes.clone());
+        let get = |path: &'static str| warp::test::request().path(path).reply(&routes);
+
+        let response = get("/primary/temperature").await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(get("/attic/temperature").await.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        probes.get("bedroom").await.unwrap().update(19.5);
+        let response = get("/primary/temperature").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "19.5");
+
+        probes.get(PRIMARY_PROBE).await.unwrap().update(21.0);
+        assert_eq!(get("/primary/temperature").await.body(), "21");
+        assert_eq!(get("/missing/temperature").await.status(), StatusCode::NOT_FOUND);
+    }
 }
@@ -5,7 +5,11 @@ use warp::{
     Filter, Rejection, Reply,
 };
 
-use crate::{error::WebErrorExt, hvac::mixer::override_pulse::OverridePulseState, StatePackage};
+use crate::{
+    error::WebErrorExt,
+    hvac::{live::LiveUpdate, mixer::override_pulse::OverridePulseState},
+    StatePackage,
+};
 
 pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
     let index = {
@@ -18,13 +22,16 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
 
     let put = {
         let hvac = state.hvac.clone();
+        let redis = state.redis.clone();
         path::end()
             .and(warp::put())
             .and(warp::body::json::<Option<OverridePulseState>>())
             .and_then(move |new_state| {
                 let state = hvac.mixer.state();
+                let redis = redis.clone();
                 async move {
-                    state.override_pulse.set(new_state);
+                    state.override_pulse.set(&redis, new_state).await.reject_err()?;
+                    state.live.send(LiveUpdate::PulseOverride { state: new_state });
                     Ok::<_, Rejection>("ok".to_string())
                 }
             })
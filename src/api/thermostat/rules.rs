@@ -1,18 +1,24 @@
+use std::{collections::HashMap, str::FromStr};
+
+use chrono::{DateTime, Datelike, Local, NaiveTime, Weekday};
+use http::StatusCode;
+use models::keys::{CURRENT_RULESET_KEY, SAVED_RULES};
 use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
 use warp::{
     filters::{path, BoxedFilter},
+    reply::Response,
     Filter, Rejection, Reply,
 };
 
 use crate::{
-    error::WebErrorExt,
-    hvac::mixer::timed_rule::{TimedRuleSet, CURRENT_RULESET_KEY},
-    StatePackage,
+    error::{json_error_with, WebErrorExt},
+    helpers::MissingOrInvalidParameter,
+    hvac::mixer::timed_rule::{DaySet, TimedRuleSet},
+    RedisConn, StatePackage,
 };
 
 pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
-    const SAVED_RULES: &str = "thermostat.config.savedrules";
-
     let current = {
         let hvac = state.hvac.clone();
         warp::path("current")
@@ -49,6 +55,16 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
                 let activate_rule = activate_rule.clone();
                 async move {
                     let mut redis = redis.get();
+                    let saved: Option<String> =
+                        redis.hget(SAVED_RULES, &rule).await.reject_err()?;
+                    let Some(saved) = saved else {
+                        return Err(warp::reject::not_found());
+                    };
+                    let ruleset: TimedRuleSet = serde_json::from_str(&saved).reject_err()?;
+                    if let Err(problems) = ruleset.validate() {
+                        return Ok(invalid_ruleset(problems));
+                    }
+
                     let success: bool = activate_rule
                         .arg(rule)
                         .invoke_async(&mut redis)
@@ -57,7 +73,7 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
 
                     if success {
                         hvac.mixer.reload_timed_rules().await;
-                        Ok("ok".to_string())
+                        Ok("ok".into_response())
                     } else {
                         Err(warp::reject::not_found())
                     }
@@ -68,11 +84,39 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
     let active_rule = {
         let hvac = state.hvac.clone();
         warp::path("active_rule")
+            .and(warp::query::<HashMap<String, String>>())
             .and(warp::get())
-            .and_then(move || {
+            .and_then(move |query: HashMap<String, String>| {
+                let hvac = hvac.clone();
+                async move {
+                    let ruleset = hvac.mixer.state().timed_ruleset.clone();
+                    let rule = match query.get("at") {
+                        // Evaluate in our local time, same as the live rule
+                        Some(at) => {
+                            let at = DateTime::parse_from_rfc3339(at)
+                                .map_err(|_| warp::reject::custom(MissingOrInvalidParameter("at")))?
+                                .with_timezone(&Local);
+                            ruleset.find_applicable_rule_at(at.weekday(), at.time())
+                        }
+                        None => ruleset.find_applicable_rule(),
+                    };
+                    serde_json::to_string(&rule).reject_err()
+                }
+            })
+    };
+
+    let applicable_rule = {
+        let hvac = state.hvac.clone();
+        warp::path("applicable")
+            .and(warp::query::<HashMap<String, String>>())
+            .and(path::end())
+            .and(warp::get())
+            .and_then(move |query: HashMap<String, String>| {
                 let hvac = hvac.clone();
                 async move {
-                    serde_json::to_string(&hvac.mixer.state().timed_ruleset.find_applicable_rule())
+                    let (day, time) = extract_applicable_params(&query)?;
+                    let ruleset = hvac.mixer.state().timed_ruleset.clone();
+                    serde_json::to_string(&ruleset.find_applicable_rule_at(day, time))
                         .reject_err()
                 }
             })
@@ -94,8 +138,54 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
             })
     };
 
-    let get_saved_rule = {
+    let saved_rule = saved_rule(state.redis.clone());
+
+    let apply_to_days = {
         let redis = state.redis.clone();
+        warp::path!("saved_rules" / String / "apply_to_days")
+            .and(path::end())
+            .and(warp::post())
+            .and(warp::body::json::<ApplyToDays>())
+            .and_then(move |name, request: ApplyToDays| {
+                let redis = redis.clone();
+                async move {
+                    let mut redis = redis.get();
+                    let saved: Option<String> =
+                        redis.hget(SAVED_RULES, &name).await.reject_err()?;
+                    let Some(saved) = saved else {
+                        return Err(warp::reject::not_found());
+                    };
+
+                    let mut ruleset: TimedRuleSet = serde_json::from_str(&saved).reject_err()?;
+                    if !ruleset.apply_to_days(request.rule, request.days) {
+                        return Err(warp::reject::custom(MissingOrInvalidParameter("rule")));
+                    }
+                    if let Err(problems) = ruleset.validate() {
+                        return Ok(invalid_ruleset(problems));
+                    }
+
+                    let data = serde_json::to_string(&ruleset).reject_err()?;
+                    let _: () = redis.hset(SAVED_RULES, &name, &data).await.reject_err()?;
+
+                    Ok(data.into_response())
+                }
+            })
+    };
+
+    current
+        .or(set_current)
+        .or(active_rule)
+        .or(applicable_rule)
+        .or(saved_rules)
+        .or(saved_rule)
+        .or(apply_to_days)
+        .boxed()
+}
+
+/// Reading and writing a single ruleset in `SAVED_RULES`
+fn saved_rule(redis: RedisConn) -> BoxedFilter<(impl Reply,)> {
+    let get_saved_rule = {
+        let redis = redis.clone();
         warp::path!("saved_rules" / String)
             .and(path::end())
             .and(warp::get())
@@ -103,37 +193,108 @@ pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
                 let redis = redis.clone();
                 async move {
                     let mut redis = redis.get();
-                    let rule: String = redis.hget(SAVED_RULES, &name).await.reject_err()?;
+                    let rule: Option<String> = redis.hget(SAVED_RULES, &name).await.reject_err()?;
 
-                    Ok::<_, Rejection>(rule)
+                    rule.ok_or_else(warp::reject::not_found)
                 }
             })
     };
 
     let put_saved_rule = {
-        let redis = state.redis.clone();
+        let redis = redis.clone();
         warp::path!("saved_rules" / String)
             .and(path::end())
             .and(warp::put())
             .and(warp::body::json::<TimedRuleSet>())
-            .and_then(move |name, rule| {
+            .and_then(move |name, rule: TimedRuleSet| {
                 let redis = redis.clone();
                 async move {
+                    if let Err(problems) = rule.validate() {
+                        return Ok(invalid_ruleset(problems));
+                    }
+
                     let data = serde_json::to_string(&rule).reject_err()?;
 
                     let mut redis = redis.get();
                     let _: () = redis.hset(SAVED_RULES, &name, &data).await.reject_err()?;
 
-                    Ok::<_, Rejection>("ok".to_string())
+                    Ok::<_, Rejection>("ok".into_response())
                 }
             })
     };
 
-    current
-        .or(set_current)
-        .or(active_rule)
-        .or(saved_rules)
-        .or(get_saved_rule)
-        .or(put_saved_rule)
-        .boxed()
+    get_saved_rule.or(put_saved_rule).boxed()
+}
+
+#[derive(Deserialize)]
+struct ApplyToDays {
+    /// Index into the saved ruleset's rules
+    rule: usize,
+    days: DaySet,
+}
+
+#[derive(Serialize)]
+struct InvalidRuleset {
+    problems: Vec<String>,
+}
+
+fn invalid_ruleset(problems: Vec<String>) -> Response {
+    json_error_with(
+        StatusCode::BAD_REQUEST,
+        "invalid_ruleset",
+        "Invalid ruleset",
+        InvalidRuleset { problems },
+    )
+}
+
+/// `at` is a local time like `14:30` or `14:30:00`, `day` is a weekday like
+/// `mon` or `Monday` and defaults to today
+fn extract_applicable_params(
+    query: &HashMap<String, String>,
+) -> Result<(Weekday, NaiveTime), Rejection> {
+    let time = query
+        .get("at")
+        .and_then(|s| {
+            NaiveTime::parse_from_str(s, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+                .ok()
+        })
+        .ok_or_else(|| warp::reject::custom(MissingOrInvalidParameter("at")))?;
+
+    let day = match query.get("day") {
+        Some(day) => Weekday::from_str(day)
+            .map_err(|_| warp::reject::custom(MissingOrInvalidParameter("day")))?,
+        None => chrono::Local::now().weekday(),
+    };
+
+    Ok((day, time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore]
+    async fn saved_rules_are_found_or_404() {
+        let redis = RedisConn::scratch().await;
+        let routes = saved_rule(redis.clone());
+        let () = redis.get().hset(SAVED_RULES, "test_present", "{}").await.unwrap();
+        let () = redis.get().hdel(SAVED_RULES, "test_absent").await.unwrap();
+
+        let found = warp::test::request()
+            .path("/saved_rules/test_present")
+            .reply(&routes)
+            .await;
+        assert_eq!(found.status(), StatusCode::OK);
+        assert_eq!(found.body(), "{}");
+
+        let missing = warp::test::request()
+            .path("/saved_rules/test_absent")
+            .reply(&routes)
+            .await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        let () = redis.get().hdel(SAVED_RULES, "test_present").await.unwrap();
+    }
 }
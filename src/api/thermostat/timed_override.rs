@@ -0,0 +1,140 @@
+use std::{
+    future::ready,
+    sync::{Arc, RwLock},
+};
+
+use models::thermostatd::TimedOverride;
+use warp::{filters::BoxedFilter, path, Filter, Rejection, Reply};
+
+use crate::{error::WebErrorExt, mqtt::MqttClient, StatePackage};
+
+/// Retained by thermostatd whenever the override changes
+const TIMED_OVERRIDE: &str = "home/thermostatd/timed_override";
+const TIMED_OVERRIDE_GET: &str = "home/thermostatd/timed_override/get";
+const TIMED_OVERRIDE_SET: &str = "home/thermostatd/timed_override/set";
+
+pub async fn routes(state: StatePackage<'_>) -> BoxedFilter<(impl Reply,)> {
+    timed_override_routes(state.mqtt).await
+}
+
+async fn timed_override_routes(mqtt: &MqttClient) -> BoxedFilter<(impl Reply,)> {
+    let current = Arc::new(RwLock::new(None::<TimedOverride>));
+
+    // Follow thermostatd's view of the override
+    {
+        let current = current.clone();
+        mqtt.subscribe(TIMED_OVERRIDE).await;
+        mqtt.handle(TIMED_OVERRIDE, move |_topic, payload| {
+            if let Ok(timed_override) = serde_json::from_slice(payload) {
+                *current.write().unwrap() = timed_override;
+            }
+        })
+        .await;
+        mqtt.publish(TIMED_OVERRIDE_GET, b"").await;
+    }
+
+    let index = {
+        let current = current.clone();
+        path::end().and(warp::get()).and_then(move || {
+            let timed_override = *current.read().unwrap();
+            ready(serde_json::to_string(&timed_override).reject_err())
+        })
+    };
+
+    let put = {
+        let mqtt = mqtt.clone();
+        path::end()
+            .and(warp::put())
+            .and(warp::body::json::<Option<TimedOverride>>())
+            .and_then(move |new_override: Option<TimedOverride>| {
+                let mqtt = mqtt.clone();
+                async move {
+                    let data = serde_json::to_string(&new_override).reject_err()?;
+                    mqtt.publish(TIMED_OVERRIDE_SET, data.as_bytes()).await;
+                    Ok::<_, Rejection>("ok".to_string())
+                }
+            })
+    };
+
+    index.or(put).boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use http::StatusCode;
+
+    use super::*;
+    use crate::hvac::mixer::HvacRequest;
+
+    /// Stands in for thermostatd, retaining whatever it's told to set
+    async fn thermostatd(mqtt: &MqttClient) {
+        let client = mqtt.clone();
+        mqtt.handle(TIMED_OVERRIDE_SET, move |_topic, payload| {
+            let mqtt = client.clone();
+            let payload = payload.to_vec();
+            tokio::spawn(async move { mqtt.publish_retained(TIMED_OVERRIDE, &payload).await });
+        })
+        .await;
+    }
+
+    async fn get(route: &BoxedFilter<(impl Reply + 'static,)>) -> Option<TimedOverride> {
+        let reply = warp::test::request().reply(route).await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        serde_json::from_slice(reply.body()).unwrap()
+    }
+
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    #[tokio::test]
+    async fn get_returns_the_retained_override() {
+        let mqtt = MqttClient::loopback(true);
+        let route = timed_override_routes(&mqtt).await;
+        assert!(get(&route).await.is_none());
+
+        let retained = TimedOverride {
+            command: HvacRequest::Cool,
+            expiration: Utc::now(),
+        };
+        let data = serde_json::to_vec(&retained).unwrap();
+        mqtt.publish_retained(TIMED_OVERRIDE, &data).await;
+        settle().await;
+
+        let current = get(&route).await.unwrap();
+        assert_eq!(current.command, HvacRequest::Cool);
+        assert_eq!(current.expiration, retained.expiration);
+    }
+
+    #[tokio::test]
+    async fn put_round_trips_through_mqtt() {
+        let mqtt = MqttClient::loopback(true);
+        thermostatd(&mqtt).await;
+        let route = timed_override_routes(&mqtt).await;
+
+        let new_override = TimedOverride {
+            command: HvacRequest::Heat,
+            expiration: Utc::now() + chrono::Duration::hours(1),
+        };
+        let reply = warp::test::request()
+            .method("PUT")
+            .json(&Some(new_override))
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        settle().await;
+        assert_eq!(get(&route).await.unwrap().command, HvacRequest::Heat);
+
+        let reply = warp::test::request()
+            .method("PUT")
+            .json(&None::<TimedOverride>)
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        settle().await;
+        assert!(get(&route).await.is_none());
+    }
+}
@@ -1,4 +1,13 @@
-use warp::{Rejection, reject::Reject};
+use http::StatusCode;
+use serde::Serialize;
+use warp::{
+    filters::body::BodyDeserializeError,
+    reject::{InvalidHeader, InvalidQuery, MethodNotAllowed, MissingHeader, Reject},
+    reply::{self, Response},
+    Rejection, Reply,
+};
+
+use crate::helpers::MissingOrInvalidParameter;
 
 pub trait WebErrorExt {
     type Out;
@@ -17,3 +26,151 @@ struct ServerError(anyhow::Error);
 
 impl Reject for ServerError {}
 
+#[derive(Serialize)]
+struct ErrorBody<T = ()> {
+    /// What kind of failure, stable enough to match on
+    error: &'static str,
+    /// Human readable, meant to be shown as is
+    detail: String,
+    #[serde(flatten)]
+    extra: T,
+}
+
+/// An error body shaped like the ones `json_rejection` renders, for routes
+/// that reply with an error themselves
+pub fn json_error(status: StatusCode, error: &'static str, detail: impl Into<String>) -> Response {
+    json_error_with(status, error, detail, ())
+}
+
+/// Same as `json_error`, with the fields of `extra` next to `error` and `detail`
+pub fn json_error_with(
+    status: StatusCode,
+    error: &'static str,
+    detail: impl Into<String>,
+    extra: impl Serialize,
+) -> Response {
+    let body = ErrorBody {
+        error,
+        detail: detail.into(),
+        extra,
+    };
+    reply::with_status(reply::json(&body), status).into_response()
+}
+
+impl ServerError {
+    fn category(&self) -> &'static str {
+        if self.0.downcast_ref::<redis::RedisError>().is_some() {
+            "redis_error"
+        } else {
+            "internal_error"
+        }
+    }
+}
+
+/// Render a rejection nothing else handled as a JSON error body
+pub fn json_rejection(rejection: &Rejection) -> Response {
+    let (status, error, detail) = if let Some(err) = rejection.find::<ServerError>() {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            err.category(),
+            format!("{:#}", err.0),
+        )
+    } else if let Some(err) = rejection.find::<BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, "invalid_body", err.to_string())
+    } else if let Some(err) = rejection.find::<MissingHeader>() {
+        (StatusCode::BAD_REQUEST, "missing_header", err.to_string())
+    } else if let Some(err) = rejection.find::<InvalidHeader>() {
+        (StatusCode::BAD_REQUEST, "invalid_header", err.to_string())
+    } else if let Some(err) = rejection.find::<InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, "invalid_query", err.to_string())
+    } else if let Some(MissingOrInvalidParameter(param)) = rejection.find() {
+        (
+            StatusCode::BAD_REQUEST,
+            "invalid_parameter",
+            format!("Missing or invalid query parameter `{param}`"),
+        )
+    } else if let Some(err) = rejection.find::<MethodNotAllowed>() {
+        (StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", err.to_string())
+    } else if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "not_found", "Not Found".to_string())
+    } else {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unhandled_rejection",
+            format!("Unhandled rejection: {rejection:?}"),
+        )
+    };
+
+    json_error(status, error, detail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::{path, Filter};
+
+    async fn body(resp: Response) -> serde_json::Value {
+        let bytes = warp::hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn redis_error_body() {
+        let error = redis::RedisError::from((redis::ErrorKind::IoError, "connection refused"));
+        let rejection = Err::<(), _>(error).reject_err().unwrap_err();
+
+        let resp = json_rejection(&rejection);
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = body(resp).await;
+        assert_eq!(body["error"], "redis_error");
+        assert!(body["detail"].as_str().unwrap().contains("connection refused"));
+    }
+
+    #[tokio::test]
+    async fn internal_error_through_a_route() {
+        let route = path("broken")
+            .and_then(|| async { Err::<String, _>(anyhow::anyhow!("disk on fire")).reject_err() })
+            .recover(|rejection: Rejection| async move {
+                Ok::<_, std::convert::Infallible>(json_rejection(&rejection))
+            });
+
+        let resp = warp::test::request().path("/broken").reply(&route).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["error"], "internal_error");
+        assert_eq!(body["detail"], "disk on fire");
+    }
+
+    #[tokio::test]
+    async fn not_found_body() {
+        let resp = json_rejection(&warp::reject::not_found());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body(resp).await["error"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn extra_fields_sit_next_to_detail() {
+        #[derive(Serialize)]
+        struct Extra {
+            problems: Vec<&'static str>,
+        }
+
+        let resp = json_error_with(
+            StatusCode::BAD_REQUEST,
+            "invalid_ruleset",
+            "Invalid ruleset",
+            Extra {
+                problems: vec!["ruleset has no rules"],
+            },
+        );
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body(resp).await,
+            serde_json::json!({
+                "error": "invalid_ruleset",
+                "detail": "Invalid ruleset",
+                "problems": ["ruleset has no rules"],
+            })
+        );
+    }
+}
@@ -1,15 +1,18 @@
 use std::{collections::HashMap, str::FromStr};
 
-use chrono::FixedOffset;
+use chrono::{DateTime, FixedOffset};
+use redis::{aio::ConnectionManager, AsyncCommands};
 use warp::{reject::Reject, Rejection};
 
+use crate::error::WebErrorExt;
+
+#[derive(Debug, Copy, Clone)]
+pub struct MissingOrInvalidParameter(pub &'static str);
+impl Reject for MissingOrInvalidParameter {}
+
 pub async fn extract_redis_history_params<'p>(
     query: &HashMap<String, String>,
 ) -> Result<(isize, isize, FixedOffset), Rejection> {
-    #[derive(Debug, Copy, Clone)]
-    struct MissingOrInvalidParameter(&'static str);
-    impl Reject for MissingOrInvalidParameter {}
-
     let start = query
         .get("start")
         .and_then(|s| isize::from_str_radix(s, 10).ok())
@@ -20,14 +23,218 @@ pub async fn extract_redis_history_params<'p>(
         .and_then(|s| isize::from_str_radix(s, 10).ok())
         .ok_or_else(|| warp::reject::custom(MissingOrInvalidParameter("stop")))?;
 
-    let offset = FixedOffset::east_opt(
+    let offset = extract_tz_offset(query)?;
+
+    Ok((start, stop, offset))
+}
+
+/// Like [`extract_redis_history_params`], but also accepts `from`/`to` RFC
+/// 3339 timestamps, which are searched for in the list at `key`. The list must
+/// be newest first, and `time_of` pulls the millisecond timestamp out of an
+/// entry.
+pub async fn extract_history_range(
+    redis: &mut ConnectionManager,
+    key: &str,
+    query: &HashMap<String, String>,
+    time_of: fn(&str) -> Option<i64>,
+) -> Result<(isize, isize, FixedOffset), Rejection> {
+    let from = extract_timestamp(query, "from")?;
+    let to = extract_timestamp(query, "to")?;
+    if from.is_none() && to.is_none() {
+        return extract_redis_history_params(query).await;
+    }
+
+    let offset = extract_tz_offset(query)?;
+    let (start, stop) = history_range(&mut RedisList { redis, key }, time_of, from, to).await?;
+
+    Ok((start, stop, offset))
+}
+
+/// Index of the first entry in the newest first list at `key` that is older
+/// than `cutoff`, in milliseconds since the epoch
+pub async fn first_index_older_than(
+    redis: &mut ConnectionManager,
+    key: &str,
+    time_of: fn(&str) -> Option<i64>,
+    cutoff: i64,
+) -> Result<isize, Rejection> {
+    let mut list = RedisList { redis, key };
+    let len = list.len().await?;
+    first_index_where(&mut list, len, time_of, |time| time < cutoff).await
+}
+
+/// A newest first list of history entries
+trait HistoryList {
+    async fn len(&mut self) -> Result<isize, Rejection>;
+    async fn entry(&mut self, index: isize) -> Result<Option<String>, Rejection>;
+}
+
+struct RedisList<'a> {
+    redis: &'a mut ConnectionManager,
+    key: &'a str,
+}
+
+impl HistoryList for RedisList<'_> {
+    async fn len(&mut self) -> Result<isize, Rejection> {
+        self.redis.llen(self.key).await.reject_err()
+    }
+
+    async fn entry(&mut self, index: isize) -> Result<Option<String>, Rejection> {
+        self.redis.lindex(self.key, index).await.reject_err()
+    }
+}
+
+/// The `start`/`stop` indices of the entries between `from` and `to`, for
+/// LRANGE
+async fn history_range(
+    list: &mut impl HistoryList,
+    time_of: fn(&str) -> Option<i64>,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<(isize, isize), Rejection> {
+    let len = list.len().await?;
+
+    let start = match to {
+        Some(to) => first_index_where(list, len, time_of, |time| time <= to).await?,
+        None => 0,
+    };
+    let end = match from {
+        Some(from) => first_index_where(list, len, time_of, |time| time < from).await?,
+        None => len,
+    };
+
+    if start >= end {
+        // A negative stop would wrap around to the end of the list
+        return Ok((len, len));
+    }
+
+    Ok((start, end - 1))
+}
+
+fn extract_timestamp(
+    query: &HashMap<String, String>,
+    name: &'static str,
+) -> Result<Option<i64>, Rejection> {
+    query
+        .get(name)
+        .map(|s| {
+            DateTime::parse_from_rfc3339(s)
+                .map(|time| time.timestamp_millis())
+                .map_err(|_| warp::reject::custom(MissingOrInvalidParameter(name)))
+        })
+        .transpose()
+}
+
+fn extract_tz_offset(query: &HashMap<String, String>) -> Result<FixedOffset, Rejection> {
+    FixedOffset::east_opt(
         (query
             .get("tzoff")
             .and_then(|s| f64::from_str(s).ok())
             .unwrap_or(0.0)
             * 3600.0) as i32,
     )
-    .ok_or_else(|| warp::reject::custom(MissingOrInvalidParameter("stop")))?;
+    .ok_or_else(|| warp::reject::custom(MissingOrInvalidParameter("tzoff")))
+}
 
-    Ok((start, stop, offset))
+/// Binary search for the first entry matching `pred`, or `len` if none do.
+/// `pred` has to go from false to true exactly once along the list. Entries
+/// that don't parse count as matching.
+async fn first_index_where(
+    list: &mut impl HistoryList,
+    len: isize,
+    time_of: fn(&str) -> Option<i64>,
+    pred: impl Fn(i64) -> bool,
+) -> Result<isize, Rejection> {
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let entry = list.entry(mid).await?;
+        let matches = entry.as_deref().and_then(time_of).is_none_or(&pred);
+
+        if matches {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    Ok(low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl HistoryList for Vec<String> {
+        async fn len(&mut self) -> Result<isize, Rejection> {
+            Ok(Vec::len(self) as isize)
+        }
+
+        async fn entry(&mut self, index: isize) -> Result<Option<String>, Rejection> {
+            Ok(self.get(index as usize).cloned())
+        }
+    }
+
+    fn time_of(entry: &str) -> Option<i64> {
+        entry.split(':').next()?.parse().ok()
+    }
+
+    /// Newest first, one entry every 10ms from 100 down to 10
+    fn history() -> Vec<String> {
+        (1..=10).rev().map(|i| format!("{}:20.0", i * 10)).collect()
+    }
+
+    async fn range(from: Option<i64>, to: Option<i64>) -> (isize, isize) {
+        history_range(&mut history(), time_of, from, to)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn range_outside_the_data_is_empty() {
+        assert_eq!(range(Some(200), Some(300)).await, (10, 10));
+        assert_eq!(range(Some(0), Some(5)).await, (10, 10));
+        assert_eq!(range(Some(60), Some(40)).await, (10, 10));
+    }
+
+    #[tokio::test]
+    async fn range_covering_everything() {
+        assert_eq!(range(Some(0), Some(1000)).await, (0, 9));
+        assert_eq!(range(None, None).await, (0, 9));
+    }
+
+    #[tokio::test]
+    async fn range_partially_overlapping() {
+        // Inclusive on both ends: 70, 60, 50
+        assert_eq!(range(Some(50), Some(70)).await, (3, 5));
+        // Everything from 85 up
+        assert_eq!(range(Some(85), None).await, (0, 1));
+        // Everything up to 25
+        assert_eq!(range(None, Some(25)).await, (8, 9));
+    }
+
+    #[tokio::test]
+    async fn finds_the_first_older_entry() {
+        let mut history = history();
+        for (cutoff, index) in [(75, 3), (100, 1), (101, 0), (10, 10)] {
+            let found = first_index_where(&mut history, 10, time_of, |time| time < cutoff)
+                .await
+                .unwrap();
+            assert_eq!(found, index, "cutoff {}", cutoff);
+        }
+    }
+
+    #[tokio::test]
+    async fn unparseable_entries_dont_stop_the_search() {
+        let mut history = history();
+        history[4] = "garbage".into();
+        let range = history_range(&mut history, time_of, Some(50), Some(70))
+            .await
+            .unwrap();
+        assert_eq!(range, (3, 5));
+        let found = first_index_where(&mut history, 10, time_of, |time| time < 35)
+            .await
+            .unwrap();
+        assert_eq!(found, 7);
+    }
 }
@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+use models::keys::{self, PROBE_HISTORY_REPORTS};
+use redis::AsyncCommands;
+use serde::Serialize;
+
+use crate::RedisConn;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CompactionReport {
+    pub probe: String,
+    pub total: usize,
+    pub malformed: usize,
+    /// Zero unless the compaction was asked to rewrite the list
+    pub removed: usize,
+    pub time: i64,
+}
+
+/// Probe history entries look like `{time_ms}:{temp}`, the same thing the
+/// history route parses
+pub fn is_valid_probe_entry(entry: &str) -> bool {
+    let mut split = entry.split(':');
+    let (Some(time), Some(temp), None) = (split.next(), split.next(), split.next()) else {
+        return false;
+    };
+    i64::from_str(time).is_ok() && f64::from_str(temp).is_ok()
+}
+
+/// Scan a probe's history for entries that can't be parsed. With `rewrite`
+/// they are removed with LREM, so the historian can keep pushing new values
+/// while this runs.
+pub async fn compact_probe_history(
+    redis: &RedisConn,
+    probe: &str,
+    rewrite: bool,
+) -> anyhow::Result<CompactionReport> {
+    let history_key = keys::probe_history(probe);
+    let mut redis = redis.get();
+    let history: Vec<String> = redis.lrange(&history_key, 0, -1).await?;
+
+    let mut malformed: Vec<&str> = history
+        .iter()
+        .map(String::as_str)
+        .filter(|entry| !is_valid_probe_entry(entry))
+        .collect();
+    let malformed_count = malformed.len();
+
+    let mut removed = 0;
+    if rewrite {
+        // LREM drops every copy of an entry at once
+        malformed.sort_unstable();
+        malformed.dedup();
+        for entry in malformed {
+            let count: usize = redis.lrem(&history_key, 0, entry).await?;
+            removed += count;
+        }
+    }
+
+    let report = CompactionReport {
+        probe: probe.to_string(),
+        total: history.len(),
+        malformed: malformed_count,
+        removed,
+        time: chrono::Utc::now().timestamp_millis(),
+    };
+
+    let () = redis
+        .hset(PROBE_HISTORY_REPORTS, probe, serde_json::to_string(&report)?)
+        .await?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_only_well_formed_entries() {
+        let history = [
+            "1690000000000:21.5",
+            "garbage",
+            "1690000060000:21.25",
+            "1690000120000:",
+            "1690000180000:21.0:extra",
+            "soon:21.0",
+            "1690000240000:-3",
+        ];
+        let kept: Vec<&str> = history
+            .iter()
+            .copied()
+            .filter(|entry| is_valid_probe_entry(entry))
+            .collect();
+        assert_eq!(kept, ["1690000000000:21.5", "1690000060000:21.25", "1690000240000:-3"]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Probes whose endpoint is an HTTP URL get polled instead of subscribed to.
+//! The URL fragment, if any, is a JSON pointer to the temperature inside the
+//! response, e.g. `http://sensor.local/status#/temperature/celsius`. Without
+//! one the whole body has to be the temperature.
+
+use std::time::Duration;
+
+use serde_json::Value;
+
+pub const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn is_http(endpoint: &str) -> bool {
+    endpoint.starts_with("http://") || endpoint.starts_with("https://")
+}
+
+/// Splits an endpoint into the URL to request and the JSON pointer
+pub fn split_endpoint(endpoint: &str) -> (&str, &str) {
+    endpoint.split_once('#').unwrap_or((endpoint, ""))
+}
+
+pub fn validate_endpoint(endpoint: &str) -> Result<(), &'static str> {
+    let (url, _) = split_endpoint(endpoint);
+    match reqwest::Url::parse(url) {
+        Ok(url) if url.host_str().is_some() => Ok(()),
+        _ => Err("Not a valid HTTP URL"),
+    }
+}
+
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("The default TLS backend should always be available")
+}
+
+pub async fn fetch(client: &reqwest::Client, endpoint: &str) -> anyhow::Result<f32> {
+    let (url, pointer) = split_endpoint(endpoint);
+    let body = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    extract_temperature(&body, pointer)
+}
+
+fn extract_temperature(body: &str, pointer: &str) -> anyhow::Result<f32> {
+    let json: Value = serde_json::from_str(body)?;
+    let Some(value) = json.pointer(pointer) else {
+        anyhow::bail!("Nothing at {pointer:?} in the response");
+    };
+
+    let temp = match value {
+        Value::Number(number) => number.as_f64().map(|n| n as f32),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    };
+    match temp {
+        Some(temp) if temp.is_finite() => Ok(temp),
+        _ => anyhow::bail!("{value} at {pointer:?} isn't a temperature"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::Filter;
+
+    use super::*;
+
+    /// Serves `body` at `/status` on a free local port
+    fn sensor(body: &'static str) -> String {
+        let route = warp::path!("status").map(move || body);
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}/status", addr)
+    }
+
+    #[tokio::test]
+    async fn reads_the_temperature_from_a_sensor() {
+        let url = sensor(r#"{"temperature":{"celsius":21.5,"fahrenheit":70.7}}"#);
+        let temp = fetch(&client(), &format!("{}#/temperature/celsius", url))
+            .await
+            .unwrap();
+        assert_eq!(temp, 21.5);
+    }
+
+    #[tokio::test]
+    async fn bare_body_is_the_temperature() {
+        let url = sensor("19.25");
+        assert_eq!(fetch(&client(), &url).await.unwrap(), 19.25);
+    }
+
+    #[tokio::test]
+    async fn missing_pages_are_errors() {
+        let url = sensor("19.25");
+        assert!(fetch(&client(), &format!("{}/nope", url)).await.is_err());
+    }
+
+    #[test]
+    fn extracts_numbers_and_numeric_strings() {
+        assert_eq!(extract_temperature(r#"{"t":"22.5 "}"#, "/t").unwrap(), 22.5);
+        assert_eq!(extract_temperature(r#"{"t":[18,19]}"#, "/t/1").unwrap(), 19.0);
+        assert!(extract_temperature(r#"{"t":"warm"}"#, "/t").is_err());
+        assert!(extract_temperature(r#"{"t":null}"#, "/t").is_err());
+        assert!(extract_temperature(r#"{"t":1}"#, "/missing").is_err());
+        assert!(extract_temperature("not json", "").is_err());
+    }
+
+    #[test]
+    fn validates_endpoints() {
+        assert!(is_http("https://sensor.local/status#/temp"));
+        assert!(!is_http("home/attic/temp"));
+        assert!(validate_endpoint("http://sensor.local/status#/temp").is_ok());
+        assert!(validate_endpoint("http://").is_err());
+        assert_eq!(
+            split_endpoint("http://sensor.local/status#/temp"),
+            ("http://sensor.local/status", "/temp")
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use super::mixer::{
+    oneshot_setpoint::OneshotSetpointState, override_pulse::OverridePulseState, HvacRequest,
+};
+
+/// A change pushed to `/api/thermostat/live` subscribers, serialized as JSON
+/// tagged by `kind`:
+///
+/// - `{"kind":"temperature","probe":"primary","value":21.5,"time":1690000000000}`
+///   (`time` is milliseconds since the epoch)
+/// - `{"kind":"mode","mode":"heat"}`
+/// - `{"kind":"pinstate","state":"off"}`
+/// - `{"kind":"pulse_override","state":null}`
+/// - `{"kind":"oneshot_setpoint","state":{"setpoint":21.0,"comparison":"greater","action":"heat"}}`
+#[derive(Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LiveUpdate {
+    Temperature { probe: String, value: f32, time: i64 },
+    Mode { mode: HvacRequest },
+    Pinstate { state: HvacRequest },
+    PulseOverride { state: Option<OverridePulseState> },
+    OneshotSetpoint { state: Option<OneshotSetpointState> },
+}
+
+#[derive(Clone)]
+pub struct LiveUpdates {
+    tx: broadcast::Sender<LiveUpdate>,
+}
+
+impl LiveUpdates {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(64);
+        LiveUpdates { tx }
+    }
+
+    pub fn send(&self, update: LiveUpdate) {
+        // No subscribers is fine, nobody is watching
+        self.tx.send(update).ok();
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LiveUpdate> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for LiveUpdates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
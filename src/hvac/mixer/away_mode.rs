@@ -0,0 +1,122 @@
+use std::sync::RwLock;
+
+use models::keys::AWAY_MODE_KEY;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+
+use crate::RedisConn;
+
+use super::HvacRequest;
+
+/// How far past the band edge the temperature must drift back before we stop
+const AWAY_HYSTERESIS: f32 = 0.5;
+
+pub struct AwayMode {
+    state: RwLock<AwayModeState>,
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AwayModeState {
+    pub active: bool,
+    pub band: AwayBand,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct AwayBand {
+    /// Degrees Celcius
+    pub heat_below: f32,
+    /// Degrees Celcius
+    pub cool_above: f32,
+}
+
+impl Default for AwayBand {
+    fn default() -> Self {
+        AwayBand {
+            heat_below: 12.0,
+            cool_above: 30.0,
+        }
+    }
+}
+
+impl AwayMode {
+    pub async fn load(redis: &RedisConn) -> Self {
+        let state = {
+            let mut redis = redis.get();
+            redis.get::<_, String>(AWAY_MODE_KEY).await
+        }
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default();
+
+        AwayMode {
+            state: RwLock::new(state),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state.read().unwrap().active
+    }
+
+    /// Hold the temperature to the away band. `None` means we're inside the
+    /// hysteresis margin and the last request should be kept.
+    pub fn evaluate(&self, mode: HvacRequest, temp: f32) -> Option<HvacRequest> {
+        let band = self.get().band;
+        match mode {
+            HvacRequest::Heat if temp < band.heat_below => Some(HvacRequest::Heat),
+            HvacRequest::Heat if temp > band.heat_below + AWAY_HYSTERESIS => {
+                Some(HvacRequest::Off)
+            }
+            HvacRequest::Cool if temp > band.cool_above => Some(HvacRequest::Cool),
+            HvacRequest::Cool if temp < band.cool_above - AWAY_HYSTERESIS => {
+                Some(HvacRequest::Off)
+            }
+            HvacRequest::Off => Some(HvacRequest::Off),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self) -> AwayModeState {
+        *self.state.read().unwrap()
+    }
+
+    pub async fn set(&self, redis: &RedisConn, state: AwayModeState) -> anyhow::Result<()> {
+        let data = serde_json::to_string(&state)?;
+        {
+            let mut redis = redis.get();
+            let () = redis.set(AWAY_MODE_KEY, data).await?;
+        }
+        *self.state.write().unwrap() = state;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn away(active: bool) -> AwayMode {
+        AwayMode {
+            state: RwLock::new(AwayModeState {
+                active,
+                band: AwayBand::default(),
+            }),
+        }
+    }
+
+    #[test]
+    fn holds_the_band_while_active() {
+        let away = away(true);
+        assert!(away.is_active());
+
+        // A comfortable room that the rules would heat is left alone
+        assert_eq!(away.evaluate(HvacRequest::Heat, 20.0), Some(HvacRequest::Off));
+        assert_eq!(away.evaluate(HvacRequest::Heat, 11.0), Some(HvacRequest::Heat));
+        assert_eq!(away.evaluate(HvacRequest::Heat, 12.2), None);
+
+        assert_eq!(away.evaluate(HvacRequest::Cool, 20.0), Some(HvacRequest::Off));
+        assert_eq!(away.evaluate(HvacRequest::Cool, 31.0), Some(HvacRequest::Cool));
+        assert_eq!(away.evaluate(HvacRequest::Cool, 29.8), None);
+
+        assert_eq!(away.evaluate(HvacRequest::Off, 5.0), Some(HvacRequest::Off));
+    }
+}
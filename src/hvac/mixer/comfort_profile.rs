@@ -0,0 +1,142 @@
+use std::{str::FromStr, sync::RwLock};
+
+use models::keys::COMFORT_PROFILE_KEY;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+
+use crate::RedisConn;
+
+pub struct ComfortProfiles {
+    state: RwLock<ComfortProfileState>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileName {
+    Comfort,
+    Eco,
+}
+
+impl FromStr for ProfileName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "comfort" => Ok(ProfileName::Comfort),
+            "eco" => Ok(ProfileName::Eco),
+            _ => anyhow::bail!("Unknown profile {s:?}"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct ComfortProfileState {
+    pub active: ProfileName,
+    pub comfort: ThresholdProfile,
+    pub eco: ThresholdProfile,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct ThresholdProfile {
+    /// Scales the active ruleset's threshold. Bigger means the set points
+    /// have to be further off before the HVAC kicks in.
+    pub threshold_multiplier: f32,
+}
+
+impl Default for ComfortProfileState {
+    fn default() -> Self {
+        ComfortProfileState {
+            active: ProfileName::Comfort,
+            comfort: ThresholdProfile {
+                threshold_multiplier: 1.0,
+            },
+            eco: ThresholdProfile {
+                threshold_multiplier: 3.0,
+            },
+        }
+    }
+}
+
+impl ComfortProfileState {
+    pub fn active_profile(&self) -> ThresholdProfile {
+        match self.active {
+            ProfileName::Comfort => self.comfort,
+            ProfileName::Eco => self.eco,
+        }
+    }
+}
+
+impl ComfortProfiles {
+    pub async fn load(redis: &RedisConn) -> Self {
+        let state = {
+            let mut redis = redis.get();
+            redis.get::<_, String>(COMFORT_PROFILE_KEY).await
+        }
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default();
+
+        ComfortProfiles {
+            state: RwLock::new(state),
+        }
+    }
+
+    /// The ruleset threshold after applying the active profile
+    pub fn threshold(&self, base: f32) -> f32 {
+        let multiplier = self.get().active_profile().threshold_multiplier;
+        if multiplier.is_finite() && multiplier > 0.0 {
+            base * multiplier
+        } else {
+            base
+        }
+    }
+
+    pub fn get(&self) -> ComfortProfileState {
+        *self.state.read().unwrap()
+    }
+
+    pub async fn set(&self, redis: &RedisConn, state: ComfortProfileState) -> anyhow::Result<()> {
+        let data = serde_json::to_string(&state)?;
+        {
+            let mut redis = redis.get();
+            let () = redis.set(COMFORT_PROFILE_KEY, data).await?;
+        }
+        *self.state.write().unwrap() = state;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiles(active: ProfileName) -> ComfortProfiles {
+        ComfortProfiles {
+            state: RwLock::new(ComfortProfileState {
+                active,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn switching_profiles_changes_the_threshold() {
+        assert_eq!(profiles(ProfileName::Comfort).threshold(0.1), 0.1);
+        assert_eq!(profiles(ProfileName::Eco).threshold(0.1), 0.1 * 3.0);
+    }
+
+    #[test]
+    fn ignores_nonsense_multipliers() {
+        let profiles = profiles(ProfileName::Eco);
+        for threshold_multiplier in [0.0, -1.0, f32::NAN] {
+            profiles.state.write().unwrap().eco = ThresholdProfile { threshold_multiplier };
+            assert_eq!(profiles.threshold(0.1), 0.1);
+        }
+    }
+
+    #[test]
+    fn parses_profile_names() {
+        assert_eq!("eco".parse::<ProfileName>().unwrap(), ProfileName::Eco);
+        assert!("Eco".parse::<ProfileName>().is_err());
+    }
+}
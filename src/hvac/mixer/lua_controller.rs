@@ -1,12 +1,21 @@
 use std::{
+    any::Any,
     collections::{BTreeMap, BTreeSet},
     future::IntoFuture,
+    panic::AssertUnwindSafe,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
-use chrono::NaiveTime;
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use futures_util::FutureExt;
 use mlua::prelude::*;
-use models::hvac_request::HvacRequest;
+use models::{
+    hvac_request::HvacRequest,
+    keys::{CONFIG_LOCATION, LUA_CURRENT_SCRIPT},
+    script_log::ScriptLog,
+    sun::{sun_times, SunTimes},
+};
 use redis::AsyncCommands;
 use tokio::{runtime::Runtime, sync::Mutex, task::LocalSet};
 
@@ -18,6 +27,30 @@ use crate::{
 
 use super::MixerState;
 
+/// Values a script reported with `explain` during one validation
+pub type Explanations = BTreeMap<String, serde_json::Value>;
+
+/// Everything a validation run produced, short of an error
+pub struct Validation {
+    pub output: Option<HvacRequest>,
+    pub issues: BTreeSet<String>,
+    pub explained: Option<Explanations>,
+    /// One line for every call to `print`
+    pub printed: Vec<String>,
+}
+
+/// Collects `print` output while validating
+#[derive(Default)]
+struct PrintCapture(Vec<String>);
+
+/// Collects issues while validating or loading, the active script reports to
+/// `ISSUES`
+#[derive(Default)]
+struct IssueCollector(BTreeSet<String>);
+
+/// How long a script being validated may run before it's aborted
+const VALIDATION_TIME_BUDGET: Duration = Duration::from_secs(2);
+
 #[derive(Clone)]
 pub struct LuaController {
     state: Arc<Mutex<LuaControllerState>>,
@@ -39,20 +72,43 @@ type LuaExecTask = Box<dyn FnOnce(&LocalSet) + Send + 'static>;
 fn create_lua_thread() -> tokio::sync::mpsc::Sender<LuaExecTask> {
     let (tx, mut rx) = tokio::sync::mpsc::channel::<LuaExecTask>(16);
 
-    std::thread::spawn(move || {
-        let rt = Runtime::new().unwrap();
-        let localset = LocalSet::new();
-        localset.block_on(&rt, async {
-            while let Some(task) = rx.recv().await {
-                task(&localset);
-            }
+    std::thread::Builder::new()
+        .name(LUA_THREAD_NAME.into())
+        .spawn(move || {
+            let rt = Runtime::new().unwrap();
+            let localset = LocalSet::new();
+            localset.block_on(&rt, async {
+                while let Some(task) = rx.recv().await {
+                    task(&localset);
+                }
+            })
         })
-    });
+        .expect("Spawning the Lua worker thread should never fail");
 
     tx
 }
 
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".into())
+}
+
+/// The worker is the one place panics are allowed to unwind, see
+/// `crate::abort_on_panic`
+const LUA_THREAD_NAME: &str = "lua_worker";
+
+/// Whether this is running on one of the Lua worker threads
+pub fn on_lua_thread() -> bool {
+    std::thread::current().name() == Some(LUA_THREAD_NAME)
+}
+
 impl LuaController {
+    /// Tasks catch their own panics so one bad script can't take the worker
+    /// thread's other tasks down with it. This relies on panics unwinding, so
+    /// the release profile mustn't switch to `panic = "abort"`.
     async fn exec_lua_thread<Fn, Fu, R>(&self, f: Fn) -> anyhow::Result<R>
     where
         Fn: FnOnce() -> Fu + Send + 'static,
@@ -63,12 +119,14 @@ impl LuaController {
         self.task_tx
             .send(Box::new(move |localset| {
                 localset.spawn_local(async move {
-                    tx.send(f().await).ok();
+                    let result = AssertUnwindSafe(async move { f().await }).catch_unwind();
+                    tx.send(result.await.map_err(panic_message)).ok();
                 });
             }))
             .await
             .ok();
-        Ok(rx.await?)
+        rx.await?
+            .map_err(|message| anyhow::anyhow!("Lua task panicked: {message}"))
     }
 
     fn fire_lua_thread<Fn, Fu>(&self, f: Fn) -> anyhow::Result<()>
@@ -79,7 +137,10 @@ impl LuaController {
         self.task_tx
             .blocking_send(Box::new(move |localset| {
                 localset.spawn_local(async move {
-                    f().await;
+                    let result = AssertUnwindSafe(async move { f().await }).catch_unwind();
+                    if let Err(panic) = result.await {
+                        tracing::error!(message = %panic_message(panic), "Lua task panicked");
+                    }
                 });
             }))
             .map_err(|e| anyhow::anyhow!("fire_lua_thread: {e}"))?;
@@ -91,36 +152,69 @@ impl LuaController {
         state.is_loaded()
     }
 
+    /// With `explain` set, whatever the script passes to `explain(key, value)`
+    /// is collected and returned alongside the result
     pub async fn validate(
         &self,
         script: String,
         mixer: MixerState,
-    ) -> anyhow::Result<(Option<HvacRequest>, BTreeSet<String>)> {
-        // Lock the state so the issues won't fill up
-        let mut _state = self.state.lock().await;
-
-        let result = self
-            .exec_lua_thread(move || async move {
-                reset_issues();
-                let mut temp_state = LuaControllerState::default();
-                temp_state.load(&script, mixer.clone()).await?;
-                let result = temp_state.evaluate(mixer).await?;
-                Ok::<_, anyhow::Error>(result)
+        explain: bool,
+    ) -> anyhow::Result<Validation> {
+        self.exec_lua_thread(move || async move {
+            let mut temp_state = LuaControllerState::default();
+            if explain {
+                temp_state.lua.set_app_data(Explanations::new());
+            }
+            // Kept apart from the active script's issues, and from any other
+            // validation running at the same time
+            temp_state.lua.set_app_data(IssueCollector::default());
+            temp_state.lua.set_app_data(PrintCapture::default());
+            temp_state.arm_timeout(VALIDATION_TIME_BUDGET)?;
+            temp_state.load(&script, mixer.clone()).await?;
+            let output = temp_state.evaluate(mixer).await?;
+
+            let lua = &temp_state.lua;
+            Ok::<_, anyhow::Error>(Validation {
+                output,
+                issues: lua.remove_app_data::<IssueCollector>().unwrap_or_default().0,
+                explained: lua.remove_app_data::<Explanations>(),
+                printed: lua.remove_app_data::<PrintCapture>().unwrap_or_default().0,
             })
-            .await??;
-
-        Ok((result, issues()))
+        })
+        .await?
     }
 
     pub async fn load(&self, script: String, mixer: MixerState) -> anyhow::Result<()> {
         let state = self.state.clone();
         self.exec_lua_thread(move || async move {
+            // Held until the new script is in place, so `tick` and `on_mqtt`
+            // wait instead of seeing a half loaded script. Loading into a
+            // fresh VM means a script that fails part way leaves the old one
+            // running untouched.
             let mut state = state.lock().await;
-            state.load(&script, mixer).await
+            let mut fresh = LuaControllerState::default();
+            // The old script's issues stay up until the new one has replaced it
+            fresh.lua.set_app_data(IssueCollector::default());
+            fresh.load(&script, mixer).await?;
+            let loading_issues = fresh.lua.remove_app_data::<IssueCollector>();
+            *state = fresh;
+            replace_issues(loading_issues.unwrap_or_default().0);
+            Ok::<_, anyhow::Error>(())
         })
         .await?
     }
 
+    /// Drop the active script, the mixer goes back to the timed ruleset
+    pub async fn unload(&self) -> anyhow::Result<()> {
+        let state = self.state.clone();
+        self.exec_lua_thread(move || async move {
+            let mut state = state.lock().await;
+            *state = LuaControllerState::default();
+            replace_issues(BTreeSet::new());
+        })
+        .await
+    }
+
     pub async fn evaluate(&self, mixer: MixerState) -> anyhow::Result<Option<HvacRequest>> {
         let state = self.state.clone();
         self.exec_lua_thread(move || async move {
@@ -133,7 +227,7 @@ impl LuaController {
     pub async fn load_redis(&self, redis: &RedisConn, mixer: MixerState) -> anyhow::Result<()> {
         let script = {
             let mut redis = redis.get();
-            redis.get("thermostat.lua.current").await?
+            redis.get(LUA_CURRENT_SCRIPT).await?
         };
 
         self.load(script, mixer).await
@@ -167,17 +261,136 @@ struct LuaControllerState {
 
 impl Default for LuaControllerState {
     fn default() -> Self {
-        LuaControllerState { lua: Lua::new() }
+        let lua = Lua::new();
+        register_globals(&lua).expect("Registering Lua globals should never fail");
+        LuaControllerState { lua }
     }
 }
 
+fn register_globals(lua: &Lua) -> LuaResult<()> {
+    let log = lua.create_function(|_, message: String| {
+        add_log(message);
+        Ok(())
+    })?;
+    lua.globals().set("log", log)?;
+
+    // Shown with the validation results while validating, nobody reads the
+    // server's stdout so it goes to the script log otherwise
+    let print = lua.create_function(|lua, args: LuaMultiValue| {
+        let tostring: LuaFunction = lua.globals().get("tostring")?;
+        let line = args
+            .into_iter()
+            .map(|arg| tostring.call::<_, String>(arg))
+            .collect::<LuaResult<Vec<_>>>()?
+            .join("\t");
+        match lua.app_data_mut::<PrintCapture>() {
+            Some(mut printed) => printed.0.push(line),
+            None => add_log(line),
+        }
+        Ok(())
+    })?;
+    lua.globals().set("print", print)?;
+
+    // Only collects anything while validating in explain mode
+    let explain = lua.create_function(|lua, (key, value): (String, LuaValue)| {
+        if let Some(mut explained) = lua.app_data_mut::<Explanations>() {
+            let value = lua.from_value(value).unwrap_or(serde_json::Value::Null);
+            explained.insert(key, value);
+        }
+        Ok(())
+    })?;
+    lua.globals().set("explain", explain)?;
+
+    register_math_helpers(lua)?;
+    Ok(())
+}
+
+/// `clamp`, `lerp` and `map`, so scripts don't each hand roll them
+fn register_math_helpers(lua: &Lua) -> LuaResult<()> {
+    let clamp = lua.create_function(|_, (x, lo, hi): (f64, f64, f64)| Ok(x.max(lo).min(hi)))?;
+    lua.globals().set("clamp", clamp)?;
+
+    let lerp = lua.create_function(|_, (a, b, t): (f64, f64, f64)| Ok(a + (b - a) * t))?;
+    lua.globals().set("lerp", lerp)?;
+
+    let map = lua.create_function(
+        |_, (x, in_lo, in_hi, out_lo, out_hi): (f64, f64, f64, f64, f64)| {
+            // An empty input range would divide by zero
+            if in_hi == in_lo {
+                return Ok(out_lo);
+            }
+            let t = (x - in_lo) / (in_hi - in_lo);
+            Ok(out_lo + (out_hi - out_lo) * t)
+        },
+    )?;
+    lua.globals().set("map", map)?;
+    Ok(())
+}
+
+/// Picks one of the times out of `SunTimes`
+type SunEvent = fn(SunTimes) -> DateTime<Utc>;
+
+/// `sun.sunrise()` and `sun.sunset()` return today's times in Unix seconds,
+/// comparable with `os.time()`. Both are nil without a configured location
+/// and on days the sun doesn't rise or set.
+fn register_sun_helpers(lua: &Lua, redis: RedisConn) -> LuaResult<()> {
+    let sun = lua.create_table()?;
+    let events: [(&str, SunEvent); 2] = [
+        ("sunrise", |times| times.sunrise),
+        ("sunset", |times| times.sunset),
+    ];
+    for (name, event) in events {
+        let redis = redis.clone();
+        let func = lua.create_async_function(move |_, ()| {
+            let mut redis = redis.get();
+            async move {
+                let location: Option<String> = redis
+                    .get(CONFIG_LOCATION)
+                    .await
+                    .map_err(|e| LuaError::ExternalError(Arc::new(e)))?;
+                Ok(location
+                    .and_then(|location| location.parse().ok())
+                    .and_then(|location| sun_times(location, Local::now().date_naive()))
+                    .map(|times| event(times).timestamp()))
+            }
+        })?;
+        sun.set(name, func)?;
+    }
+    lua.globals().set("sun", sun)?;
+    Ok(())
+}
+
 impl LuaControllerState {
     fn is_loaded(&self) -> bool {
         self.lua.globals().get::<_, LuaFunction>("evaluate").is_ok()
     }
 
+    /// Abort any Lua still running `budget` from now, so a runaway loop
+    /// becomes an error instead of wedging the Lua thread.
+    fn arm_timeout(&self, budget: Duration) -> anyhow::Result<()> {
+        let deadline = Instant::now() + budget;
+        self.lua.set_hook(
+            LuaHookTriggers {
+                every_nth_instruction: Some(10_000),
+                ..Default::default()
+            },
+            move |_, _| {
+                if Instant::now() > deadline {
+                    Err(LuaError::RuntimeError(format!(
+                        "Script exceeded its {budget:?} time budget"
+                    )))
+                } else {
+                    Ok(())
+                }
+            },
+        )?;
+        Ok(())
+    }
+
     async fn load(&mut self, script: &str, mixer: MixerState) -> anyhow::Result<()> {
-        self.lua.load(script).exec_async().await?;
+        // Needs Redis for the location, which isn't around when the state is created
+        register_sun_helpers(&self.lua, mixer.redis.clone())?;
+        self.exec_chunk(script).await?;
 
         if let Ok(init) = self.lua.globals().get::<_, LuaFunction>("init") {
             let () = init.call_async(mixer).await?;
@@ -186,6 +399,16 @@ impl LuaControllerState {
         Ok(())
     }
 
+    /// Run the script's top level, which has to leave an `evaluate` behind.
+    /// Otherwise the script would load fine and then never do anything.
+    async fn exec_chunk(&self, script: &str) -> anyhow::Result<()> {
+        self.lua.load(script).exec_async().await?;
+        if !self.is_loaded() {
+            anyhow::bail!("Script doesn't define an `evaluate` function");
+        }
+        Ok(())
+    }
+
     async fn evaluate(&mut self, mixer: MixerState) -> anyhow::Result<Option<HvacRequest>> {
         let evaluate: LuaFunction = self.lua.globals().get("evaluate")?;
         let result: Option<String> = evaluate.call_async(mixer).await?;
@@ -210,6 +433,28 @@ impl LuaControllerState {
     }
 }
 
+/// Runs the block whose time most recently passed at `now`, wrapping around
+/// to the last one before the first block of the day. When it returns nil,
+/// or there are no blocks at all, `default` gets a turn.
+async fn run_timed_program<'lua>(
+    program: &BTreeMap<NaiveTime, LuaFunction<'lua>>,
+    default: Option<LuaFunction<'lua>>,
+    now: NaiveTime,
+) -> LuaResult<LuaValue<'lua>> {
+    let active = program
+        .range(..now)
+        .next_back()
+        .or_else(|| program.last_key_value());
+    let result = match active {
+        Some((_, block)) => block.call_async(()).await?,
+        None => LuaValue::Nil,
+    };
+    match (result, default) {
+        (LuaValue::Nil, Some(default)) => default.call_async(()).await,
+        (result, _) => Ok(result),
+    }
+}
+
 impl LuaUserData for MixerState {
     fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
         fields.add_field_method_get("redis", |_, this| Ok(this.redis.clone()));
@@ -218,21 +463,40 @@ impl LuaUserData for MixerState {
         fields.add_field_method_get("last_result", |_, this| {
             Ok(this.last_result.load().payload_str())
         });
+        // Read-only so scripts can back off while someone is in manual control
+        fields.add_field_method_get("override_pulse", |lua, this| {
+            lua.to_value(&this.override_pulse.active())
+        });
+        fields.add_field_method_get("oneshot_setpoint", |lua, this| {
+            lua.to_value(&this.oneshot_setpoint.get())
+        });
     }
 
     fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        // The block whose time most recently passed runs first. Only when it
+        // returns nil does the `default` (or `"*"`) function get a turn.
         methods.add_async_method("timed_program", |lua, _this, program: LuaTable| async move {
             let mut program_table = BTreeMap::new();
+            let mut default = None;
             for pair in program.pairs::<String, LuaFunction>() {
                 let Ok((time_str, func)) = pair else {
-                    add_issue(format!(
+                    add_issue(lua, format!(
                         "[src:{}] Timed program must be passed a table mapping time strings to functions",
                         lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
                     ));
                     continue
                 };
+                if time_str == "default" || time_str == "*" {
+                    if default.replace(func).is_some() {
+                        add_issue(lua, format!(
+                            "[src:{}] Timed program can only have one of 'default' and '*'",
+                            lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
+                        ));
+                    }
+                    continue
+                }
                 let Ok(time) = NaiveTime::parse_from_str(&time_str, "%H:%M") else {
-                    add_issue(format!(
+                    add_issue(lua, format!(
                         "[src:{}] Timed program keys must be in 'HH:MM' format. Found {time_str}",
                         lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
                     ));
@@ -240,29 +504,43 @@ impl LuaUserData for MixerState {
                 };
                 program_table.insert(time, func);
             }
-            if program_table.is_empty() {
-                add_issue(format!(
+            if program_table.is_empty() && default.is_none() {
+                add_issue(lua, format!(
                     "[src:{}] Empty program table",
                     lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
                 ));
                 return Ok(LuaValue::Nil)
             }
 
-            let current_time = chrono::Local::now().time();
-            let mut active_time = *program_table.last_key_value().unwrap().0;
-            for time in program_table.keys() {
-                if *time < current_time {
-                    active_time = *time;
-                } else {
-                    break;
-                }
+            run_timed_program(&program_table, default, chrono::Local::now().time()).await
+        });
+
+        // What the programmed rules would decide, so a script can defer to them
+        methods.add_async_method("evaluate_rules", |lua, this, ()| async move {
+            if lua.app_data_ref::<EvaluatingRules>().is_some() {
+                return Err(LuaError::RuntimeError(
+                    "evaluate_rules can't be called while it's already running".into(),
+                ));
             }
 
-            program_table[&active_time].call_async(()).await
+            lua.set_app_data(EvaluatingRules);
+            let threshold = this
+                .comfort_profiles
+                .threshold(this.timed_ruleset.threshold);
+            let request = this
+                .timed_ruleset
+                .evaluate_with_threshold(&this, threshold)
+                .await;
+            lua.remove_app_data::<EvaluatingRules>();
+
+            Ok(request.map(|request| request.payload_str()))
         });
     }
 }
 
+/// Set while `evaluate_rules` is running
+struct EvaluatingRules;
+
 impl LuaUserData for Probes {
     fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_async_meta_method(LuaMetaMethod::Index, |_, this, probe: String| async move {
@@ -273,7 +551,10 @@ impl LuaUserData for Probes {
 
 impl LuaUserData for Probe {
     fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
-        fields.add_field_method_get("temperature", |_, this| Ok(this.value()))
+        fields.add_field_method_get("temperature", |_, this| Ok(this.value()));
+        // Seconds since the last update
+        fields.add_field_method_get("age", |_, this| Ok(this.age().as_secs_f64()));
+        fields.add_field_method_get("is_stale", |_, this| Ok(this.is_stale()));
     }
 }
 
@@ -347,18 +628,329 @@ impl LuaUserData for MqttClient {
     }
 }
 
+/// Issues from the active script, replaced once a new one has loaded
 static ISSUES: std::sync::Mutex<BTreeSet<String>> = std::sync::Mutex::new(BTreeSet::new());
 
-pub fn reset_issues() {
+fn replace_issues(new_issues: BTreeSet<String>) {
     let mut issues = ISSUES.lock().unwrap();
-    issues.clear();
+    *issues = new_issues;
 }
 
-fn add_issue(issue: String) {
+fn add_issue(lua: &Lua, issue: String) {
+    if let Some(mut collector) = lua.app_data_mut::<IssueCollector>() {
+        collector.0.insert(issue);
+        return;
+    }
     let mut issues = ISSUES.lock().unwrap();
     issues.insert(issue);
 }
 
+/// Adjustments made while loading the current timed ruleset. Kept apart from
+/// `ISSUES` since loading a script doesn't change the ruleset.
+static RULESET_ISSUES: std::sync::Mutex<BTreeSet<String>> =
+    std::sync::Mutex::new(BTreeSet::new());
+
+pub fn set_ruleset_issues(issues: impl IntoIterator<Item = String>) {
+    let mut ruleset_issues = RULESET_ISSUES.lock().unwrap();
+    *ruleset_issues = issues.into_iter().collect();
+}
+
 pub fn issues() -> BTreeSet<String> {
-    ISSUES.lock().unwrap().clone()
+    let mut issues = ISSUES.lock().unwrap().clone();
+    issues.extend(RULESET_ISSUES.lock().unwrap().iter().cloned());
+    issues
+}
+
+static SCRIPT_LOG: std::sync::Mutex<ScriptLog> = std::sync::Mutex::new(ScriptLog::new());
+
+fn add_log(message: String) {
+    let mut log = SCRIPT_LOG.lock().unwrap();
+    log.push(message);
+}
+
+pub fn script_log() -> ScriptLog {
+    SCRIPT_LOG.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn block<'lua>(lua: &'lua Lua, chunk: &str) -> LuaFunction<'lua> {
+        lua.load(chunk).eval_async().await.unwrap()
+    }
+
+    async fn run_at(
+        lua: &Lua,
+        program: &BTreeMap<NaiveTime, LuaFunction<'_>>,
+        default: Option<&str>,
+        time: &str,
+    ) -> Option<String> {
+        let default = match default {
+            Some(chunk) => Some(block(lua, chunk).await),
+            None => None,
+        };
+        let now = NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+        let result = run_timed_program(program, default, now).await.unwrap();
+        lua.from_value(result).unwrap()
+    }
+
+    #[tokio::test]
+    async fn timed_program_default_fires_only_on_nil() {
+        let lua = Lua::new();
+        let at = |time: &str| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+        let program = BTreeMap::from([
+            (at("06:00"), block(&lua, "return function() return 'heat' end").await),
+            (at("22:00"), block(&lua, "return function() return nil end").await),
+        ]);
+        let off = Some("return function() return 'off' end");
+
+        assert_eq!(run_at(&lua, &program, off, "12:00").await.as_deref(), Some("heat"));
+        assert_eq!(run_at(&lua, &program, off, "23:00").await.as_deref(), Some("off"));
+        // Before the first block of the day the evening one is still running
+        assert_eq!(run_at(&lua, &program, off, "03:00").await.as_deref(), Some("off"));
+        // Without a default a nil block stays nil
+        assert_eq!(run_at(&lua, &program, None, "23:00").await, None);
+    }
+
+    #[tokio::test]
+    async fn timed_program_of_only_a_default() {
+        let lua = Lua::new();
+        let default = block(&lua, "return function() return 'cool' end").await;
+        let result = run_timed_program(&BTreeMap::new(), Some(default), NaiveTime::MIN)
+            .await
+            .unwrap();
+        assert_eq!(lua.from_value::<String>(result).unwrap(), "cool");
+    }
+
+    #[test]
+    fn probe_age_and_staleness_from_a_script() {
+        let lua = Lua::new();
+        let probe = Probe::new("attic", "home/attic/temp");
+        probe.update(21.0);
+        probe.set_stale_after(Duration::from_millis(50));
+        lua.globals().set("probe", probe.clone()).unwrap();
+
+        let fresh: (f64, bool) = lua.load("return probe.age, probe.is_stale").eval().unwrap();
+        assert!(fresh.0 < 0.05, "{}", fresh.0);
+        assert!(!fresh.1);
+
+        std::thread::sleep(Duration::from_millis(100));
+        let aged: (f64, bool) = lua.load("return probe.age, probe.is_stale").eval().unwrap();
+        assert!(aged.0 >= 0.1, "{}", aged.0);
+        assert!(aged.1);
+
+        // A fresh reading makes it usable again
+        probe.update(21.5);
+        let stale: bool = lua.load("return probe.is_stale").eval().unwrap();
+        assert!(!stale);
+    }
+
+    #[test]
+    fn math_helpers_from_a_script() {
+        let lua = Lua::new();
+        register_math_helpers(&lua).unwrap();
+        let eval = |chunk: &str| -> f64 { lua.load(chunk).eval().unwrap() };
+
+        assert_eq!(eval("return clamp(25, 18, 22)"), 22.0);
+        assert_eq!(eval("return clamp(10, 18, 22)"), 18.0);
+        assert_eq!(eval("return clamp(20, 18, 22)"), 20.0);
+        assert_eq!(eval("return lerp(18, 22, 0.25)"), 19.0);
+        assert_eq!(eval("return map(15, 10, 20, 0, 100)"), 50.0);
+        assert_eq!(eval("return map(5, 10, 20, 100, 0)"), 150.0);
+        // An empty input range doesn't divide by zero
+        assert_eq!(eval("return map(5, 10, 10, 1, 2)"), 1.0);
+    }
+
+    #[tokio::test]
+    async fn worker_survives_a_panicking_task() {
+        let controller = LuaController::default();
+
+        let panicked: anyhow::Result<()> = controller
+            .exec_lua_thread(|| async { panic!("script blew up") })
+            .await;
+        let error = panicked.unwrap_err().to_string();
+        assert!(error.contains("script blew up"), "{}", error);
+
+        let answer = controller.exec_lua_thread(|| async { 42 }).await.unwrap();
+        assert_eq!(answer, 42);
+
+        // Which is why only the worker is spared by `crate::abort_on_panic`
+        let on_worker = controller.exec_lua_thread(|| async { on_lua_thread() }).await;
+        assert!(on_worker.unwrap());
+        assert!(!on_lua_thread());
+    }
+
+    #[test]
+    fn collected_issues_stay_separate() {
+        let first = LuaControllerState::default();
+        let second = LuaControllerState::default();
+        first.lua.set_app_data(IssueCollector::default());
+        second.lua.set_app_data(IssueCollector::default());
+
+        add_issue(&first.lua, "first validation".into());
+        add_issue(&second.lua, "second validation".into());
+
+        let collected = |state: &LuaControllerState| {
+            state.lua.remove_app_data::<IssueCollector>().unwrap().0
+        };
+        assert_eq!(collected(&first), BTreeSet::from(["first validation".to_string()]));
+        assert_eq!(collected(&second), BTreeSet::from(["second validation".to_string()]));
+        assert!(!issues().contains("first validation"));
+        assert!(!issues().contains("second validation"));
+    }
+
+    #[test]
+    fn explained_values_are_collected() {
+        let state = LuaControllerState::default();
+        state.lua.set_app_data(Explanations::new());
+        state
+            .lua
+            .load(
+                r#"
+                local error = 21.5 - 20
+                explain("error", error)
+                explain("probes", { "attic", "bedroom" })
+                explain("heating", error > 1)
+                "#,
+            )
+            .exec()
+            .unwrap();
+
+        let explained = state.lua.remove_app_data::<Explanations>().unwrap();
+        assert_eq!(explained["error"], serde_json::json!(1.5));
+        assert_eq!(explained["probes"], serde_json::json!(["attic", "bedroom"]));
+        assert_eq!(explained["heating"], serde_json::json!(true));
+    }
+
+    #[test]
+    fn explain_is_ignored_outside_explain_mode() {
+        let state = LuaControllerState::default();
+        state.lua.load(r#"explain("error", 1.5)"#).exec().unwrap();
+        assert!(state.lua.remove_app_data::<Explanations>().is_none());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn evaluate_rules_matches_the_ruleset() {
+        let redis = RedisConn::scratch().await;
+        let mqtt = MqttClient::loopback(false);
+        let probes = Probes::unfed(&["primary"]).await;
+        // Cold enough that any programmed rule asks for heat
+        probes.get("primary").await.unwrap().update(5.0);
+        let mixer = MixerState::new(
+            &redis,
+            &mqtt,
+            probes,
+            Arc::new(super::super::AtomicHvacRequest::new()),
+            Default::default(),
+            crate::hvac::live::LiveUpdates::new(),
+        )
+        .await;
+
+        let mut state = LuaControllerState::default();
+        state
+            .exec_chunk("function evaluate(state) return state:evaluate_rules() end")
+            .await
+            .unwrap();
+        let threshold = mixer
+            .comfort_profiles
+            .threshold(mixer.timed_ruleset.threshold);
+        let standalone = mixer
+            .timed_ruleset
+            .evaluate_with_threshold(&*mixer, threshold)
+            .await;
+        assert!(standalone.is_some());
+        let scripted = state.evaluate((*mixer).clone()).await.unwrap();
+        assert_eq!(scripted, standalone);
+    }
+
+    #[test]
+    fn print_is_captured_while_validating() {
+        let state = LuaControllerState::default();
+        state.lua.set_app_data(PrintCapture::default());
+        state
+            .lua
+            .load(r#"print("hi") print("probe", 21.5, nil)"#)
+            .exec()
+            .unwrap();
+
+        let printed = state.lua.remove_app_data::<PrintCapture>().unwrap().0;
+        assert_eq!(printed, ["hi", "probe\t21.5\tnil"]);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn ticks_never_see_a_half_loaded_script() {
+        let redis = RedisConn::scratch().await;
+        let mixer = MixerState::new(
+            &redis,
+            &MqttClient::loopback(false),
+            Probes::unfed(&["primary"]).await,
+            Arc::new(super::super::AtomicHvacRequest::new()),
+            Default::default(),
+            crate::hvac::live::LiveUpdates::new(),
+        )
+        .await;
+        let script = |generation: usize| {
+            format!(
+                r#"
+                generation = {}
+                ready = false
+                function init(state) state:evaluate_rules() ready = true end
+                function tick(state)
+                    if not ready then log("half loaded tick " .. generation) end
+                end
+                function evaluate(state) return "off" end
+                "#,
+                generation
+            )
+        };
+
+        let controller = mixer.lua.clone();
+        let reloads = {
+            let mixer = (*mixer).clone();
+            async move {
+                for generation in 0..50 {
+                    controller.load(script(generation), mixer.clone()).await.unwrap();
+                    tokio::task::yield_now().await;
+                }
+            }
+        };
+        let ticks = async {
+            for _ in 0..500 {
+                mixer.lua.tick((*mixer).clone()).await;
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::join!(reloads, ticks);
+
+        let half_loaded = script_log()
+            .lines()
+            .any(|line| line.message.starts_with("half loaded tick"));
+        assert!(!half_loaded);
+    }
+
+    #[tokio::test]
+    async fn runaway_scripts_are_aborted() {
+        let state = LuaControllerState::default();
+        state.arm_timeout(Duration::from_millis(50)).unwrap();
+        let error = state.exec_chunk("while true do end").await.unwrap_err();
+        assert!(format!("{error:#}").contains("time budget"), "{:#}", error);
+    }
+
+    #[tokio::test]
+    async fn requires_evaluate() {
+        let state = LuaControllerState::default();
+        let error = state.exec_chunk("x = 1").await.unwrap_err();
+        assert!(error.to_string().contains("evaluate"), "{}", error);
+        assert!(!state.is_loaded());
+
+        let state = LuaControllerState::default();
+        state
+            .exec_chunk("function evaluate(state) return 'heat' end")
+            .await
+            .unwrap();
+        assert!(state.is_loaded());
+    }
 }
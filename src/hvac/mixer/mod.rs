@@ -1,6 +1,5 @@
 use std::{
     cmp,
-    collections::BTreeSet,
     sync::{
         atomic::{AtomicU8, Ordering},
         Arc,
@@ -8,24 +7,32 @@ use std::{
 };
 
 use arc_cell::ArcCell;
+use serde::Serialize;
 
 use crate::{api::atticfan::FanState, RedisConn, mqtt::MqttClient};
 
 use self::{
-    lua_controller::LuaController,
+    away_mode::AwayMode,
+    comfort_profile::ComfortProfiles,
+    lua_controller::{LuaController, Validation},
     oneshot_setpoint::{OneshotOrdering, OneshotSetpoint},
     override_pulse::OverridePulse,
-    timed_rule::TimedRuleSet,
+    timed_rule::{TimedRule, TimedRuleSet},
 };
 
-use super::Probes;
+use super::{
+    live::{LiveUpdate, LiveUpdates},
+    Probes, PRIMARY_PROBE,
+};
 
-pub use models::hvac_request::HvacRequest;
+pub use models::{hvac_request::HvacRequest, set_point};
 
+pub mod away_mode;
+pub mod comfort_profile;
 pub mod lua_controller;
 pub mod oneshot_setpoint;
 pub mod override_pulse;
-pub mod set_point;
+pub mod script_schedule;
 pub mod timed_rule;
 
 #[derive(Clone)]
@@ -36,10 +43,13 @@ pub struct MixerState {
     pub fan_state: FanState,
     pub override_pulse: Arc<OverridePulse>,
     pub oneshot_setpoint: Arc<OneshotSetpoint>,
+    pub away_mode: Arc<AwayMode>,
+    pub comfort_profiles: Arc<ComfortProfiles>,
     pub timed_ruleset: Arc<TimedRuleSet>,
     pub lua: LuaController,
     pub last_result: Arc<AtomicHvacRequest>,
     pub mode: Arc<AtomicHvacRequest>,
+    pub live: LiveUpdates,
 }
 
 impl MixerState {
@@ -49,18 +59,22 @@ impl MixerState {
         probes: Probes,
         mode: Arc<AtomicHvacRequest>,
         fan_state: FanState,
+        live: LiveUpdates,
     ) -> Arc<Self> {
         let state = MixerState {
             redis: redis.clone(),
             mqtt: mqtt.clone(),
             probes,
             fan_state,
-            override_pulse: Arc::new(OverridePulse::new()),
+            override_pulse: Arc::new(OverridePulse::load(redis).await),
             oneshot_setpoint: Arc::new(OneshotSetpoint::new()),
+            away_mode: Arc::new(AwayMode::load(redis).await),
+            comfort_profiles: Arc::new(ComfortProfiles::load(redis).await),
             timed_ruleset: Arc::new(timed_rule::load(redis).await),
             lua: LuaController::default(),
             last_result: Arc::new(AtomicHvacRequest::new()),
             mode,
+            live,
         };
 
         state.lua.load_redis(redis, state.clone()).await.ok();
@@ -69,80 +83,140 @@ impl MixerState {
     }
 
     pub async fn query(&self) -> HvacRequest {
-        if let Some(request) = self.get_query().await {
+        self.query_traced().await.0
+    }
+
+    /// `query`, along with how it came to its answer
+    pub async fn query_traced(&self) -> (HvacRequest, EvaluationTrace) {
+        let trace = self.evaluate(false).await;
+        let request = if let Some(request) = trace.request {
             self.last_result.store(request);
             request
         } else {
             self.last_result.load()
-        }
+        };
+        (request, trace)
     }
 
-    async fn get_query(&self) -> Option<HvacRequest> {
+    /// Runs the same stages as `query` and reports which one decided, without
+    /// touching `last_result`, clearing a finished oneshot setpoint or running
+    /// the Lua script
+    pub async fn trace(&self) -> EvaluationTrace {
+        self.evaluate(true).await
+    }
+
+    async fn evaluate(&self, dry_run: bool) -> EvaluationTrace {
+        let primary_probe = self.probes.primary().await;
+        let mut trace = EvaluationTrace {
+            stage: EvaluationStage::None,
+            request: None,
+            primary_probe: primary_probe.as_ref().map(|probe| probe.value()),
+            rule: None,
+            weights: None,
+        };
+
         // Check if there's an override pulse
         if let Some(request) = self.override_pulse.evaluate() {
-            return Some(request);
+            return trace.decided(EvaluationStage::OverridePulse, Some(request));
         }
 
         // Check if the big succ is running
         if self.fan_state.big_succ().await {
-            return Some(HvacRequest::Off);
+            return trace.decided(EvaluationStage::BigSucc, Some(HvacRequest::Off));
         }
 
         // Execute a oneshot setpoint if it exists
         'oneshot: {
             let Some(setpoint) = self.oneshot_setpoint.get() else { break 'oneshot };
 
-            // The primary probe must be available
-            let Some(primary_probe) = self.probes.get("primary").await else { break 'oneshot };
+            // The target probe must be available
+            let probe = if setpoint.probe == PRIMARY_PROBE {
+                primary_probe.clone()
+            } else {
+                self.probes.get(&setpoint.probe).await
+            };
+            let Some(probe) = probe else { break 'oneshot };
 
             // Check if the setpoint is completed
             match (
                 setpoint.comparison,
-                primary_probe.value().partial_cmp(&setpoint.setpoint),
+                probe.value().partial_cmp(&setpoint.setpoint),
             ) {
-                (OneshotOrdering::Less, Some(cmp::Ordering::Less)) => {
-                    self.oneshot_setpoint.set(None);
-                    break 'oneshot;
-                }
-                (OneshotOrdering::Greater, Some(cmp::Ordering::Greater)) => {
-                    self.oneshot_setpoint.set(None);
+                (OneshotOrdering::Less, Some(cmp::Ordering::Less))
+                | (OneshotOrdering::Greater, Some(cmp::Ordering::Greater)) => {
+                    if !dry_run {
+                        self.clear_oneshot_setpoint();
+                    }
                     break 'oneshot;
                 }
                 _ => (),
             }
 
             // We are not complete, execute action
-            return Some(setpoint.action);
+            return trace.decided(EvaluationStage::OneshotSetpoint, Some(setpoint.action));
+        }
+
+        // Away mode parks the thermostat in its band instead of following the rules
+        if self.away_mode.is_active() {
+            let request = primary_probe
+                .and_then(|probe| self.away_mode.evaluate(self.mode(), probe.value()));
+            return trace.decided(EvaluationStage::AwayMode, request);
         }
 
         if self.lua.is_loaded().await {
+            // The script can write overrides and persisted state, so a trace
+            // only reports that it would have been asked
+            if dry_run {
+                return trace.decided(EvaluationStage::Lua, None);
+            }
             if let Some(request) = self.eval_lua().await {
-                return Some(request);
+                return trace.decided(EvaluationStage::Lua, Some(request));
             }
-        } else {
-            if let Some(request) = self.timed_ruleset.evaluate(self).await {
-                return Some(request);
+        } else if let Some(rule) = self.timed_ruleset.find_applicable_rule() {
+            let threshold = self.comfort_profiles.threshold(self.timed_ruleset.threshold);
+            let (on_weight, off_weight) = rule.weights(self).await;
+            trace.rule = Some(rule.clone());
+            trace.weights = Some(RuleWeights {
+                on_weight,
+                off_weight,
+                threshold,
+            });
+
+            if let Some(request) =
+                TimedRuleSet::decide(self.mode(), on_weight, off_weight, threshold)
+            {
+                return trace.decided(EvaluationStage::Ruleset, Some(request));
             }
         }
 
-        None
+        trace
     }
 
     pub fn mode(&self) -> HvacRequest {
         self.mode.load()
     }
 
+    fn clear_oneshot_setpoint(&self) {
+        self.oneshot_setpoint.set(None);
+        self.live.send(LiveUpdate::OneshotSetpoint { state: None });
+    }
+
     pub async fn validate_lua_script(
         &self,
         script: String,
-    ) -> anyhow::Result<(Option<HvacRequest>, BTreeSet<String>)> {
-        self.lua.validate(script, self.clone()).await
+        explain: bool,
+    ) -> anyhow::Result<Validation> {
+        self.lua.validate(script, self.clone(), explain).await
     }
 
     pub async fn set_active_lua_script(&self, script: String) -> anyhow::Result<()> {
         self.lua.load(script, self.clone()).await
     }
 
+    pub async fn clear_active_lua_script(&self) -> anyhow::Result<()> {
+        self.lua.unload().await
+    }
+
     async fn eval_lua(&self) -> Option<HvacRequest> {
         if !self.lua.is_loaded().await {
             return None;
@@ -162,6 +236,48 @@ impl models::mixer::Mixer for MixerState {
     }
 }
 
+/// Which stage of `MixerState::query` made the call
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvaluationStage {
+    OverridePulse,
+    BigSucc,
+    OneshotSetpoint,
+    AwayMode,
+    /// The active Lua script, which a trace doesn't run
+    Lua,
+    Ruleset,
+    /// Nothing decided, the last result is kept
+    None,
+}
+
+#[derive(Clone, Serialize)]
+pub struct EvaluationTrace {
+    pub stage: EvaluationStage,
+    pub request: Option<HvacRequest>,
+    pub primary_probe: Option<f32>,
+    /// Only filled in when the ruleset was consulted
+    pub rule: Option<TimedRule>,
+    pub weights: Option<RuleWeights>,
+}
+
+impl EvaluationTrace {
+    fn decided(self, stage: EvaluationStage, request: Option<HvacRequest>) -> Self {
+        EvaluationTrace {
+            stage,
+            request,
+            ..self
+        }
+    }
+}
+
+#[derive(Copy, Clone, Serialize)]
+pub struct RuleWeights {
+    pub on_weight: f32,
+    pub off_weight: f32,
+    pub threshold: f32,
+}
+
 #[derive(Clone)]
 pub struct Mixer {
     state: Arc<ArcCell<MixerState>>,
@@ -178,6 +294,10 @@ impl Mixer {
         self.state().query().await
     }
 
+    pub async fn query_traced(&self) -> (HvacRequest, EvaluationTrace) {
+        self.state().query_traced().await
+    }
+
     pub fn state(&self) -> Arc<MixerState> {
         self.state.get()
     }
@@ -223,3 +343,82 @@ impl AtomicHvacRequest {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        away_mode::{AwayBand, AwayModeState},
+        oneshot_setpoint::OneshotSetpointState,
+        *,
+    };
+
+    #[tokio::test]
+    #[ignore]
+    async fn clearing_away_mode_hands_back_to_the_rules() {
+        let redis = RedisConn::scratch().await;
+        let probes = Probes::unfed(&[PRIMARY_PROBE]).await;
+        // Cold enough for both the away band and the default rules to heat
+        probes.get(PRIMARY_PROBE).await.unwrap().update(5.0);
+        let mode = Arc::new(AtomicHvacRequest::new());
+        mode.store(HvacRequest::Heat);
+        let mixer = MixerState::new(
+            &redis,
+            &MqttClient::loopback(false),
+            probes,
+            mode,
+            FanState::default(),
+            LiveUpdates::new(),
+        )
+        .await;
+
+        let away = |active| AwayModeState {
+            active,
+            band: AwayBand::default(),
+        };
+        mixer.away_mode.set(&redis, away(true)).await.unwrap();
+        let trace = mixer.trace().await;
+        assert_eq!(trace.stage, EvaluationStage::AwayMode);
+        assert_eq!(trace.request, Some(HvacRequest::Heat));
+
+        mixer.away_mode.set(&redis, away(false)).await.unwrap();
+        let trace = mixer.trace().await;
+        assert_eq!(trace.stage, EvaluationStage::Ruleset);
+        assert!(trace.rule.is_some());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn a_non_primary_probe_completes_the_oneshot() {
+        let redis = RedisConn::scratch().await;
+        let probes = Probes::unfed(&[PRIMARY_PROBE, "bedroom"]).await;
+        let mixer = MixerState::new(
+            &redis,
+            &MqttClient::loopback(false),
+            probes.clone(),
+            Arc::new(AtomicHvacRequest::new()),
+            FanState::default(),
+            LiveUpdates::new(),
+        )
+        .await;
+        mixer.oneshot_setpoint.set(Some(OneshotSetpointState {
+            setpoint: 21.0,
+            comparison: OneshotOrdering::Less,
+            action: HvacRequest::Cool,
+            probe: "bedroom".into(),
+        }));
+
+        // The primary probe being past the setpoint doesn't matter
+        probes.get(PRIMARY_PROBE).await.unwrap().update(19.0);
+        probes.get("bedroom").await.unwrap().update(23.0);
+        let trace = mixer.trace().await;
+        assert_eq!(trace.stage, EvaluationStage::OneshotSetpoint);
+        assert_eq!(trace.request, Some(HvacRequest::Cool));
+
+        probes.get("bedroom").await.unwrap().update(20.5);
+        assert_ne!(mixer.trace().await.stage, EvaluationStage::OneshotSetpoint);
+        // Only a real query clears it
+        assert!(mixer.oneshot_setpoint.get().is_some());
+        mixer.query().await;
+        assert!(mixer.oneshot_setpoint.get().is_none());
+    }
+}
@@ -1,6 +1,10 @@
+use models::{keys::ONESHOT_BOUNDS_KEY, PRIMARY_PROBE};
+use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 use std::sync::RwLock;
 
+use crate::RedisConn;
+
 use super::HvacRequest;
 
 pub struct OneshotSetpoint {
@@ -37,6 +41,76 @@ pub struct OneshotSetpointState {
     pub setpoint: f32,
     pub comparison: OneshotOrdering,
     pub action: HvacRequest,
+    /// Which probe has to reach the setpoint
+    #[serde(default = "default_probe")]
+    pub probe: String,
+}
+
+fn default_probe() -> String {
+    PRIMARY_PROBE.to_string()
+}
+
+/// The range of setpoints a oneshot is allowed to target
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct OneshotBounds {
+    /// Degrees Celcius
+    pub min: f32,
+    /// Degrees Celcius
+    pub max: f32,
 }
 
+impl OneshotBounds {
+    pub async fn load(redis: &RedisConn) -> Self {
+        let data: Option<String> = {
+            let mut redis = redis.get();
+            redis.get(ONESHOT_BOUNDS_KEY).await.ok().flatten()
+        };
+        data.and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn contains(&self, setpoint: f32) -> bool {
+        self.min <= setpoint && setpoint <= self.max
+    }
+}
 
+impl Default for OneshotBounds {
+    fn default() -> Self {
+        OneshotBounds {
+            min: 18.0,
+            max: 25.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_are_inclusive() {
+        let bounds = OneshotBounds::default();
+        assert!(bounds.contains(18.0));
+        assert!(bounds.contains(21.5));
+        assert!(bounds.contains(25.0));
+    }
+
+    #[test]
+    fn rejects_setpoints_outside_the_bounds() {
+        let bounds = OneshotBounds { min: 16.0, max: 22.0 };
+        assert!(!bounds.contains(15.9));
+        assert!(!bounds.contains(22.1));
+        assert!(!bounds.contains(f32::NAN));
+    }
+
+    #[test]
+    fn setpoints_without_a_probe_target_the_primary() {
+        let json = r#"{"setpoint":21.0,"comparison":"less","action":"cool"}"#;
+        let state: OneshotSetpointState = serde_json::from_str(json).unwrap();
+        assert_eq!(state.probe, PRIMARY_PROBE);
+
+        let json = r#"{"setpoint":21.0,"comparison":"less","action":"cool","probe":"bedroom"}"#;
+        let state: OneshotSetpointState = serde_json::from_str(json).unwrap();
+        assert_eq!(state.probe, "bedroom");
+    }
+}
@@ -1,8 +1,12 @@
 use std::sync::RwLock;
 
 use chrono::{DateTime, Utc};
+use models::keys::OVERRIDE_PULSE_KEY;
+use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 
+use crate::RedisConn;
+
 use super::HvacRequest;
 
 pub struct OverridePulse {
@@ -22,27 +26,126 @@ impl OverridePulse {
         }
     }
 
-    pub fn evaluate(&self) -> Option<HvacRequest> {
-        let current = self.get();
-        if let Some(current) = current {
-            if current.active_until > Utc::now() {
-                return Some(current.request);
-            }
+    /// Picks up the override saved before a restart, unless it ran out in
+    /// the meantime
+    pub async fn load(redis: &RedisConn) -> Self {
+        let data = {
+            let mut redis = redis.get();
+            redis.get::<_, String>(OVERRIDE_PULSE_KEY).await
+        };
+
+        OverridePulse {
+            state: RwLock::new(data.ok().and_then(|data| restore(&data, Utc::now()))),
         }
-        None
+    }
+
+    pub fn evaluate(&self) -> Option<HvacRequest> {
+        self.active().map(|current| current.request)
+    }
+
+    /// The current override, unless it has already run out
+    pub fn active(&self) -> Option<OverridePulseState> {
+        self.get().filter(|current| current.active_until > Utc::now())
     }
 
     pub fn get(&self) -> Option<OverridePulseState> {
         *self.state.read().unwrap()
     }
 
-    pub fn set(&self, state: Option<OverridePulseState>) {
+    pub async fn set(
+        &self,
+        redis: &RedisConn,
+        state: Option<OverridePulseState>,
+    ) -> anyhow::Result<()> {
+        {
+            let mut redis = redis.get();
+            match &state {
+                Some(state) => {
+                    let data = serde_json::to_string(state)?;
+                    let () = redis.set(OVERRIDE_PULSE_KEY, data).await?;
+                }
+                None => {
+                    let () = redis.del(OVERRIDE_PULSE_KEY).await?;
+                }
+            }
+        }
         *self.state.write().unwrap() = state;
+        Ok(())
     }
 }
 
+/// The saved override, if it's still running at `now`
+fn restore(data: &str, now: DateTime<Utc>) -> Option<OverridePulseState> {
+    serde_json::from_str::<OverridePulseState>(data)
+        .ok()
+        .filter(|state| state.active_until > now)
+}
+
 impl Default for OverridePulse {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mlua::prelude::*;
+
+    use super::*;
+
+    fn pulse(active_until: DateTime<Utc>) -> OverridePulse {
+        OverridePulse {
+            state: RwLock::new(Some(OverridePulseState {
+                active_until,
+                request: HvacRequest::Heat,
+            })),
+        }
+    }
+
+    #[test]
+    fn scripts_see_an_active_override() {
+        let pulse = pulse(Utc::now() + Duration::hours(1));
+        assert_eq!(pulse.evaluate(), Some(HvacRequest::Heat));
+
+        // The same conversion the `override_pulse` field does
+        let lua = Lua::new();
+        let value = lua.to_value(&pulse.active()).unwrap();
+        lua.globals().set("override_pulse", value).unwrap();
+        let request: String = lua.load("return override_pulse.request").eval().unwrap();
+        assert_eq!(request, "heat");
+    }
+
+    fn saved(active_until: DateTime<Utc>) -> String {
+        serde_json::to_string(&OverridePulseState {
+            active_until,
+            request: HvacRequest::Cool,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn running_pulse_reloads() {
+        let now = Utc::now();
+        let active_until = now + Duration::minutes(20);
+        let restored = restore(&saved(active_until), now).unwrap();
+        assert_eq!(restored.active_until, active_until);
+        assert_eq!(restored.request, HvacRequest::Cool);
+    }
+
+    #[test]
+    fn expired_pulse_is_dropped_on_reload() {
+        let now = Utc::now();
+        assert!(restore(&saved(now - Duration::minutes(1)), now).is_none());
+        assert!(restore(&saved(now), now).is_none());
+        assert!(restore("not json", now).is_none());
+    }
+
+    #[test]
+    fn expired_overrides_are_hidden() {
+        let pulse = pulse(Utc::now() - Duration::seconds(1));
+        assert!(pulse.get().is_some());
+        assert!(pulse.active().is_none());
+        assert_eq!(pulse.evaluate(), None);
+    }
+}
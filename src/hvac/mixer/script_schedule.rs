@@ -0,0 +1,220 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime};
+use models::keys::{LUA_CURRENT_NAME, LUA_CURRENT_SCRIPT, LUA_SAVED_SCRIPTS, LUA_SCRIPT_SCHEDULE};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+
+use crate::RedisConn;
+
+use super::{timed_rule::DaySet, Mixer};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Which saved script should be active when. The first matching window wins,
+/// and nothing is changed while no window matches.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ScriptSchedule {
+    pub windows: Vec<ScriptWindow>,
+}
+
+/// Every bound is optional, a window without any applies all the time
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScriptWindow {
+    /// Name of a script in the saved scripts hash
+    pub script: String,
+    #[serde(default)]
+    pub days: Option<DaySet>,
+    /// Wraps past midnight when `end_time` is earlier than `start_time`
+    #[serde(default)]
+    pub start_time: Option<NaiveTime>,
+    #[serde(default)]
+    pub end_time: Option<NaiveTime>,
+    /// Inclusive
+    #[serde(default)]
+    pub start_date: Option<NaiveDate>,
+    /// Inclusive
+    #[serde(default)]
+    pub end_date: Option<NaiveDate>,
+}
+
+impl ScriptWindow {
+    pub fn contains(&self, now: DateTime<Local>) -> bool {
+        let date = now.date_naive();
+        let time = now.time();
+
+        if self.days.is_some_and(|days| !days.enabled(now.weekday())) {
+            return false;
+        }
+        if self.start_date.is_some_and(|start| date < start)
+            || self.end_date.is_some_and(|end| date > end)
+        {
+            return false;
+        }
+
+        match (self.start_time, self.end_time) {
+            (Some(start), Some(end)) if end < start => time >= start || time < end,
+            (start, end) => {
+                start.is_none_or(|start| time >= start) && end.is_none_or(|end| time < end)
+            }
+        }
+    }
+}
+
+impl ScriptSchedule {
+    pub async fn load(redis: &RedisConn) -> anyhow::Result<Self> {
+        let data: Option<String> = {
+            let mut redis = redis.get();
+            redis.get(LUA_SCRIPT_SCHEDULE).await?
+        };
+
+        Ok(match data {
+            Some(data) => serde_json::from_str(&data)?,
+            None => Default::default(),
+        })
+    }
+
+    pub async fn save(&self, redis: &RedisConn) -> anyhow::Result<()> {
+        let data = serde_json::to_string(self)?;
+        let mut redis = redis.get();
+        let () = redis.set(LUA_SCRIPT_SCHEDULE, data).await?;
+        Ok(())
+    }
+
+    /// Name of the script that should be active at `now`, if any
+    pub fn script_at(&self, now: DateTime<Local>) -> Option<&str> {
+        self.windows
+            .iter()
+            .find(|window| window.contains(now))
+            .map(|window| window.script.as_str())
+    }
+}
+
+/// Only switches scripts when the scheduled name changes, so a script
+/// activated by hand stays put until the next window starts
+pub async fn run_scheduler(redis: RedisConn, mixer: Mixer) {
+    let mut last_scheduled: Option<String> = None;
+    loop {
+        let scheduled = ScriptSchedule::load(&redis)
+            .await
+            .ok()
+            .and_then(|schedule| schedule.script_at(Local::now()).map(String::from));
+
+        match &scheduled {
+            Some(name) if scheduled != last_scheduled => {
+                match activate_saved_script(&redis, &mixer, name).await {
+                    Ok(()) => last_scheduled = scheduled.clone(),
+                    Err(error) => {
+                        tracing::warn!(script = %name, ?error, "Failed to activate scheduled script")
+                    }
+                }
+            }
+            Some(_) => {}
+            None => last_scheduled = None,
+        }
+
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+async fn activate_saved_script(redis: &RedisConn, mixer: &Mixer, name: &str) -> anyhow::Result<()> {
+    let script: Option<String> = {
+        let mut redis = redis.get();
+        redis.hget(LUA_SAVED_SCRIPTS, name).await?
+    };
+    let Some(script) = script else {
+        anyhow::bail!("No saved script named {name:?}");
+    };
+
+    mixer.state().set_active_lua_script(script.clone()).await?;
+
+    let mut redis = redis.get();
+    let () = redis::pipe()
+        .atomic()
+        .set(LUA_CURRENT_SCRIPT, script)
+        .ignore()
+        .set(LUA_CURRENT_NAME, name)
+        .ignore()
+        .query_async(&mut redis)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Weekday};
+
+    use super::*;
+
+    fn window(script: &str) -> ScriptWindow {
+        ScriptWindow {
+            script: script.into(),
+            days: None,
+            start_time: None,
+            end_time: None,
+            start_date: None,
+            end_date: None,
+        }
+    }
+
+    fn hms(hour: u32, min: u32) -> Option<NaiveTime> {
+        NaiveTime::from_hms_opt(hour, min, 0)
+    }
+
+    fn ymd(year: i32, month: u32, day: u32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(year, month, day)
+    }
+
+    /// 2024-01-15 is a Monday
+    fn at(month: u32, day: u32, hour: u32, min: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, month, day, hour, min, 0)
+            .single()
+            .unwrap()
+    }
+
+    fn schedule() -> ScriptSchedule {
+        ScriptSchedule {
+            windows: vec![
+                ScriptWindow {
+                    days: Some(DaySet::from_days([Weekday::Sat, Weekday::Sun])),
+                    ..window("weekend")
+                },
+                ScriptWindow {
+                    start_time: hms(22, 0),
+                    end_time: hms(6, 0),
+                    ..window("night")
+                },
+                ScriptWindow {
+                    start_date: ymd(2024, 1, 1),
+                    end_date: ymd(2024, 3, 31),
+                    ..window("winter")
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn picks_the_first_matching_window() {
+        let schedule = schedule();
+        // Saturday night matches both, the earlier window wins
+        assert_eq!(schedule.script_at(at(1, 20, 23, 0)), Some("weekend"));
+        assert_eq!(schedule.script_at(at(1, 15, 23, 0)), Some("night"));
+        assert_eq!(schedule.script_at(at(1, 16, 3, 0)), Some("night"));
+        assert_eq!(schedule.script_at(at(1, 15, 12, 0)), Some("winter"));
+        assert_eq!(schedule.script_at(at(3, 29, 12, 0)), Some("winter"));
+    }
+
+    #[test]
+    fn nothing_scheduled_outside_every_window() {
+        let schedule = schedule();
+        assert_eq!(schedule.script_at(at(4, 1, 12, 0)), None);
+        assert_eq!(schedule.script_at(at(4, 2, 6, 0)), None);
+        assert_eq!(ScriptSchedule::default().script_at(at(1, 15, 12, 0)), None);
+    }
+
+    #[test]
+    fn unbounded_window_always_applies() {
+        assert!(window("always").contains(at(6, 1, 0, 0)));
+    }
+}
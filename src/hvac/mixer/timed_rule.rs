@@ -1,10 +1,12 @@
+use models::{keys::CURRENT_RULESET_KEY, set_point};
 use redis::AsyncCommands;
 
 use crate::RedisConn;
 
+use super::lua_controller;
+
 pub use models::timed_rule::{DaySet, TimedRule, TimedRuleSet};
 
-pub const CURRENT_RULESET_KEY: &str = "thermostat.config.timedruleset";
 const DEFAULT_CONFIG: &str = "{\"rules\":[
     {\"set_points\":[{\"min_temp\":22.0,\"max_temp\":22.5,\"probe\":\"primary\",\"weight\":1.0}],
     \"start_time\":\"06:00:00\",\"days_enabled\":255},
@@ -25,7 +27,9 @@ pub async fn load(redis: &RedisConn) -> TimedRuleSet {
         }
     };
 
+    set_point::take_adjustments();
     let mut ruleset: TimedRuleSet = serde_json::from_str(&data).ok().unwrap_or_default();
+    lua_controller::set_ruleset_issues(set_point::take_adjustments());
     ruleset.rules.sort_by_key(|rule| rule.start_time);
     ruleset
 }
@@ -1,38 +1,244 @@
-use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
 
+use models::keys::{
+    self, CONFIG_MODE, CONFIG_PINSTATE_HISTORY_MAX_LEN, CONFIG_PUBLISH_ON_CHANGE,
+    PINSTATE_HISTORY, PROBE_ENDPOINTS, PROBE_MIN_INTERVALS, PROBE_STALE_AFTER,
+};
 use redis::AsyncCommands;
-use tokio::sync::RwLock;
+use serde::Serialize;
+use tokio::sync::{watch, RwLock};
 
-use crate::{api::atticfan::FanState, mqtt::MqttClient, RedisConn};
+use crate::{
+    api::atticfan::FanState,
+    mqtt::{handler::HandlerId, MqttClient},
+    RedisConn,
+};
 
 use self::{
-    mixer::{AtomicHvacRequest, HvacRequest, Mixer, MixerState},
-    probe::Probe,
+    live::{LiveUpdate, LiveUpdates},
+    mixer::{
+        script_schedule, AtomicHvacRequest, EvaluationStage, EvaluationTrace, HvacRequest, Mixer,
+        MixerState,
+    },
+    probe::{Probe, ThrottleDecision},
+    sync_status::SyncTracker,
 };
 
+pub mod history;
+pub mod http_probe;
+pub mod live;
 pub mod mixer;
 pub mod probe;
+pub mod sync_status;
+
+pub use models::PRIMARY_PROBE;
+
+/// Changes are rare, so this is many months of history
+const DEFAULT_PINSTATE_HISTORY_MAX_LEN: isize = 20_000;
+
+/// Retained JSON describing the mixer's current decision, see `DecisionStatus`
+pub const DECISION_STATUS_TOPIC: &str = "home/thermostat/hvac/status";
+
+/// Moves a probe's config and history from `ARGV[1]` to `ARGV[2]` in one go.
+/// KEYS are the endpoint, min interval and stale after hashes, then the old
+/// and new history. Returns 0 without changing anything if both names have
+/// history, since `delete_probe` leaves it behind.
+const RENAME_PROBE_SCRIPT: &str = r#"
+    if redis.call('EXISTS', KEYS[4]) == 1 and redis.call('RENAMENX', KEYS[4], KEYS[5]) == 0 then
+        return 0
+    end
+    redis.call('HSET', KEYS[1], ARGV[2], ARGV[3])
+    redis.call('HDEL', KEYS[1], ARGV[1])
+    for _, hash in ipairs({ KEYS[2], KEYS[3] }) do
+        local value = redis.call('HGET', hash, ARGV[1])
+        if value then
+            redis.call('HSET', hash, ARGV[2], value)
+            redis.call('HDEL', hash, ARGV[1])
+        end
+    end
+    return 1
+"#;
+
+/// How often the thermostat unit is asked for its mode
+pub const MODE_POLL_INTERVAL: Duration = Duration::from_secs(500);
+/// The unit counts as offline once nothing has been heard for this long
+pub const MODE_STALE_AFTER: Duration = Duration::from_secs(3 * 500);
 
 #[derive(Clone)]
 pub struct HvacState {
     pub probes: Probes,
     pub mixer: Mixer,
     pub hvac_mode: Arc<AtomicHvacRequest>,
+    pub mode_last_seen: Arc<LastSeen>,
+    /// Follows every mode the unit reports, for waiting on a confirmation
+    pub mode_changes: watch::Receiver<HvacRequest>,
+    pub live: LiveUpdates,
+    pub sync: Arc<SyncTracker>,
 }
 
-pub const PROBE_ENDPOINTS: &str = "thermostat.config.probe_endpoints";
-pub const CONFIG_MODE: &str = "thermostat.config.mode";
-pub const PROBE_HISTORY: &str = "thermostat.probes.history";
-pub const PINSTATE_HISTORY: &str = "thermostat.pinstate.history";
+#[derive(Default)]
+pub struct LastSeen(Mutex<Option<DateTime<Utc>>>);
+
+impl LastSeen {
+    pub fn mark(&self) {
+        *self.0.lock().unwrap() = Some(Utc::now());
+    }
+
+    pub fn get(&self) -> Option<DateTime<Utc>> {
+        *self.0.lock().unwrap()
+    }
+
+    pub fn within(&self, max_age: Duration) -> bool {
+        self.get()
+            .and_then(|seen| (Utc::now() - seen).to_std().ok())
+            .is_some_and(|age| age <= max_age)
+    }
+}
+
+/// Published to `DECISION_STATUS_TOPIC` whenever the request or the stage that
+/// decided it changes
+#[derive(Serialize)]
+struct DecisionStatus {
+    request: HvacRequest,
+    stage: EvaluationStage,
+    /// Celsius, whatever the mixer used as the primary probe at the time
+    primary_temp: Option<f32>,
+}
+
+/// Keeps track of the last `DecisionStatus` that went out
+#[derive(Default)]
+struct DecisionStatusPublisher {
+    last_published: Option<(HvacRequest, EvaluationStage)>,
+}
+
+impl DecisionStatusPublisher {
+    async fn update(&mut self, mqtt: &MqttClient, request: HvacRequest, trace: &EvaluationTrace) {
+        if self.last_published == Some((request, trace.stage)) {
+            return;
+        }
+        let status = DecisionStatus {
+            request,
+            stage: trace.stage,
+            primary_temp: trace.primary_probe,
+        };
+        if let Ok(status) = serde_json::to_vec(&status) {
+            mqtt.publish_retained(DECISION_STATUS_TOPIC, &status).await;
+            self.last_published = Some((request, trace.stage));
+        }
+    }
+}
+
+/// A probe that only reports on change still gets a history point this often,
+/// so the spacing keeps up with elapsed time
+const HISTORY_MAX_GAP_MS: i64 = 5 * 60 * 1000;
+
+/// What the historian should push on top of `last`, if anything. A fresh
+/// update is recorded at its own time, a held value is repeated once the gap
+/// reaches `HISTORY_MAX_GAP_MS`.
+fn next_history_entry(
+    last: Option<&str>,
+    value: f32,
+    last_update: i64,
+    now: i64,
+) -> Option<String> {
+    let last_time = last
+        .and_then(|last| last.split(':').next())
+        .and_then(|time| time.parse::<i64>().ok())
+        .unwrap_or(0);
+
+    if last_update > last_time {
+        Some(format!("{last_update}:{value}"))
+    } else if now - last_time >= HISTORY_MAX_GAP_MS {
+        // Nothing new, repeat the held value
+        Some(format!("{now}:{value}"))
+    } else {
+        None
+    }
+}
+
+/// Pushes `state` onto the pinstate history unless it's what was last
+/// recorded, keeping only the newest `max_len` entries
+async fn record_pinstate(
+    redis: &RedisConn,
+    state: HvacRequest,
+    now: i64,
+    max_len: isize,
+) -> redis::RedisResult<()> {
+    let mut redis = redis.get();
+    let latest: Option<String> = redis.lindex(PINSTATE_HISTORY, 0).await?;
+    if !is_pinstate_change(latest.as_deref(), state) {
+        return Ok(());
+    }
+
+    let state = state.payload_str();
+    redis::pipe()
+        .lpush(PINSTATE_HISTORY, format!("{state}:{now}"))
+        .ignore()
+        .ltrim(PINSTATE_HISTORY, 0, max_len - 1)
+        .ignore()
+        .query_async(&mut redis)
+        .await
+}
+
+/// Whether `state` differs from the `state:time` entry last recorded
+fn is_pinstate_change(latest: Option<&str>, state: HvacRequest) -> bool {
+    let latest_state = latest.and_then(|latest| {
+        let (state, _time) = latest.split_once(':')?;
+        HvacRequest::from_payload(state.as_bytes())
+    });
+    latest_state != Some(state)
+}
+
+/// Decides when the mix sender republishes the remote state
+struct RemoteStatePublisher {
+    publish_on_change: bool,
+    last_published: Option<(HvacRequest, Instant)>,
+}
+
+impl RemoteStatePublisher {
+    /// Republish now and then even without a change, in case the remote
+    /// missed one
+    const KEEPALIVE: Duration = Duration::from_secs(5 * 60);
+
+    fn new(publish_on_change: bool) -> Self {
+        RemoteStatePublisher {
+            publish_on_change,
+            last_published: None,
+        }
+    }
+
+    /// Whether `request` should go out at `now`, marking it published if so
+    fn should_publish(&mut self, request: HvacRequest, now: Instant) -> bool {
+        let unchanged = matches!(
+            self.last_published,
+            Some((last, at)) if last == request && now - at < Self::KEEPALIVE
+        );
+        if self.publish_on_change && unchanged {
+            return false;
+        }
+        self.last_published = Some((request, now));
+        true
+    }
+}
 
 pub async fn initialize(
     mqtt: &MqttClient,
     redis: &RedisConn,
     fan_state: &FanState,
 ) -> anyhow::Result<HvacState> {
+    let live = LiveUpdates::new();
+    let sync = Arc::new(SyncTracker::default());
+
     // Create the primary probe
-    let probes: Probes = Default::default();
-    init_probe(&probes, mqtt, Probe::new("primary", "home/thermostat/temp")).await;
+    let probes = Probes::new(live.clone());
+    init_probe(&probes, redis, mqtt, Probe::new(PRIMARY_PROBE, "home/thermostat/temp")).await;
 
     // Get additional configured probes
     let probe_endpoints: HashMap<String, String> = {
@@ -40,11 +246,12 @@ pub async fn initialize(
         redis.hgetall(PROBE_ENDPOINTS).await.unwrap_or_default()
     };
     for (name, endpoint) in probe_endpoints {
-        init_probe(&probes, mqtt, Probe::new(name, endpoint)).await;
+        init_probe(&probes, redis, mqtt, Probe::new(name, endpoint)).await;
     }
 
     // Create a handler for the HVAC Mode
     let hvac_mode = Arc::new(AtomicHvacRequest::new());
+    let mode_last_seen = Arc::new(LastSeen::default());
 
     // Try to get the last known hvac mode first
     hvac_mode.store(
@@ -57,13 +264,20 @@ pub async fn initialize(
         .unwrap_or(HvacRequest::Heat),
     );
 
+    let (mode_sender, mode_changes) = watch::channel(hvac_mode.load());
+
     // Set up a handler to request it from the thermostat unit
     mqtt.subscribe("home/thermostat/hvac/mode").await;
     {
         let hvac_mode = hvac_mode.clone();
+        let mode_last_seen = mode_last_seen.clone();
+        let live = live.clone();
         mqtt.handle("home/thermostat/hvac/mode", move |_, payload| {
             if let Some(mode) = HvacRequest::from_payload(payload) {
                 hvac_mode.store(mode);
+                mode_last_seen.mark();
+                mode_sender.send_replace(mode);
+                live.send(LiveUpdate::Mode { mode });
             }
         })
         .await;
@@ -75,7 +289,7 @@ pub async fn initialize(
         crate::spawn("hvac_mode_checker", async move {
             loop {
                 mqtt.publish("home/thermostat/hvac/mode/get", b"").await;
-                tokio::time::sleep(Duration::from_secs(500)).await;
+                tokio::time::sleep(MODE_POLL_INTERVAL).await;
             }
         });
     }
@@ -87,6 +301,7 @@ pub async fn initialize(
         probes.clone(),
         hvac_mode.clone(),
         fan_state.clone(),
+        live.clone(),
     )
     .await;
     let mixer = Mixer::new(mixer_state);
@@ -95,11 +310,30 @@ pub async fn initialize(
     {
         let mqtt = mqtt.clone();
         let mixer = mixer.clone();
+        let sync = sync.clone();
+        let publish_on_change = {
+            let mut redis = redis.get();
+            redis
+                .get::<_, Option<bool>>(CONFIG_PUBLISH_ON_CHANGE)
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or(false)
+        };
         crate::spawn("hvac_state_setter", async move {
+            let mut remote_state = RemoteStatePublisher::new(publish_on_change);
+            let mut status = DecisionStatusPublisher::default();
             loop {
-                let request = mixer.query().await;
-                mqtt.publish("home/thermostat/hvac/remotestate/set", request.payload())
-                    .await;
+                let (request, trace) = mixer.query_traced().await;
+                sync.record_commanded(request);
+
+                // For anything else in the house that wants to know why
+                status.update(&mqtt, request, &trace).await;
+
+                if remote_state.should_publish(request, Instant::now()) {
+                    mqtt.publish("home/thermostat/hvac/remotestate/set", request.payload())
+                        .await;
+                }
                 tokio::time::sleep(Duration::from_secs(10)).await;
             }
         });
@@ -124,17 +358,18 @@ pub async fn initialize(
                     if value.is_nan() {
                         continue;
                     }
-                    let history_key = format!("{PROBE_HISTORY}:{}", probe.name());
-                    let data = format!(
-                        "{time}:{value}",
-                        value = probe.value(),
-                        time = probe.last_update()
-                    );
-
-                    let Ok(last_value) = redis.lindex::<_, String>(&history_key, 0).await else {
+                    let history_key = keys::probe_history(probe.name());
+
+                    // An empty list comes back as nil, which is how a new
+                    // probe gets its first entry
+                    let Ok(last_value) = redis.lindex::<_, Option<String>>(&history_key, 0).await
+                    else {
                         continue;
                     };
-                    if last_value != data {
+                    let now = chrono::Utc::now().timestamp_millis();
+                    let data =
+                        next_history_entry(last_value.as_deref(), value, probe.last_update(), now);
+                    if let Some(data) = data {
                         let Ok(()) = redis.lpush(&history_key, data).await else {
                             continue;
                         };
@@ -147,34 +382,53 @@ pub async fn initialize(
         });
     }
 
+    // Swap saved Lua scripts in and out on their schedule
+    {
+        let redis = redis.clone();
+        let mixer = mixer.clone();
+        crate::spawn("lua_script_scheduler", script_schedule::run_scheduler(redis, mixer));
+    }
+
+    // Drop unparseable history entries once a day so they don't hide gaps
+    {
+        let redis = redis.clone();
+        let probes = probes.clone();
+        crate::spawn("probe_history_compactor", async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(60 * 60 * 24)).await;
+                for probe in probes.keys().await {
+                    history::compact_probe_history(&redis, &probe, true).await.ok();
+                }
+            }
+        });
+    }
+
     // Create the state historian
     {
         let redis = redis.clone();
         let mqtt = mqtt.clone();
+        let live = live.clone();
+        let sync = sync.clone();
+        let max_len = {
+            let mut redis = redis.get();
+            redis
+                .get::<_, Option<isize>>(CONFIG_PINSTATE_HISTORY_MAX_LEN)
+                .await
+                .ok()
+                .flatten()
+                .filter(|&len| len > 0)
+                .unwrap_or(DEFAULT_PINSTATE_HISTORY_MAX_LEN)
+        };
 
         mqtt.subscribe("home/thermostat/hvac/pinstate").await;
         mqtt.handle("home/thermostat/hvac/pinstate", move |_, payload| {
             let now = chrono::Utc::now().timestamp_millis();
             if let Some(state) = HvacRequest::from_payload(payload) {
+                live.send(LiveUpdate::Pinstate { state });
+                sync.record_reported(state);
                 let redis = redis.clone();
                 crate::spawn("record_pinstate", async move {
-                    let mut redis = redis.get();
-                    let Ok(latest) = redis.lindex::<_, Option<String>>(PINSTATE_HISTORY, 0).await
-                    else {
-                        return;
-                    };
-                    let state = state.payload_str();
-                    if latest
-                        .map(|latest| latest.chars().nth(0) != state.chars().nth(0))
-                        .unwrap_or(true)
-                    {
-                        let Ok(()) = redis
-                            .lpush(PINSTATE_HISTORY, format!("{state}:{now}"))
-                            .await
-                        else {
-                            return;
-                        };
-                    }
+                    record_pinstate(&redis, state, now, max_len).await.ok();
                 });
             }
         })
@@ -219,15 +473,30 @@ pub async fn initialize(
         probes,
         mixer,
         hvac_mode,
+        mode_last_seen,
+        mode_changes,
+        live,
+        sync,
     })
 }
 
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct Probes {
     probes: Arc<RwLock<HashMap<String, Probe>>>,
+    /// The MQTT handler feeding each probe, so it can be detached again
+    handlers: Arc<RwLock<HashMap<String, HandlerId>>>,
+    live: LiveUpdates,
 }
 
 impl Probes {
+    pub fn new(live: LiveUpdates) -> Self {
+        Probes {
+            probes: Default::default(),
+            handlers: Default::default(),
+            live,
+        }
+    }
+
     pub async fn get(&self, name: &str) -> Option<Probe> {
         self.probes.read().await.get(name).cloned()
     }
@@ -239,39 +508,572 @@ impl Probes {
         name: &str,
         endpoint: &str,
     ) -> anyhow::Result<()> {
-        let mut redis = redis.get();
-        let () = redis.hset(PROBE_ENDPOINTS, name, endpoint).await?;
-        init_probe(self, mqtt, Probe::new(name, endpoint)).await;
+        if !valid_probe_name(name) {
+            anyhow::bail!("Invalid probe name {name:?}");
+        }
+        if let Err(problem) = validate_endpoint(endpoint) {
+            anyhow::bail!("Invalid endpoint {endpoint:?}: {problem}");
+        }
+        if self.get(name).await.is_some() {
+            anyhow::bail!("A probe named {name:?} already exists");
+        }
+
+        {
+            let mut redis = redis.get();
+            let () = redis.hset(PROBE_ENDPOINTS, name, endpoint).await?;
+        }
+        init_probe(self, redis, mqtt, Probe::new(name, endpoint)).await;
         Ok(())
     }
 
-    pub async fn delete_probe(&self, redis: &RedisConn, name: &str) -> anyhow::Result<()> {
-        self.probes.write().await.remove(name);
+    pub async fn delete_probe(
+        &self,
+        redis: &RedisConn,
+        mqtt: &MqttClient,
+        name: &str,
+    ) -> anyhow::Result<()> {
+        self.detach_probe(mqtt, name).await;
         let mut redis = redis.get();
         let () = redis.hdel(PROBE_ENDPOINTS, name).await?;
         Ok(())
     }
 
+    /// Moves the endpoint, min interval and history over to `new`. The
+    /// primary probe isn't configurable, so it can't be renamed either.
+    /// `false` means `new` still has a deleted probe's history, which would
+    /// be overwritten, so nothing was changed.
+    pub async fn rename_probe(
+        &self,
+        redis: &RedisConn,
+        mqtt: &MqttClient,
+        old: &str,
+        new: &str,
+    ) -> anyhow::Result<bool> {
+        let probe = self.rename_source(old, new).await?;
+
+        let renamed: bool = redis::Script::new(RENAME_PROBE_SCRIPT)
+            .key(PROBE_ENDPOINTS)
+            .key(PROBE_MIN_INTERVALS)
+            .key(PROBE_STALE_AFTER)
+            .key(keys::probe_history(old))
+            .key(keys::probe_history(new))
+            .arg(old)
+            .arg(new)
+            .arg(probe.endpoint())
+            .invoke_async(&mut redis.get())
+            .await?;
+        if !renamed {
+            return Ok(false);
+        }
+
+        self.detach_probe(mqtt, old).await;
+        init_probe(self, redis, mqtt, Probe::new(new, probe.endpoint())).await;
+        Ok(true)
+    }
+
+    /// The probe being renamed, if `new` is free to take over from it
+    async fn rename_source(&self, old: &str, new: &str) -> anyhow::Result<Probe> {
+        if old == PRIMARY_PROBE || new == PRIMARY_PROBE {
+            anyhow::bail!("The primary probe can't be renamed");
+        }
+        let Some(probe) = self.get(old).await else {
+            anyhow::bail!("No probe named {old:?}");
+        };
+        if self.get(new).await.is_some() {
+            anyhow::bail!("A probe named {new:?} already exists");
+        }
+        Ok(probe)
+    }
+
+    async fn detach_probe(&self, mqtt: &MqttClient, name: &str) {
+        let probe = self.probes.write().await.remove(name);
+        let handler = self.handlers.write().await.remove(name);
+        if let (Some(probe), Some(handler)) = (probe, handler) {
+            mqtt.unhandle(probe.endpoint(), handler).await;
+        }
+    }
+
+    /// The primary probe, or while it has no reading, whichever other probe
+    /// comes first by name and has one
+    pub async fn primary(&self) -> Option<Probe> {
+        let probes = self.probes.read().await;
+        let has_reading = |probe: &&Probe| !probe.value().is_nan();
+        probes
+            .get(PRIMARY_PROBE)
+            .filter(has_reading)
+            .or_else(|| {
+                let mut others: Vec<_> = probes.values().filter(has_reading).collect();
+                others.sort_by(|a, b| a.name().cmp(b.name()));
+                others.into_iter().next()
+            })
+            .cloned()
+    }
+
     pub async fn keys(&self) -> Vec<String> {
         self.probes.read().await.keys().cloned().collect()
     }
+
+    /// Name to endpoint of every probe, the primary one included
+    pub async fn endpoints(&self) -> BTreeMap<String, String> {
+        self.probes
+            .read()
+            .await
+            .iter()
+            .map(|(name, probe)| (name.clone(), probe.endpoint().to_string()))
+            .collect()
+    }
 }
 
-async fn init_probe(probes: &Probes, mqtt: &MqttClient, probe: Probe) {
+#[cfg(test)]
+impl Probes {
+    /// Probes on `home/<name>/temp` with nothing subscribed to feed them
+    pub async fn unfed(names: &[&str]) -> Probes {
+        let probes = Probes::new(LiveUpdates::new());
+        for name in names {
+            let probe = Probe::new(*name, format!("home/{}/temp", name));
+            probes.probes.write().await.insert(name.to_string(), probe);
+        }
+        probes
+    }
+}
+
+/// Names end up in Redis keys after a `:`, and the primary probe isn't
+/// configurable
+pub fn valid_probe_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(':') && name != PRIMARY_PROBE
+}
+
+/// Either an HTTP URL to poll, or an MQTT topic to subscribe to. Wildcards
+/// aren't allowed since each probe reads a single sensor.
+pub fn validate_endpoint(endpoint: &str) -> Result<(), &'static str> {
+    if http_probe::is_http(endpoint) {
+        return http_probe::validate_endpoint(endpoint);
+    }
+
+    if endpoint.is_empty() {
+        Err("The topic can't be empty")
+    } else if endpoint.contains(['#', '+']) {
+        Err("The topic can't contain wildcards")
+    } else if endpoint.starts_with('/') || endpoint.ends_with('/') {
+        Err("The topic can't start or end with '/'")
+    } else if endpoint.contains("//") {
+        Err("The topic can't have empty levels")
+    } else if endpoint.contains('\0') || endpoint.chars().any(char::is_whitespace) {
+        Err("The topic can't contain whitespace or NUL characters")
+    } else {
+        Ok(())
+    }
+}
+
+async fn init_probe(probes: &Probes, redis: &RedisConn, mqtt: &MqttClient, probe: Probe) {
+    let min_interval: Option<u64> = {
+        let mut redis = redis.get();
+        redis
+            .hget(PROBE_MIN_INTERVALS, probe.name())
+            .await
+            .ok()
+            .flatten()
+    };
+    if let Some(ms) = min_interval {
+        probe.set_min_interval(Duration::from_millis(ms));
+    }
+    let stale_after: Option<u64> = {
+        let mut redis = redis.get();
+        redis
+            .hget(PROBE_STALE_AFTER, probe.name())
+            .await
+            .ok()
+            .flatten()
+    };
+    if let Some(ms) = stale_after {
+        probe.set_stale_after(Duration::from_millis(ms));
+    }
+
     probes
         .probes
         .write()
         .await
         .insert(probe.name().to_string(), probe.clone());
     let endpoint = probe.endpoint().to_owned();
+    let live = probes.live.clone();
+    let name = probe.name().to_string();
+
+    if http_probe::is_http(&endpoint) {
+        let probes = probes.clone();
+        crate::spawn("http_probe_poller", async move {
+            let client = http_probe::client();
+            // Stops once the probe is deleted or replaced by a rename
+            while probes
+                .get(probe.name())
+                .await
+                .is_some_and(|current| current.same(&probe))
+            {
+                match http_probe::fetch(&client, probe.endpoint()).await {
+                    Ok(temp) => offer_probe_update(&probe, &live, temp),
+                    Err(error) => {
+                        tracing::warn!(probe = %probe.name(), ?error, "Failed to poll HTTP probe")
+                    }
+                }
+                tokio::time::sleep(http_probe::POLL_INTERVAL).await;
+            }
+        });
+        return;
+    }
+
     mqtt.subscribe(&endpoint).await;
-    mqtt.handle(&endpoint, move |_topic, payload| {
-        if let Some(temp) = std::str::from_utf8(payload)
+    let handler = mqtt.handle(&endpoint, move |_topic, payload| {
+        let Some(temp) = std::str::from_utf8(payload)
             .ok()
             .and_then(|s| f32::from_str(s).ok())
-        {
-            probe.update(temp);
-        }
+        else {
+            return;
+        };
+
+        offer_probe_update(&probe, &live, temp);
     })
     .await;
+    probes.handlers.write().await.insert(name, handler);
+}
+
+fn offer_probe_update(probe: &Probe, live: &LiveUpdates, temp: f32) {
+    match probe.offer(temp) {
+        ThrottleDecision::Process => process_probe_update(probe, live, temp),
+        ThrottleDecision::Deferred(wait) => {
+            let probe = probe.clone();
+            let live = live.clone();
+            crate::spawn("probe_throttle", async move {
+                tokio::time::sleep(wait).await;
+                if let Some(temp) = probe.take_pending() {
+                    process_probe_update(&probe, &live, temp);
+                }
+            });
+        }
+        ThrottleDecision::Coalesced => {}
+    }
+}
+
+fn process_probe_update(probe: &Probe, live: &LiveUpdates, temp: f32) {
+    probe.update(temp);
+    live.send(LiveUpdate::Temperature {
+        probe: probe.name().to_string(),
+        value: temp,
+        time: probe.last_update(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the historian's checks every 5s over `minutes` of a value that
+    /// never changes after its first reading
+    fn stable_history(minutes: i64) -> Vec<i64> {
+        let first_reading = 1_000;
+        let mut history: Vec<String> = Vec::new();
+        for now in (first_reading..=first_reading + minutes * 60_000).step_by(5_000) {
+            let last = history.last().map(String::as_str);
+            if let Some(entry) = next_history_entry(last, 21.5, first_reading, now) {
+                history.push(entry);
+            }
+        }
+        history
+            .iter()
+            .map(|entry| entry.split(':').next().unwrap().parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn stable_values_are_recorded_at_a_regular_cadence() {
+        let times = stable_history(60);
+        assert_eq!(times.len(), 13);
+        for pair in times.windows(2) {
+            assert_eq!(pair[1] - pair[0], HISTORY_MAX_GAP_MS);
+        }
+    }
+
+    #[test]
+    fn new_probes_record_their_first_reading() {
+        let probe = Probe::new("attic", "home/attic/temp");
+        probe.update(21.5);
+        let now = probe.last_update();
+        let entry = next_history_entry(None, probe.value(), probe.last_update(), now);
+        assert_eq!(entry, Some(format!("{}:21.5", now)));
+    }
+
+    #[test]
+    fn fresh_updates_are_recorded_at_their_own_time() {
+        let last = "1000:21.5";
+        assert_eq!(next_history_entry(Some(last), 22.0, 6000, 6500), Some("6000:22".into()));
+        assert_eq!(next_history_entry(Some(last), 21.5, 1000, 6500), None);
+    }
+
+    #[test]
+    fn malformed_topics_are_rejected() {
+        for topic in ["", "home/+/temp", "home/#", "/home/attic", "home/attic/", "home//temp"] {
+            assert!(validate_endpoint(topic).is_err(), "{:?}", topic);
+        }
+        assert!(validate_endpoint("home/attic temp").is_err());
+        assert_eq!(validate_endpoint("home/attic/temp"), Ok(()));
+        assert_eq!(validate_endpoint("http://sensor.local/status#/temp"), Ok(()));
+    }
+
+    #[test]
+    fn probe_names() {
+        assert!(valid_probe_name("attic"));
+        assert!(!valid_probe_name(""));
+        assert!(!valid_probe_name("attic:history"));
+        assert!(!valid_probe_name(PRIMARY_PROBE));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn create_probe_checks_before_writing() {
+        let redis = RedisConn::scratch().await;
+        let mqtt = MqttClient::loopback(false);
+        let probes = Probes::unfed(&["attic"]).await;
+        let name = "test_create";
+        let () = redis.get().hdel(PROBE_ENDPOINTS, name).await.unwrap();
+
+        let Err(error) = probes.create_probe(&redis, &mqtt, name, "").await else {
+            panic!("created a probe without a topic");
+        };
+        assert!(error.to_string().contains("empty"), "{}", error);
+        let Err(error) = probes.create_probe(&redis, &mqtt, "attic", "home/loft/temp").await
+        else {
+            panic!("created a probe under a taken name");
+        };
+        assert!(error.to_string().contains("already exists"), "{}", error);
+        let endpoint: Option<String> = redis.get().hget(PROBE_ENDPOINTS, name).await.unwrap();
+        assert_eq!(endpoint, None);
+
+        probes.create_probe(&redis, &mqtt, name, "home/test/temp").await.unwrap();
+        assert_eq!(probes.get(name).await.unwrap().endpoint(), "home/test/temp");
+        let endpoint: Option<String> = redis.get().hget(PROBE_ENDPOINTS, name).await.unwrap();
+        assert_eq!(endpoint.as_deref(), Some("home/test/temp"));
+        let () = redis.get().hdel(PROBE_ENDPOINTS, name).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn primary_falls_back_to_the_first_probe_with_a_reading() {
+        let probes = Probes::unfed(&["garage", "bedroom", "attic"]).await;
+        assert!(probes.primary().await.is_none());
+
+        probes.get("garage").await.unwrap().update(15.0);
+        probes.get("bedroom").await.unwrap().update(19.5);
+        assert_eq!(probes.primary().await.unwrap().name(), "bedroom");
+
+        let probes = Probes::unfed(&[PRIMARY_PROBE, "attic"]).await;
+        probes.get("attic").await.unwrap().update(25.0);
+        assert_eq!(probes.primary().await.unwrap().name(), "attic");
+        probes.get(PRIMARY_PROBE).await.unwrap().update(21.0);
+        assert_eq!(probes.primary().await.unwrap().name(), PRIMARY_PROBE);
+    }
+
+    fn published_statuses(eventloop: &rumqttc::EventLoop) -> Vec<serde_json::Value> {
+        let mut statuses = Vec::new();
+        while let Ok(rumqttc::Request::Publish(publish)) = eventloop.requests_rx.try_recv() {
+            assert_eq!(publish.topic, DECISION_STATUS_TOPIC);
+            assert!(publish.retain);
+            statuses.push(serde_json::from_slice(&publish.payload).unwrap());
+        }
+        statuses
+    }
+
+    #[tokio::test]
+    async fn status_reflects_an_active_override_pulse() {
+        let (mqtt, eventloop) = MqttClient::unconnected();
+        let mut status = DecisionStatusPublisher::default();
+        let trace = |stage, request| EvaluationTrace {
+            stage,
+            request: Some(request),
+            primary_probe: Some(20.5),
+            rule: None,
+            weights: None,
+        };
+
+        let ruleset = trace(EvaluationStage::Ruleset, HvacRequest::Off);
+        status.update(&mqtt, HvacRequest::Off, &ruleset).await;
+        let pulse = trace(EvaluationStage::OverridePulse, HvacRequest::Heat);
+        status.update(&mqtt, HvacRequest::Heat, &pulse).await;
+        // Nothing new to say while the pulse keeps deciding
+        status.update(&mqtt, HvacRequest::Heat, &pulse).await;
+
+        let statuses = published_statuses(&eventloop);
+        assert_eq!(statuses.len(), 2);
+        let expected = serde_json::json!({
+            "request": "heat",
+            "stage": "override_pulse",
+            "primary_temp": 20.5,
+        });
+        assert_eq!(statuses[1], expected);
+    }
+
+    #[tokio::test]
+    async fn rename_rejects_a_taken_name() {
+        let probes = Probes::unfed(&["attic", "bedroom"]).await;
+        let Err(error) = probes.rename_source("attic", "bedroom").await else {
+            panic!("renamed onto a taken name");
+        };
+        assert!(error.to_string().contains("already exists"));
+
+        assert!(probes.rename_source("garage", "shed").await.is_err());
+        assert!(probes.rename_source(PRIMARY_PROBE, "shed").await.is_err());
+        assert!(probes.rename_source("attic", PRIMARY_PROBE).await.is_err());
+        assert!(probes.rename_source("attic", "loft").await.is_ok());
+    }
+
+    #[test]
+    fn repeated_pinstates_are_skipped() {
+        assert!(!is_pinstate_change(Some("heat:1000"), HvacRequest::Heat));
+        assert!(!is_pinstate_change(Some("off:1000"), HvacRequest::Off));
+    }
+
+    #[test]
+    fn changed_pinstates_are_recorded() {
+        assert!(is_pinstate_change(Some("heat:1000"), HvacRequest::Cool));
+        assert!(is_pinstate_change(Some("cool:1000"), HvacRequest::Off));
+        assert!(is_pinstate_change(None, HvacRequest::Heat));
+        assert!(is_pinstate_change(Some("garbage"), HvacRequest::Heat));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn pinstate_history_is_capped() {
+        let redis = RedisConn::scratch().await;
+        let () = redis.get().del(PINSTATE_HISTORY).await.unwrap();
+
+        let states = [HvacRequest::Heat, HvacRequest::Off, HvacRequest::Cool];
+        for (time, &state) in states.iter().cycle().take(6).enumerate() {
+            record_pinstate(&redis, state, time as i64, 4).await.unwrap();
+        }
+
+        let mut redis = redis.get();
+        let history: Vec<String> = redis.lrange(PINSTATE_HISTORY, 0, -1).await.unwrap();
+        assert_eq!(history, ["cool:5", "off:4", "heat:3", "cool:2"]);
+        let () = redis.del(PINSTATE_HISTORY).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn rename_moves_the_history() {
+        let redis = RedisConn::scratch().await;
+        let mqtt = MqttClient::loopback(false);
+        let probes = Probes::new(LiveUpdates::new());
+        let (old, new) = ("test_rename_old", "test_rename_new");
+        {
+            let mut redis = redis.get();
+            let () = redis::pipe()
+                .del(keys::probe_history(new))
+                .ignore()
+                .rpush(keys::probe_history(old), &["2000:21.5", "1000:21.0"])
+                .ignore()
+                .query_async(&mut redis)
+                .await
+                .unwrap();
+        }
+        probes.create_probe(&redis, &mqtt, old, "home/test/temp").await.unwrap();
+
+        assert!(probes.rename_probe(&redis, &mqtt, old, new).await.unwrap());
+
+        assert!(probes.get(old).await.is_none());
+        assert_eq!(probes.get(new).await.unwrap().endpoint(), "home/test/temp");
+
+        let mut redis = redis.get();
+        let moved: Vec<String> = redis.lrange(keys::probe_history(new), 0, -1).await.unwrap();
+        assert_eq!(moved, ["2000:21.5", "1000:21.0"]);
+        let left: bool = redis.exists(keys::probe_history(old)).await.unwrap();
+        assert!(!left);
+        let endpoint: Option<String> = redis.hget(PROBE_ENDPOINTS, old).await.unwrap();
+        assert_eq!(endpoint, None);
+
+        let () = redis::pipe()
+            .del(keys::probe_history(new))
+            .ignore()
+            .hdel(PROBE_ENDPOINTS, new)
+            .ignore()
+            .query_async(&mut redis)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn rename_keeps_a_deleted_probes_history() {
+        let redis = RedisConn::scratch().await;
+        let mqtt = MqttClient::loopback(false);
+        let probes = Probes::new(LiveUpdates::new());
+        let (old, new) = ("test_rename_kept_old", "test_rename_kept_new");
+        {
+            let mut redis = redis.get();
+            let () = redis::pipe()
+                .del(&[keys::probe_history(old), keys::probe_history(new)])
+                .ignore()
+                .rpush(keys::probe_history(old), "2000:21.5")
+                .ignore()
+                .rpush(keys::probe_history(new), "1000:18.0")
+                .ignore()
+                .query_async(&mut redis)
+                .await
+                .unwrap();
+        }
+        probes.create_probe(&redis, &mqtt, old, "home/test/temp").await.unwrap();
+
+        assert!(!probes.rename_probe(&redis, &mqtt, old, new).await.unwrap());
+        assert!(probes.get(old).await.is_some());
+        assert!(probes.get(new).await.is_none());
+
+        let mut redis = redis.get();
+        let kept: Vec<String> = redis.lrange(keys::probe_history(new), 0, -1).await.unwrap();
+        assert_eq!(kept, ["1000:18.0"]);
+        let endpoint: Option<String> = redis.hget(PROBE_ENDPOINTS, old).await.unwrap();
+        assert_eq!(endpoint.as_deref(), Some("home/test/temp"));
+
+        let () = redis::pipe()
+            .del(&[keys::probe_history(old), keys::probe_history(new)])
+            .ignore()
+            .hdel(PROBE_ENDPOINTS, old)
+            .ignore()
+            .query_async(&mut redis)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn mode_goes_offline_after_the_staleness_window() {
+        let seen = |ago: Duration| {
+            LastSeen(Mutex::new(Some(Utc::now() - chrono::Duration::from_std(ago).unwrap())))
+        };
+
+        assert!(seen(Duration::from_secs(60)).within(MODE_STALE_AFTER));
+        assert!(!seen(MODE_STALE_AFTER + Duration::from_secs(1)).within(MODE_STALE_AFTER));
+        assert!(!LastSeen::default().within(MODE_STALE_AFTER));
+
+        let fresh = LastSeen::default();
+        fresh.mark();
+        assert!(fresh.within(MODE_STALE_AFTER));
+    }
+
+    #[test]
+    fn publish_on_change_skips_repeats_until_the_keepalive() {
+        let mut publisher = RemoteStatePublisher::new(true);
+        let start = Instant::now();
+        let tick = |n: u64| start + Duration::from_secs(10 * n);
+
+        let published: Vec<u64> = (0..40)
+            .filter(|&n| publisher.should_publish(HvacRequest::Heat, tick(n)))
+            .collect();
+        // Once up front, then again when the keepalive comes due
+        assert_eq!(published, [0, 30]);
+
+        assert!(publisher.should_publish(HvacRequest::Off, tick(40)));
+        assert!(!publisher.should_publish(HvacRequest::Off, tick(41)));
+    }
+
+    #[test]
+    fn publishes_every_tick_by_default() {
+        let mut publisher = RemoteStatePublisher::new(false);
+        let now = Instant::now();
+        assert!(publisher.should_publish(HvacRequest::Heat, now));
+        assert!(publisher.should_publish(HvacRequest::Heat, now));
+    }
 }
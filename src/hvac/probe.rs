@@ -1,8 +1,15 @@
-use std::sync::{
-    atomic::{AtomicI64, AtomicU32, Ordering},
-    Arc,
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
+/// How long a probe can go without an update before it counts as stale,
+/// unless configured otherwise
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(5 * 60);
+
 #[derive(Clone)]
 pub struct Probe {
     inner: Arc<ProbeInner>,
@@ -16,6 +23,9 @@ impl Probe {
                 endpoint: endpoint.into(),
                 value: AtomicU32::new(f32::to_bits(f32::NAN)),
                 last_update: AtomicI64::new(current_timestamp()),
+                min_interval_ms: AtomicI64::new(0),
+                stale_after_ms: AtomicI64::new(DEFAULT_STALE_AFTER.as_millis() as i64),
+                throttle: Mutex::new(Throttle::default()),
             }),
         }
     }
@@ -28,6 +38,12 @@ impl Probe {
         &self.inner.endpoint
     }
 
+    /// Whether both handles point at the same probe, rather than two probes
+    /// that happen to share a name
+    pub fn same(&self, other: &Probe) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
     pub fn value(&self) -> f32 {
         f32::from_bits(self.inner.value.load(Ordering::SeqCst))
     }
@@ -44,6 +60,85 @@ impl Probe {
     pub fn last_update(&self) -> i64 {
         self.inner.last_update.load(Ordering::SeqCst)
     }
+
+    /// Time since the last update, or since the probe was created if it
+    /// hasn't had one yet
+    pub fn age(&self) -> Duration {
+        Duration::from_millis((current_timestamp() - self.last_update()).max(0) as u64)
+    }
+
+    pub fn set_stale_after(&self, stale_after: Duration) {
+        self.inner
+            .stale_after_ms
+            .store(stale_after.as_millis() as i64, Ordering::SeqCst);
+    }
+
+    pub fn stale_after(&self) -> Duration {
+        Duration::from_millis(self.inner.stale_after_ms.load(Ordering::SeqCst).max(0) as u64)
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.age() > self.stale_after()
+    }
+
+    /// Updates arriving closer together than this get coalesced. Zero turns
+    /// throttling off.
+    pub fn set_min_interval(&self, interval: Duration) {
+        self.inner
+            .min_interval_ms
+            .store(interval.as_millis() as i64, Ordering::SeqCst);
+    }
+
+    pub fn min_interval(&self) -> Duration {
+        Duration::from_millis(self.inner.min_interval_ms.load(Ordering::SeqCst).max(0) as u64)
+    }
+
+    /// Decide what to do with a freshly received value. Anything that isn't
+    /// `Process` is held as pending, only the latest pending value is kept.
+    pub fn offer(&self, value: f32) -> ThrottleDecision {
+        let interval = self.min_interval();
+        if interval.is_zero() {
+            return ThrottleDecision::Process;
+        }
+
+        let mut throttle = self.inner.throttle.lock().unwrap();
+        let now = Instant::now();
+        match throttle.last_processed {
+            Some(last) if now - last < interval => {
+                if throttle.pending.replace(value).is_some() {
+                    ThrottleDecision::Coalesced
+                } else {
+                    ThrottleDecision::Deferred(interval - (now - last))
+                }
+            }
+            _ => {
+                throttle.last_processed = Some(now);
+                ThrottleDecision::Process
+            }
+        }
+    }
+
+    /// The latest value held back by `offer`, once its window is over
+    pub fn take_pending(&self) -> Option<f32> {
+        let mut throttle = self.inner.throttle.lock().unwrap();
+        let value = throttle.pending.take()?;
+        throttle.last_processed = Some(Instant::now());
+        Some(value)
+    }
+}
+
+pub enum ThrottleDecision {
+    Process,
+    /// Call `take_pending` after waiting this long
+    Deferred(Duration),
+    /// A deferred update is already on its way and will carry this value
+    Coalesced,
+}
+
+#[derive(Default)]
+struct Throttle {
+    last_processed: Option<Instant>,
+    pending: Option<f32>,
 }
 
 fn current_timestamp() -> i64 {
@@ -55,4 +150,38 @@ struct ProbeInner {
     endpoint: String,
     value: AtomicU32,
     last_update: AtomicI64,
+    min_interval_ms: AtomicI64,
+    stale_after_ms: AtomicI64,
+    throttle: Mutex<Throttle>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_updates_are_coalesced() {
+        let probe = Probe::new("attic", "home/attic/temp");
+        probe.set_min_interval(Duration::from_secs(60));
+
+        assert!(matches!(probe.offer(20.0), ThrottleDecision::Process));
+        assert!(matches!(
+            probe.offer(20.5),
+            ThrottleDecision::Deferred(wait) if wait <= Duration::from_secs(60)
+        ));
+        assert!(matches!(probe.offer(21.0), ThrottleDecision::Coalesced));
+
+        // The deferred update carries only the latest value
+        assert_eq!(probe.take_pending(), Some(21.0));
+        assert_eq!(probe.take_pending(), None);
+    }
+
+    #[test]
+    fn no_interval_processes_everything() {
+        let probe = Probe::new("attic", "home/attic/temp");
+        for temp in [20.0, 20.5, 21.0] {
+            assert!(matches!(probe.offer(temp), ThrottleDecision::Process));
+        }
+        assert_eq!(probe.take_pending(), None);
+    }
 }
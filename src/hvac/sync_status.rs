@@ -0,0 +1,129 @@
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use super::mixer::HvacRequest;
+
+/// How long the unit may disagree with us before it counts as a fault. The
+/// relays take a moment to follow a new command.
+const PERSISTENT_MISMATCH_MS: i64 = 60 * 1000;
+
+/// Compares what we last commanded on remotestate to what the unit last
+/// reported on pinstate
+#[derive(Default)]
+pub struct SyncTracker {
+    inner: Mutex<SyncInner>,
+}
+
+#[derive(Default)]
+struct SyncInner {
+    commanded: Option<HvacRequest>,
+    reported: Option<(HvacRequest, i64)>,
+    mismatch_since: Option<i64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SyncStatus {
+    pub commanded: Option<HvacRequest>,
+    pub reported: Option<HvacRequest>,
+    /// Milliseconds since the epoch
+    pub reported_at: Option<i64>,
+    pub in_sync: bool,
+    /// Milliseconds since the epoch
+    pub mismatch_since: Option<i64>,
+    pub persistent_mismatch: bool,
+}
+
+impl SyncTracker {
+    pub fn record_commanded(&self, request: HvacRequest) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.commanded = Some(request);
+        inner.update_mismatch();
+    }
+
+    pub fn record_reported(&self, state: HvacRequest) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.reported = Some((state, current_timestamp()));
+        inner.update_mismatch();
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        let inner = self.inner.lock().unwrap();
+        let persistent_mismatch = inner
+            .mismatch_since
+            .map(|since| current_timestamp() - since >= PERSISTENT_MISMATCH_MS)
+            .unwrap_or(false);
+        SyncStatus {
+            commanded: inner.commanded,
+            reported: inner.reported.map(|(state, _)| state),
+            reported_at: inner.reported.map(|(_, time)| time),
+            in_sync: inner.mismatch_since.is_none(),
+            mismatch_since: inner.mismatch_since,
+            persistent_mismatch,
+        }
+    }
+}
+
+impl SyncInner {
+    fn update_mismatch(&mut self) {
+        let (Some(commanded), Some((reported, _))) = (self.commanded, self.reported) else {
+            // Nothing to compare against yet
+            self.mismatch_since = None;
+            return;
+        };
+
+        if commanded == reported {
+            self.mismatch_since = None;
+        } else if self.mismatch_since.is_none() {
+            self.mismatch_since = Some(current_timestamp());
+        }
+    }
+}
+
+fn current_timestamp() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_states_are_in_sync() {
+        let tracker = SyncTracker::default();
+        tracker.record_commanded(HvacRequest::Heat);
+        tracker.record_reported(HvacRequest::Heat);
+
+        let status = tracker.status();
+        assert!(status.in_sync);
+        assert_eq!(status.mismatch_since, None);
+        assert!(!status.persistent_mismatch);
+    }
+
+    #[test]
+    fn mismatch_is_tracked_until_resolved() {
+        let tracker = SyncTracker::default();
+        tracker.record_commanded(HvacRequest::Cool);
+        tracker.record_reported(HvacRequest::Off);
+
+        let status = tracker.status();
+        assert!(!status.in_sync);
+        assert!(status.mismatch_since.is_some());
+        // Too fresh to be a fault yet
+        assert!(!status.persistent_mismatch);
+
+        tracker.inner.lock().unwrap().mismatch_since =
+            Some(current_timestamp() - PERSISTENT_MISMATCH_MS);
+        assert!(tracker.status().persistent_mismatch);
+
+        tracker.record_reported(HvacRequest::Cool);
+        assert!(tracker.status().in_sync);
+    }
+
+    #[test]
+    fn nothing_reported_yet_is_not_a_mismatch() {
+        let tracker = SyncTracker::default();
+        tracker.record_commanded(HvacRequest::Heat);
+        assert!(tracker.status().in_sync);
+    }
+}
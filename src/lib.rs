@@ -2,7 +2,7 @@
 
 use hvac::HvacState;
 use mqtt::MqttClient;
-use rumqttc::MqttOptions;
+use rumqttc::{MqttOptions, Transport};
 use std::future::Future;
 use std::time::Duration;
 
@@ -20,6 +20,7 @@ pub mod redis;
 const PORT: u16 = 3030;
 const MQTT_HOST: &str = "raspberrypi.local";
 const MQTT_PORT: u16 = 1883;
+const MQTT_TLS_PORT: u16 = 8883;
 const REDIS_HOST: &str = "nas.iot.connieh.com";
 const REDIS_PORT: u16 = 6379;
 
@@ -33,13 +34,12 @@ pub struct StatePackage<'a> {
 
 #[cfg(feature = "routes")]
 pub async fn run_server() -> anyhow::Result<()> {
-    let mqtt = {
-        let mut options = MqttOptions::new("pi-management-server", MQTT_HOST, MQTT_PORT);
-        options.set_keep_alive(Duration::from_secs(5));
-        mqtt::init(options)
-    };
+    abort_on_panic();
+    let mqtt = mqtt::init(mqtt_options()?);
 
     let redis: RedisConn = RedisConn::open(REDIS_HOST, REDIS_PORT).await?;
+    mqtt::clear_deprecated_retained(&mqtt, &redis).await;
+
     let fan_state = FanState::default();
     let hvac = hvac::initialize(&mqtt, &redis, &fan_state).await?;
 
@@ -57,6 +57,49 @@ pub async fn run_server() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Connects the same way thermostatd does when `MQTT_USER`/`MQTT_PASS` are
+/// set, and over TLS when `MQTT_CA_PATH` points at the broker's CA
+/// certificate. Without them it's plaintext and anonymous.
+#[cfg(feature = "routes")]
+fn mqtt_options() -> anyhow::Result<MqttOptions> {
+    mqtt_options_from(|name| std::env::var(name).ok())
+}
+
+#[cfg(feature = "routes")]
+fn mqtt_options_from(env: impl Fn(&str) -> Option<String>) -> anyhow::Result<MqttOptions> {
+    let ca_path = env("MQTT_CA_PATH");
+    let port = if ca_path.is_some() { MQTT_TLS_PORT } else { MQTT_PORT };
+
+    let mut options = MqttOptions::new("pi-management-server", MQTT_HOST, port);
+    options.set_keep_alive(Duration::from_secs(5));
+
+    if let (Some(user), Some(pass)) = (env("MQTT_USER"), env("MQTT_PASS")) {
+        options.set_credentials(user, pass);
+    }
+    if let Some(ca_path) = ca_path {
+        let ca = std::fs::read(&ca_path)
+            .map_err(|e| anyhow::anyhow!("Couldn't read MQTT CA from {ca_path}: {e}"))?;
+        options.set_transport(Transport::tls(ca, None, None));
+    }
+
+    Ok(options)
+}
+
+/// Release builds unwind so the Lua worker can catch a script's panic, but
+/// anywhere else a panic would only end its own task, e.g. leaving nothing
+/// driving the furnace while HTTP keeps answering. Those take the process
+/// down instead so it gets restarted.
+#[cfg(feature = "routes")]
+fn abort_on_panic() {
+    let report = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        report(info);
+        if !hvac::mixer::lua_controller::on_lua_thread() {
+            std::process::abort();
+        }
+    }));
+}
+
 #[cfg(tokio_unstable)]
 #[track_caller]
 fn spawn(name: &str, future: impl Future<Output = impl Send + 'static> + Send + 'static) {
@@ -67,3 +110,77 @@ fn spawn(name: &str, future: impl Future<Output = impl Send + 'static> + Send +
 fn spawn(_name: &str, future: impl Future<Output = impl Send + 'static> + Send + 'static) {
     tokio::spawn(future);
 }
+
+#[cfg(all(test, feature = "routes"))]
+mod tests {
+    use std::collections::HashMap;
+
+    use rumqttc::Transport;
+
+    use super::*;
+
+    fn options(vars: &[(&str, &str)]) -> anyhow::Result<MqttOptions> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        mqtt_options_from(|name| vars.get(name).cloned())
+    }
+
+    /// Any readable file does, rumqttc only parses the CA when connecting
+    fn ca() -> String {
+        let path = std::env::temp_dir().join("home-server-test-ca.pem");
+        std::fs::write(&path, "not really a certificate").unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn plaintext_and_anonymous_by_default() {
+        let options = options(&[]).unwrap();
+        assert_eq!(options.broker_address(), (MQTT_HOST.to_string(), MQTT_PORT));
+        assert!(matches!(options.transport(), Transport::Tcp));
+        assert_eq!(options.credentials(), None);
+    }
+
+    #[test]
+    fn credentials_without_tls() {
+        let options = options(&[("MQTT_USER", "server"), ("MQTT_PASS", "hunter2")]).unwrap();
+        assert_eq!(options.broker_address().1, MQTT_PORT);
+        assert!(matches!(options.transport(), Transport::Tcp));
+        assert_eq!(options.credentials(), Some(("server".into(), "hunter2".into())));
+    }
+
+    #[test]
+    fn half_the_credentials_are_ignored() {
+        let options = options(&[("MQTT_USER", "server")]).unwrap();
+        assert_eq!(options.credentials(), None);
+    }
+
+    #[test]
+    fn tls_without_credentials() {
+        let ca = ca();
+        let options = options(&[("MQTT_CA_PATH", &ca)]).unwrap();
+        assert_eq!(options.broker_address().1, MQTT_TLS_PORT);
+        assert!(matches!(options.transport(), Transport::Tls(_)));
+        assert_eq!(options.credentials(), None);
+    }
+
+    #[test]
+    fn tls_with_credentials() {
+        let ca = ca();
+        let options = options(&[
+            ("MQTT_CA_PATH", &ca),
+            ("MQTT_USER", "server"),
+            ("MQTT_PASS", "hunter2"),
+        ])
+        .unwrap();
+        assert_eq!(options.broker_address().1, MQTT_TLS_PORT);
+        assert!(matches!(options.transport(), Transport::Tls(_)));
+        assert_eq!(options.credentials(), Some(("server".into(), "hunter2".into())));
+    }
+
+    #[test]
+    fn unreadable_ca_is_an_error() {
+        assert!(options(&[("MQTT_CA_PATH", "/nonexistent/ca.pem")]).is_err());
+    }
+}
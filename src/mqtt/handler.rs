@@ -1,35 +1,65 @@
 use std::collections::HashMap;
 
-pub struct Handler(Box<dyn Fn(&str, &[u8]) + Send + Sync + 'static>);
+/// Identifies a registered handler so it can be removed again
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HandlerId(u64);
+
+type Callback = Box<dyn Fn(&str, &[u8]) + Send + Sync + 'static>;
+
+pub struct Handler {
+    id: HandlerId,
+    callback: Callback,
+}
 
 impl<F> From<F> for Handler
 where
     F: Fn(&str, &[u8]) + Send + Sync + 'static,
 {
     fn from(handler: F) -> Handler {
-        Handler(Box::new(handler))
+        Handler {
+            id: HandlerId(0),
+            callback: Box::new(handler),
+        }
     }
 }
 
 impl std::fmt::Debug for Handler {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        ((&self.0) as *const _ as *const ()).fmt(fmt)
+        ((&self.callback) as *const _ as *const ()).fmt(fmt)
     }
 }
 
 pub struct Router {
     root: Route,
+    next_id: u64,
 }
 
 impl Router {
     pub fn new() -> Self {
         Router {
             root: Route::Leaf(vec![]),
+            next_id: 0,
         }
     }
 
-    pub fn insert(&mut self, path: &str, handler: Handler) {
+    pub fn insert(&mut self, path: &str, mut handler: Handler) -> HandlerId {
+        let id = HandlerId(self.next_id);
+        self.next_id += 1;
+        handler.id = id;
         insert(&mut self.root, path, handler);
+        id
+    }
+
+    /// Returns whether a handler with that id was registered under `path`
+    pub fn remove(&mut self, path: &str, id: HandlerId) -> bool {
+        let Some(route) = sub_tree_mut(&mut self.root, path) else {
+            return false;
+        };
+
+        let handlers = handlers_of_mut(route);
+        let before = handlers.len();
+        handlers.retain(|handler| handler.id != id);
+        handlers.len() != before
     }
 
     pub fn dispatch(&self, topic: &str, payload: &[u8]) {
@@ -122,6 +152,23 @@ fn dispatch(route: &Route, path: &str, topic: &str, payload: &[u8]) {
     }
 }
 
+fn sub_tree_mut<'a>(route: &'a mut Route, path: &str) -> Option<&'a mut Route> {
+    if path.is_empty() {
+        return Some(route);
+    }
+
+    let (stem, leaf) = if let Some((stem, leaf)) = path.split_once('/') {
+        (stem, leaf)
+    } else {
+        (path, "")
+    };
+
+    match route {
+        Route::Node(node) => sub_tree_mut(node.children.get_mut(stem)?, leaf),
+        _ => None,
+    }
+}
+
 fn sub_tree<'a>(route: &'a Route, stem: &str) -> Option<&'a Route> {
     match route {
         Route::Node(node) => node.children.get(stem),
@@ -131,6 +178,6 @@ fn sub_tree<'a>(route: &'a Route, stem: &str) -> Option<&'a Route> {
 
 fn execute(route: &Route, topic: &str, payload: &[u8]) {
     for handler in handlers_of(route) {
-        (handler.0)(topic, payload);
+        (handler.callback)(topic, payload);
     }
 }
@@ -1,10 +1,14 @@
 use std::sync::Arc;
 
+use models::keys::DEPRECATED_RETAINED_TOPICS;
+use redis::AsyncCommands;
 use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
 use tokio::sync::RwLock;
 use tracing::Instrument;
 
-use self::handler::Router;
+use crate::RedisConn;
+
+use self::handler::{HandlerId, Router};
 
 pub mod handler;
 
@@ -19,9 +23,18 @@ impl MqttClient {
         self.client.subscribe(topic, QoS::AtMostOnce).await.unwrap();
     }
 
-    pub async fn handle(&self, path: &str, handler: impl Fn(&str, &[u8]) + Send + Sync + 'static) {
+    pub async fn handle(
+        &self,
+        path: &str,
+        handler: impl Fn(&str, &[u8]) + Send + Sync + 'static,
+    ) -> HandlerId {
+        let mut router = self.router.write().await;
+        router.insert(path, handler.into())
+    }
+
+    pub async fn unhandle(&self, path: &str, id: HandlerId) -> bool {
         let mut router = self.router.write().await;
-        router.insert(path, handler.into());
+        router.remove(path, id)
     }
 
     pub async fn publish(&self, topic: &str, payload: &[u8]) {
@@ -30,6 +43,61 @@ impl MqttClient {
             .await
             .unwrap();
     }
+
+    pub async fn publish_retained(&self, topic: &str, payload: &[u8]) {
+        self.client
+            .publish(topic, QoS::AtLeastOnce, true, payload)
+            .await
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+impl MqttClient {
+    /// A client without a broker. With `echo` every publish is handed straight
+    /// back to the handlers as if the broker sent it, otherwise it's dropped.
+    pub fn loopback(echo: bool) -> MqttClient {
+        let (mqtt, eventloop) = MqttClient::unconnected();
+
+        let broker = mqtt.router.clone();
+        tokio::spawn(async move {
+            while let Ok(request) = eventloop.requests_rx.recv().await {
+                if let (true, rumqttc::Request::Publish(publish)) = (echo, request) {
+                    broker.read().await.dispatch(&publish.topic, &publish.payload);
+                }
+            }
+        });
+
+        mqtt
+    }
+
+    /// A client whose requests are left in the returned event loop's queue
+    pub fn unconnected() -> (MqttClient, rumqttc::EventLoop) {
+        let options = MqttOptions::new("loopback", "localhost", 1883);
+        let (client, eventloop) = AsyncClient::new(options, 50);
+        let router = Arc::new(RwLock::new(Router::new()));
+        (MqttClient { client, router }, eventloop)
+    }
+}
+
+/// An empty retained message makes the broker forget whatever it was holding
+/// for the topic. Used to clean up after topics that have since moved.
+pub async fn clear_deprecated_retained(mqtt: &MqttClient, redis: &RedisConn) {
+    let topics: Vec<String> = {
+        let mut redis = redis.get();
+        redis
+            .smembers(DEPRECATED_RETAINED_TOPICS)
+            .await
+            .unwrap_or_default()
+    };
+
+    clear_retained(mqtt, &topics).await;
+}
+
+async fn clear_retained(mqtt: &MqttClient, topics: &[String]) {
+    for topic in topics {
+        mqtt.publish_retained(topic, b"").await;
+    }
 }
 
 #[tracing::instrument]
@@ -58,3 +126,28 @@ async fn mqtt_listener(mut eventloop: rumqttc::EventLoop, router: Arc<RwLock<Rou
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rumqttc::Request;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn cleanup_publishes_empty_retained_messages() {
+        let (mqtt, eventloop) = MqttClient::unconnected();
+        let topics = ["test/home/thermostat".to_string(), "old/atticfan".to_string()];
+        clear_retained(&mqtt, &topics).await;
+
+        let mut cleared = Vec::new();
+        while let Ok(request) = eventloop.requests_rx.try_recv() {
+            let Request::Publish(publish) = request else {
+                panic!("expected only publishes, got {:?}", request);
+            };
+            assert!(publish.retain);
+            assert!(publish.payload.is_empty());
+            cleared.push(publish.topic);
+        }
+        assert_eq!(cleared, topics);
+    }
+}
@@ -17,3 +17,60 @@ impl RedisConn {
         self.connection.clone()
     }
 }
+
+#[cfg(test)]
+impl RedisConn {
+    /// A scratch database for the ignored tests, e.g.
+    /// `REDIS_URL=redis://127.0.0.1/15 cargo test -- --ignored`
+    pub async fn scratch() -> RedisConn {
+        let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1/15".into());
+        let client = Client::open(url).unwrap();
+        RedisConn {
+            connection: ConnectionManager::new(client).await.unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::AsyncCommands;
+
+    use super::*;
+
+    #[tokio::test]
+    #[ignore]
+    async fn commands_work_again_after_a_dropped_connection() {
+        let redis = RedisConn::scratch().await;
+        let mut connection = redis.get();
+        let id: i64 = redis::cmd("CLIENT")
+            .arg("ID")
+            .query_async(&mut connection)
+            .await
+            .unwrap();
+
+        // Drop the connection from the server side, like a Redis restart would
+        let killer = RedisConn::scratch().await;
+        let killed: i64 = redis::cmd("CLIENT")
+            .arg("KILL")
+            .arg("ID")
+            .arg(id)
+            .query_async(&mut killer.get())
+            .await
+            .unwrap();
+        assert_eq!(killed, 1);
+
+        // The first command may still see the dead connection
+        let mut reconnected = false;
+        for _ in 0..10 {
+            let mut connection = redis.get();
+            if connection.set::<_, _, ()>("test:reconnect", 1).await.is_ok() {
+                reconnected = true;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        assert!(reconnected);
+
+        let () = redis.get().del("test:reconnect").await.unwrap();
+    }
+}
@@ -7,6 +7,8 @@ function init(state)
     --     state.mqtt:subscribe('home/thermostat/hvac/pinstate')
     -- Later in a tick or evaluate:
     --     local pinstate = state.mqtt["home/thermostat/hvac/pinstate"]
+    -- Anything kept in the global `persist` table survives reloads and restarts:
+    --     persist.counter = (persist.counter or 0) + 1
 end
 
 function evaluate(state)
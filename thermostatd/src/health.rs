@@ -0,0 +1,151 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use warp::{http::StatusCode, Filter};
+
+const DEFAULT_HEALTH_PORT: u16 = 9464;
+/// Evaluation normally runs every 10 seconds
+const DEFAULT_EVALUATE_MAX_AGE: Duration = Duration::from_secs(60);
+/// The MQTT keepalive is 5 seconds, so something should come in well before this
+const MQTT_MAX_SILENCE: Duration = Duration::from_secs(30);
+
+/// Outlives `run_thermostat` so readiness is still reported while it restarts
+#[derive(Default)]
+pub struct Health {
+    last_mqtt_event: AtomicI64,
+    last_evaluate: AtomicI64,
+    redis: Mutex<Option<redis::aio::ConnectionManager>>,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    mqtt: bool,
+    redis: bool,
+    evaluate: bool,
+}
+
+impl Health {
+    pub fn mark_mqtt_event(&self) {
+        self.last_mqtt_event.store(now_millis(), Ordering::SeqCst);
+    }
+
+    pub fn mark_evaluated(&self) {
+        self.last_evaluate.store(now_millis(), Ordering::SeqCst);
+    }
+
+    pub fn set_redis(&self, redis: Option<redis::aio::ConnectionManager>) {
+        *self.redis.lock().unwrap() = redis;
+    }
+
+    async fn readiness(&self) -> Readiness {
+        let now = now_millis();
+        let mqtt = now - self.last_mqtt_event.load(Ordering::SeqCst)
+            <= MQTT_MAX_SILENCE.as_millis() as i64;
+        let evaluate = now - self.last_evaluate.load(Ordering::SeqCst)
+            <= evaluate_max_age().as_millis() as i64;
+
+        let redis = self.redis.lock().unwrap().clone();
+        let redis = match redis {
+            Some(mut redis) => {
+                let cmd = redis::cmd("PING");
+                let ping = cmd.query_async::<_, String>(&mut redis);
+                matches!(tokio::time::timeout(Duration::from_secs(1), ping).await, Ok(Ok(_)))
+            }
+            None => false,
+        };
+
+        Readiness {
+            ready: mqtt && redis && evaluate,
+            mqtt,
+            redis,
+            evaluate,
+        }
+    }
+}
+
+/// `HEALTH_PORT` picks the port, `READY_EVALUATE_MAX_AGE_SECS` how stale the
+/// last script evaluation may get before we stop being ready
+pub async fn run_health_server(health: Arc<Health>) {
+    let port = std::env::var("HEALTH_PORT")
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(DEFAULT_HEALTH_PORT);
+
+    let healthz = warp::path("healthz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| "ok");
+
+    let readyz = warp::path("readyz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(move || {
+            let health = health.clone();
+            async move {
+                let readiness = health.readiness().await;
+                let status = if readiness.ready {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                Ok::<_, Infallible>(warp::reply::with_status(
+                    warp::reply::json(&readiness),
+                    status,
+                ))
+            }
+        });
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    warp::serve(healthz.or(readyz)).run(addr).await;
+}
+
+fn evaluate_max_age() -> Duration {
+    std::env::var("READY_EVALUATE_MAX_AGE_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_EVALUATE_MAX_AGE)
+}
+
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stale_evaluation_is_not_ready() {
+        let health = Health::default();
+        health.mark_mqtt_event();
+        health.mark_evaluated();
+        assert!(health.readiness().await.evaluate);
+
+        let stale = now_millis() - DEFAULT_EVALUATE_MAX_AGE.as_millis() as i64 - 1000;
+        health.last_evaluate.store(stale, Ordering::SeqCst);
+        let readiness = health.readiness().await;
+        assert!(!readiness.evaluate);
+        assert!(!readiness.ready);
+        assert!(readiness.mqtt);
+    }
+
+    #[tokio::test]
+    async fn not_ready_without_redis() {
+        let health = Health::default();
+        health.mark_mqtt_event();
+        health.mark_evaluated();
+        let readiness = health.readiness().await;
+        assert!(!readiness.redis);
+        assert!(!readiness.ready);
+    }
+}
@@ -2,7 +2,9 @@
 
 use std::{
     collections::HashMap,
-    sync::{Arc, RwLock},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
     time::{Duration, Instant},
 };
 
@@ -10,13 +12,23 @@ use arc_cell::ArcCell;
 use chrono::{DateTime, Utc};
 use models::{
     hvac_request::HvacRequest,
+    keys::thermostatd as keys,
+    script_log::ScriptLog,
     thermostatd::{OneshotOverride, TimedOverride},
 };
 use redis::AsyncCommands;
 use rumqttc::{MqttOptions, QoS};
+use tokio::{signal, sync::watch};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 
-use crate::{mqtt::run_mqtt_eventloop, scripting::run_script_loop};
+use crate::{
+    health::{run_health_server, Health},
+    mqtt::run_mqtt_eventloop,
+    scripting::run_script_loop,
+};
 
+mod health;
 mod mqtt;
 mod scripting;
 
@@ -34,6 +46,7 @@ mod channels {
     pub const SCRIPT_DATA_ERROR: &str = "home/thermostatd/script/error";
     pub const SCRIPT_DATA_TEST: &str = "home/thermostatd/script/test";
     pub const SCRIPT_DATA_TEST_ERROR: &str = "home/thermostatd/script/test/error";
+    pub const SCRIPT_LOG: &str = "home/thermostatd/log";
 
     pub const TIMED_OVERRIDE: &str = "home/thermostatd/timed_override";
     pub const TIMED_OVERRIDE_GET: &str = "home/thermostatd/timed_override/get";
@@ -46,13 +59,6 @@ mod channels {
     pub const ONESHOT_OVERRIDE_ERROR: &str = "home/thermostatd/oneshot_override/error";
 }
 
-mod keys {
-    pub const SAVED_SCRIPT: &str = "thermostatd.script";
-    pub const TIMED_OVERRIDE: &str = "thermostatd.timed_override";
-    pub const ONESHOT_OVERRIDE: &str = "thermostatd.oneshot_override";
-    pub const PROBE_ENDPOINTS: &str = "thermostat.probes";
-}
-
 #[derive(Default, Clone)]
 struct CommonState {
     mode: ArcCell<HvacRequest>,
@@ -62,44 +68,188 @@ struct CommonState {
     script: ArcCell<(String, DateTime<Utc>)>,
     probe_values: ArcCell<HashMap<String, f64>>,
     retained_keys: Arc<RwLock<HashMap<String, String>>>,
+    script_log: Arc<Mutex<ScriptLog>>,
+    health: Arc<Health>,
 }
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
+    init_tracing();
+
+    let health: Arc<Health> = Default::default();
+    tokio::spawn(run_health_server(health.clone()));
 
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        shutdown_tx.send(true).ok();
+    });
+
+    supervise(shutdown, |shutdown| run_thermostat(health.clone(), shutdown)).await;
+}
+
+/// Keeps restarting `run` until a shutdown is requested, backing off while it
+/// keeps failing soon after each restart
+async fn supervise<F, Fut>(mut shutdown: watch::Receiver<bool>, mut run: F)
+where
+    F: FnMut(watch::Receiver<bool>) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
     let mut last_restart = None::<Instant>;
     let mut pileon_fails = 0;
     loop {
-        if let Err(err) = run_thermostat().await {
-            eprintln!("Error encountered: {err}");
+        if let Err(err) = run(shutdown.clone()).await {
+            error!(error = %err, "Thermostat stopped");
         };
 
+        // An intentional shutdown isn't a failure, don't back off or restart
+        if *shutdown.borrow() {
+            info!("Shut down");
+            return;
+        }
+
         if let Some(last_restart_time) = last_restart {
             let now = Instant::now();
             if (now - last_restart_time) < Duration::from_secs(60) {
                 pileon_fails += 1;
-                tokio::time::sleep(Duration::from_secs(pileon_fails * pileon_fails)).await;
+                let backoff = tokio::time::sleep(Duration::from_secs(pileon_fails * pileon_fails));
+                tokio::select! {
+                    () = backoff => {}
+                    () = wait_for_shutdown(&mut shutdown) => {
+                        info!("Shut down");
+                        return;
+                    }
+                }
             }
         }
 
         last_restart = Some(Instant::now());
-        eprintln!("Restarting thermostatd");
+        warn!(pileon_fails, "Restarting thermostatd");
+    }
+}
+
+/// Filtered by `RUST_LOG`, defaulting to info. Safe to call more than once.
+fn init_tracing() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).try_init().ok();
+}
+
+async fn wait_for_signal() {
+    let Ok(mut terminate) = signal::unix::signal(signal::unix::SignalKind::terminate()) else {
+        // Still catch ctrl-c even if SIGTERM can't be hooked
+        signal::ctrl_c().await.ok();
+        return;
+    };
+
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = signal::ctrl_c() => {}
     }
 }
 
-async fn run_thermostat() -> anyhow::Result<()> {
-    println!("Starting!");
-    let (mqtt, mqtt_eventloop) = open_mqtt()?;
+async fn wait_for_shutdown(shutdown: &mut watch::Receiver<bool>) {
+    while !*shutdown.borrow() {
+        if shutdown.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn run_thermostat(
+    health: Arc<Health>,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    info!("Starting");
+    health.set_redis(None);
+    let (mqtt, mut mqtt_eventloop) = open_mqtt()?;
     let redis = open_redis().await?;
+    health.set_redis(Some(redis.clone()));
 
-    let state: Arc<CommonState> = Default::default();
+    let state = Arc::new(CommonState {
+        health,
+        ..Default::default()
+    });
     initialize_state(mqtt.clone(), redis.clone(), state.clone()).await?;
 
-    tokio::try_join!(
-        run_mqtt_eventloop(mqtt.clone(), redis.clone(), mqtt_eventloop, state.clone()),
-        run_script_loop(mqtt.clone(), redis.clone(), state.clone()),
-    )?;
+    let tasks_mqtt = mqtt.clone();
+    run_until_shutdown(&mqtt, &mut mqtt_eventloop, shutdown, move |mqtt_eventloop| {
+        Box::pin(async move {
+            tokio::try_join!(
+                run_mqtt_eventloop(
+                    tasks_mqtt.clone(),
+                    redis.clone(),
+                    mqtt_eventloop,
+                    state.clone()
+                ),
+                run_script_loop(tasks_mqtt, redis, state),
+            )?;
+            Ok(())
+        })
+    })
+    .await
+}
+
+/// The tasks of one run, which drive the MQTT eventloop they're handed
+type Tasks<'e> = Pin<Box<dyn Future<Output = anyhow::Result<()>> + 'e>>;
+
+/// Runs the tasks from `start` until they fail or a shutdown is requested, in
+/// which case the HVAC is left off on the way out
+async fn run_until_shutdown<F>(
+    mqtt: &rumqttc::AsyncClient,
+    mqtt_eventloop: &mut rumqttc::EventLoop,
+    mut shutdown: watch::Receiver<bool>,
+    start: F,
+) -> anyhow::Result<()>
+where
+    F: for<'e> FnOnce(&'e mut rumqttc::EventLoop) -> Tasks<'e>,
+{
+    // Dropping the tasks cancels them, they're only ever interrupted at an
+    // await point so nothing is left half published
+    let shutdown_requested = {
+        let tasks = start(mqtt_eventloop);
+        tokio::select! {
+            result = tasks => {
+                result?;
+                false
+            }
+            () = wait_for_shutdown(&mut shutdown) => true,
+        }
+    };
+
+    if shutdown_requested {
+        info!("Shutting down");
+        publish_final_state(mqtt, mqtt_eventloop).await?;
+    }
+
+    Ok(())
+}
+
+/// Leave the HVAC off rather than stuck on whatever the last script call was
+async fn publish_final_state(
+    mqtt: &rumqttc::AsyncClient,
+    mqtt_eventloop: &mut rumqttc::EventLoop,
+) -> anyhow::Result<()> {
+    mqtt.publish(
+        channels::HVAC_REMOTESTATE_SET,
+        QoS::AtLeastOnce,
+        true,
+        HvacRequest::Off.payload_str(),
+    )
+    .await?;
+    mqtt.disconnect().await?;
+
+    // Nothing actually goes out until the eventloop is polled
+    let flush = async {
+        use rumqttc::{Event, Outgoing};
+        loop {
+            match mqtt_eventloop.poll().await {
+                Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(2), flush).await.ok();
 
     Ok(())
 }
@@ -109,7 +259,7 @@ async fn initialize_state(
     mut redis: redis::aio::ConnectionManager,
     state: Arc<CommonState>,
 ) -> anyhow::Result<()> {
-    println!("Initializing state");
+    info!("Initializing state");
     let saved_script: Option<String> = redis.get(keys::SAVED_SCRIPT).await?;
     state.script.set(Arc::new((
         saved_script.unwrap_or_else(|| DEFAULT_SCRIPT.into()),
@@ -169,3 +319,88 @@ async fn open_redis() -> anyhow::Result<redis::aio::ConnectionManager> {
     let cm = redis::aio::ConnectionManager::new(client).await?;
     Ok(cm)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A client whose broker refuses to connect, so everything it sends stays
+    /// queued in the eventloop
+    fn refused_mqtt() -> (rumqttc::AsyncClient, rumqttc::EventLoop) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        rumqttc::AsyncClient::new(MqttOptions::new("thermostatd-test", "127.0.0.1", port), 10)
+    }
+
+    #[tokio::test]
+    async fn shutdown_interrupts_running_tasks() {
+        let (mqtt, mut eventloop) = refused_mqtt();
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        shutdown_tx.send(true).unwrap();
+
+        let run = run_until_shutdown(&mqtt, &mut eventloop, shutdown, |_| {
+            Box::pin(std::future::pending())
+        });
+        tokio::time::timeout(Duration::from_secs(5), run)
+        .await
+        .unwrap()
+        .unwrap();
+
+        // The HVAC is told to turn off before disconnecting
+        match eventloop.requests_rx.try_recv().unwrap() {
+            rumqttc::Request::Publish(publish) => {
+                assert_eq!(publish.topic, channels::HVAC_REMOTESTATE_SET);
+                assert_eq!(&publish.payload[..], b"off");
+            }
+            request => panic!("expected a publish, got {request:?}"),
+        }
+        assert!(matches!(
+            eventloop.requests_rx.try_recv(),
+            Ok(rumqttc::Request::Disconnect)
+        ));
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_the_restarts() {
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let mut runs = 0;
+        supervise(shutdown, |_| {
+            runs += 1;
+            // The first run is restarted, the second is shut down during
+            if runs == 2 {
+                shutdown_tx.send(true).unwrap();
+            }
+            async { Err(anyhow::anyhow!("Stopped")) }
+        })
+        .await;
+        assert_eq!(runs, 2);
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_the_signal() {
+        let (shutdown_tx, mut shutdown) = watch::channel(false);
+        let waiting = tokio::spawn(async move { wait_for_shutdown(&mut shutdown).await });
+
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        shutdown_tx.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn tracing_can_be_initialized_twice() {
+        init_tracing();
+        init_tracing();
+    }
+
+    #[tokio::test]
+    async fn dropped_sender_ends_the_wait() {
+        let (shutdown_tx, mut shutdown) = watch::channel(false);
+        drop(shutdown_tx);
+        wait_for_shutdown(&mut shutdown).await;
+    }
+}
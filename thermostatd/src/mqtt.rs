@@ -7,6 +7,7 @@ use models::{
 };
 use redis::AsyncCommands;
 use rumqttc::QoS;
+use tracing::info;
 
 use crate::{
     channels, keys,
@@ -17,7 +18,7 @@ use crate::{
 pub async fn run_mqtt_eventloop(
     mqtt: rumqttc::AsyncClient,
     mut redis: redis::aio::ConnectionManager,
-    mut mqtt_eventloop: rumqttc::EventLoop,
+    mqtt_eventloop: &mut rumqttc::EventLoop,
     state: Arc<CommonState>,
 ) -> anyhow::Result<()> {
     mqtt.subscribe(channels::SCRIPT_DATA_GET, QoS::ExactlyOnce)
@@ -46,8 +47,10 @@ pub async fn run_mqtt_eventloop(
 
     loop {
         use rumqttc::{Event, Packet};
-        match mqtt_eventloop.poll().await? {
-            Event::Incoming(Packet::Publish(message)) => match &*message.topic {
+        let event = mqtt_eventloop.poll().await?;
+        state.health.mark_mqtt_event();
+        if let Event::Incoming(Packet::Publish(message)) = event {
+            match &*message.topic {
                 channels::HVAC_MODE => {
                     if let Some(mode) = HvacRequest::from_payload(&message.payload) {
                         state.mode.set(mode.into());
@@ -80,7 +83,7 @@ pub async fn run_mqtt_eventloop(
                         continue;
                     };
 
-                    println!("Updating script due to incoming topic {}", message.topic);
+                    info!(topic = %message.topic, "Updating script due to incoming topic");
                     redis.set(keys::SAVED_SCRIPT, script).await?;
                     state.script.set(Arc::new((script.into(), Utc::now())));
                     mqtt.publish(channels::SCRIPT_DATA, QoS::ExactlyOnce, true, script)
@@ -111,6 +114,7 @@ pub async fn run_mqtt_eventloop(
                             Arc::make_mut(&mut copy_state);
                             copy_state
                         },
+                        dry_run: true,
                     };
                     if let Err(e) = test_script(script, &test_state).await {
                         mqtt.publish(
@@ -169,10 +173,7 @@ pub async fn run_mqtt_eventloop(
                             }
                         };
 
-                    let normalized_data = serde_json::to_string(&new_override)?;
-                    redis.set(keys::TIMED_OVERRIDE, &normalized_data).await?;
-                    state.timed_override.set(Arc::new(new_override));
-                    publish_timed_override(&mqtt, &state).await?;
+                    set_timed_override(&mqtt, &mut redis, &state, new_override).await?;
                 }
 
                 channels::ONESHOT_OVERRIDE_GET => {
@@ -204,10 +205,7 @@ pub async fn run_mqtt_eventloop(
                             }
                         };
 
-                    let normalized_data = serde_json::to_string(&new_override)?;
-                    redis.set(keys::ONESHOT_OVERRIDE, &normalized_data).await?;
-                    state.oneshot_override.set(Arc::new(new_override));
-                    publish_oneshot_override(&mqtt, &state).await?;
+                    set_oneshot_override(&mqtt, &mut redis, &state, new_override).await?;
                 }
 
                 channels::REMOTESTATE => {
@@ -248,12 +246,37 @@ pub async fn run_mqtt_eventloop(
                 }
 
                 _ => {}
-            },
-            _ => {}
+            }
         }
     }
 }
 
+/// Persist and publish a new timed override, `None` clears it
+pub async fn set_timed_override(
+    mqtt: &rumqttc::AsyncClient,
+    redis: &mut redis::aio::ConnectionManager,
+    state: &CommonState,
+    new_override: Option<TimedOverride>,
+) -> anyhow::Result<()> {
+    let normalized_data = serde_json::to_string(&new_override)?;
+    let () = redis.set(keys::TIMED_OVERRIDE, &normalized_data).await?;
+    state.timed_override.set(Arc::new(new_override));
+    publish_timed_override(mqtt, state).await
+}
+
+/// Persist and publish a new oneshot override, `None` clears it
+pub async fn set_oneshot_override(
+    mqtt: &rumqttc::AsyncClient,
+    redis: &mut redis::aio::ConnectionManager,
+    state: &CommonState,
+    new_override: Option<OneshotOverride>,
+) -> anyhow::Result<()> {
+    let normalized_data = serde_json::to_string(&new_override)?;
+    let () = redis.set(keys::ONESHOT_OVERRIDE, &normalized_data).await?;
+    state.oneshot_override.set(Arc::new(new_override));
+    publish_oneshot_override(mqtt, state).await
+}
+
 pub async fn publish_timed_override(
     mqtt: &rumqttc::AsyncClient,
     state: &CommonState,
@@ -1,20 +1,30 @@
 use std::{
     cmp::Ordering,
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     sync::Arc,
     time::{Duration, Instant},
 };
 
 use anyhow::Context;
-use chrono::{DateTime, NaiveTime, Utc};
+use chrono::{DateTime, Local, NaiveTime, Utc};
 use mlua::prelude::*;
-use models::{hvac_request::HvacRequest, thermostatd::OneshotOrdering};
+use models::{
+    hvac_request::HvacRequest,
+    keys::CONFIG_LOCATION,
+    sun::{sun_times, SunTimes},
+    thermostatd::{OneshotOrdering, OneshotOverride, TimedOverride},
+};
 use redis::AsyncCommands;
 use rumqttc::QoS;
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
 
 use crate::{
-    channels,
-    mqtt::{publish_oneshot_override, publish_timed_override},
+    channels, keys,
+    mqtt::{
+        publish_oneshot_override, publish_timed_override, set_oneshot_override,
+        set_timed_override,
+    },
     CommonState,
 };
 
@@ -29,7 +39,9 @@ pub async fn run_script_loop(
         mqtt: mqtt.clone(),
         redis,
         state: state.clone(),
+        dry_run: false,
     };
+    register_globals(&lua, &script_state)?;
 
     // Give MQTT time to initialize
     tokio::time::sleep(Duration::from_secs(1).into()).await;
@@ -42,7 +54,7 @@ pub async fn run_script_loop(
         try_load(&mut lua, &script_state, &mut last_script_timestamp).await?;
 
         if let Err(e) = tick_script(&mut lua, &script_state).await {
-            eprintln!("Script tick error{e:?}");
+            error!(stage = "tick_script", error = ?e, "Script error");
             mqtt.publish(
                 channels::SCRIPT_DATA_ERROR,
                 QoS::ExactlyOnce,
@@ -57,6 +69,10 @@ pub async fn run_script_loop(
             .await?;
         }
 
+        if let Err(e) = save_persisted(&lua, &script_state).await {
+            warn!(error = ?e, "Error saving persisted script state");
+        }
+
         if next_evaluation < Instant::now() {
             evaluate_call(&mut lua, &script_state).await?;
 
@@ -76,7 +92,7 @@ async fn try_load(
     if *last_script_timestamp != state_script.1 {
         *last_script_timestamp = state_script.1;
         if let Err(e) = test_script(&state_script.0, &script_state).await {
-            eprintln!("Error loading script during test_script\n{e:?}");
+            error!(stage = "test_script", error = ?e, "Script error");
             script_state
                 .mqtt
                 .publish(
@@ -93,8 +109,11 @@ async fn try_load(
                 .await?;
             return Ok(());
         }
+        if let Err(e) = restore_persisted(lua, script_state, &state_script.0).await {
+            warn!(error = ?e, "Error restoring persisted script state");
+        }
         if let Err(e) = load_script(lua, &state_script.0).await {
-            eprintln!("Error loading script\n{e:?}");
+            error!(stage = "load_script", error = ?e, "Script error");
             script_state
                 .mqtt
                 .publish(
@@ -112,7 +131,7 @@ async fn try_load(
             return Ok(());
         }
         if let Err(e) = init_script(lua, &script_state).await {
-            eprintln!("Error initializing script\n{e:?}");
+            error!(stage = "init_script", error = ?e, "Script error");
             script_state
                 .mqtt
                 .publish(
@@ -191,10 +210,13 @@ async fn evaluate_call(lua: &mut Lua, script_state: &ScriptState) -> anyhow::Res
 
     if next_call.is_none() {
         match evaluate_script(lua, &script_state).await {
-            Ok(Some(call)) => next_call = HvacRequest::from_string(call),
-            Ok(None) => {}
+            Ok(Some(call)) => {
+                script_state.state.health.mark_evaluated();
+                next_call = HvacRequest::from_string(call);
+            }
+            Ok(None) => script_state.state.health.mark_evaluated(),
             Err(e) => {
-                eprintln!("Script evaluate error{e:?}");
+                error!(stage = "evaluate_script", error = ?e, "Script error");
                 script_state
                     .mqtt
                     .publish(
@@ -211,11 +233,14 @@ async fn evaluate_call(lua: &mut Lua, script_state: &ScriptState) -> anyhow::Res
                     .await?;
             }
         };
+    } else {
+        // An override is in charge, the script not running isn't a problem
+        script_state.state.health.mark_evaluated();
     }
 
     if let Some(next_call) = next_call {
         if next_call != *script_state.state.last_call.get() {
-            println!("New call: {next_call}");
+            info!(%next_call, "New call");
             script_state.state.last_call.set(Arc::new(next_call));
         }
     }
@@ -232,20 +257,79 @@ async fn evaluate_call(lua: &mut Lua, script_state: &ScriptState) -> anyhow::Res
     Ok(())
 }
 
+const DEFAULT_SCRIPT_TIME_BUDGET: Duration = Duration::from_secs(2);
+
+/// How long a single call into the script may run, from `SCRIPT_TIMEOUT_MS`
+fn script_time_budget() -> Duration {
+    std::env::var("SCRIPT_TIMEOUT_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_SCRIPT_TIME_BUDGET)
+}
+
+/// Abort whatever Lua runs next once it passes the time budget, so a runaway
+/// loop becomes a script error instead of freezing the script loop.
+fn arm_timeout(lua: &Lua) -> anyhow::Result<()> {
+    arm_timeout_for(lua, script_time_budget())
+}
+
+fn arm_timeout_for(lua: &Lua, budget: Duration) -> anyhow::Result<()> {
+    let deadline = Instant::now() + budget;
+    lua.set_hook(
+        LuaHookTriggers {
+            every_nth_instruction: Some(10_000),
+            ..Default::default()
+        },
+        move |_, _| {
+            if Instant::now() > deadline {
+                Err(anyhow::anyhow!("Script exceeded its {budget:?} time budget")).luafy_error()
+            } else {
+                Ok(())
+            }
+        },
+    )?;
+    Ok(())
+}
+
+/// Runs the script in a throwaway VM where overrides and MQTT publishes are
+/// only logged, so testing a script can't change what the thermostat does
 pub async fn test_script(script: &str, state: &ScriptState) -> anyhow::Result<()> {
+    let state = ScriptState {
+        dry_run: true,
+        ..state.clone()
+    };
     let mut lua = Lua::new();
+    register_globals(&lua, &state)?;
     load_script(&mut lua, script).await?;
-    evaluate_script(&mut lua, state).await?;
+    evaluate_script(&mut lua, &state).await?;
+
+    for effect in lua.remove_app_data::<SkippedEffects>().unwrap_or_default().0 {
+        info!(%effect, "Skipped during test run");
+    }
     Ok(())
 }
 
+/// What a dry run would have done, had it been for real
+#[derive(Default)]
+struct SkippedEffects(Vec<String>);
+
+fn skip_effect(lua: &Lua, effect: String) {
+    if lua.app_data_ref::<SkippedEffects>().is_none() {
+        lua.set_app_data(SkippedEffects::default());
+    }
+    lua.app_data_mut::<SkippedEffects>().unwrap().0.push(effect);
+}
+
 async fn load_script(lua: &mut Lua, script: &str) -> anyhow::Result<()> {
+    arm_timeout(lua)?;
     lua.load(script).exec_async().await?;
     Ok(())
 }
 
 async fn init_script(lua: &mut Lua, state: &ScriptState) -> anyhow::Result<()> {
     if let Ok(init) = lua.globals().get::<_, LuaFunction>("init") {
+        arm_timeout(lua)?;
         let () = init.call_async(state.clone()).await?;
     }
     Ok(())
@@ -253,6 +337,7 @@ async fn init_script(lua: &mut Lua, state: &ScriptState) -> anyhow::Result<()> {
 
 async fn tick_script(lua: &mut Lua, state: &ScriptState) -> anyhow::Result<()> {
     if let Ok(tick) = lua.globals().get::<_, LuaFunction>("tick") {
+        arm_timeout(lua)?;
         let () = tick.call_async(state.clone()).await?;
     }
     Ok(())
@@ -260,6 +345,7 @@ async fn tick_script(lua: &mut Lua, state: &ScriptState) -> anyhow::Result<()> {
 
 async fn evaluate_script(lua: &mut Lua, state: &ScriptState) -> anyhow::Result<Option<String>> {
     if let Ok(evaluate) = lua.globals().get::<_, LuaFunction>("evaluate") {
+        arm_timeout(lua)?;
         let result: Option<String> = evaluate.call_async(state.clone()).await?;
         Ok(result)
     } else {
@@ -272,6 +358,184 @@ pub struct ScriptState {
     pub mqtt: rumqttc::AsyncClient,
     pub redis: redis::aio::ConnectionManager,
     pub state: Arc<CommonState>,
+    /// Set while testing a script, see `test_script`
+    pub dry_run: bool,
+}
+
+impl ScriptState {
+    /// Append to the script log and republish it so late subscribers see the backlog
+    async fn log(&self, message: String) -> anyhow::Result<()> {
+        let data = {
+            let mut log = self.state.script_log.lock().unwrap();
+            log.push(message);
+            serde_json::to_string(&*log)?
+        };
+        self.mqtt
+            .publish(channels::SCRIPT_LOG, QoS::AtLeastOnce, true, data)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Global table whose contents survive script reloads and daemon restarts
+const PERSIST_GLOBAL: &str = "persist";
+
+/// Redis key the `persist` table of the loaded script is saved under
+struct PersistKey {
+    key: String,
+    /// What's in Redis as far as we know, so unchanged state isn't rewritten
+    saved: Option<String>,
+    /// Set after a failed save, a value that can't be serialized would
+    /// otherwise warn on every tick
+    failing: bool,
+}
+
+/// Load the saved `persist` table for this script, starting fresh if there is
+/// none or it no longer deserializes.
+async fn restore_persisted(
+    lua: &Lua,
+    script_state: &ScriptState,
+    script: &str,
+) -> anyhow::Result<()> {
+    let key = keys::script_persist(Sha256::digest(script));
+    let data: Option<String> = script_state.redis.clone().get(&key).await?;
+    lua.set_app_data(PersistKey {
+        key,
+        saved: data.clone(),
+        failing: false,
+    });
+    lua.globals()
+        .set(PERSIST_GLOBAL, persisted_table(lua, data.as_deref())?)?;
+    Ok(())
+}
+
+fn persisted_table<'lua>(lua: &'lua Lua, data: Option<&str>) -> LuaResult<LuaTable<'lua>> {
+    match data
+        .and_then(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .and_then(|value| lua.to_value(&value).ok())
+    {
+        Some(LuaValue::Table(table)) => Ok(table),
+        _ => lua.create_table(),
+    }
+}
+
+fn serialize_persisted(lua: &Lua) -> anyhow::Result<String> {
+    let value: LuaValue = lua.globals().get(PERSIST_GLOBAL)?;
+    let value: serde_json::Value = lua.from_value(value)?;
+    Ok(serde_json::to_string(&value)?)
+}
+
+async fn save_persisted(lua: &Lua, script_state: &ScriptState) -> anyhow::Result<()> {
+    if lua.app_data_ref::<PersistKey>().is_none() {
+        return Ok(());
+    }
+
+    let data = match serialize_persisted(lua) {
+        Ok(data) => data,
+        Err(e) => {
+            let mut persist = lua.app_data_mut::<PersistKey>().unwrap();
+            let first_failure = !std::mem::replace(&mut persist.failing, true);
+            return if first_failure { Err(e) } else { Ok(()) };
+        }
+    };
+    let key = {
+        let mut persist = lua.app_data_mut::<PersistKey>().unwrap();
+        persist.failing = false;
+        if persist.saved.as_ref() == Some(&data) {
+            return Ok(());
+        }
+        persist.key.clone()
+    };
+
+    let () = script_state.redis.clone().set(key, &data).await?;
+    lua.app_data_mut::<PersistKey>().unwrap().saved = Some(data);
+    Ok(())
+}
+
+fn register_globals(lua: &Lua, state: &ScriptState) -> anyhow::Result<()> {
+    lua.globals().set(PERSIST_GLOBAL, lua.create_table()?)?;
+
+    let script_state = state.clone();
+    let log = lua.create_async_function(move |_, message: String| {
+        let script_state = script_state.clone();
+        async move { script_state.log(message).await.luafy_error() }
+    })?;
+    lua.globals().set("log", log)?;
+    register_math_helpers(lua)?;
+    register_sun_helpers(lua, state.redis.clone())?;
+    Ok(())
+}
+
+/// `sun.sunrise()` and `sun.sunset()` return today's times in Unix seconds,
+/// comparable with `os.time()`. Both are nil without a configured location
+/// and on days the sun doesn't rise or set.
+fn register_sun_helpers(lua: &Lua, redis: redis::aio::ConnectionManager) -> LuaResult<()> {
+    let sun = lua.create_table()?;
+    type Event = fn(SunTimes) -> DateTime<Utc>;
+    let events: [(&str, Event); 2] = [
+        ("sunrise", |times| times.sunrise),
+        ("sunset", |times| times.sunset),
+    ];
+    for (name, event) in events {
+        let redis = redis.clone();
+        let func = lua.create_async_function(move |_, ()| {
+            let mut redis = redis.clone();
+            async move {
+                let location: Option<String> = redis.get(CONFIG_LOCATION).await.luafy_error()?;
+                Ok(location
+                    .and_then(|location| location.parse().ok())
+                    .and_then(|location| sun_times(location, Local::now().date_naive()))
+                    .map(|times| event(times).timestamp()))
+            }
+        })?;
+        sun.set(name, func)?;
+    }
+    lua.globals().set("sun", sun)?;
+    Ok(())
+}
+
+/// `clamp`, `lerp` and `map`, so scripts don't each hand roll them
+fn register_math_helpers(lua: &Lua) -> LuaResult<()> {
+    let clamp = lua.create_function(|_, (x, lo, hi): (f64, f64, f64)| Ok(x.max(lo).min(hi)))?;
+    lua.globals().set("clamp", clamp)?;
+
+    let lerp = lua.create_function(|_, (a, b, t): (f64, f64, f64)| Ok(a + (b - a) * t))?;
+    lua.globals().set("lerp", lerp)?;
+
+    let map = lua.create_function(
+        |_, (x, in_lo, in_hi, out_lo, out_hi): (f64, f64, f64, f64, f64)| {
+            // An empty input range would divide by zero
+            if in_hi == in_lo {
+                return Ok(out_lo);
+            }
+            let t = (x - in_lo) / (in_hi - in_lo);
+            Ok(out_lo + (out_hi - out_lo) * t)
+        },
+    )?;
+    lua.globals().set("map", map)?;
+    Ok(())
+}
+
+/// Runs the block whose time most recently passed at `now`, wrapping around
+/// to the last one before the first block of the day. When it returns nil,
+/// or there are no blocks at all, `default` gets a turn.
+async fn run_timed_program<'lua>(
+    program: &BTreeMap<NaiveTime, LuaFunction<'lua>>,
+    default: Option<LuaFunction<'lua>>,
+    now: NaiveTime,
+) -> LuaResult<LuaValue<'lua>> {
+    let active = program
+        .range(..now)
+        .next_back()
+        .or_else(|| program.last_key_value());
+    let result = match active {
+        Some((_, block)) => block.call_async(()).await?,
+        None => LuaValue::Nil,
+    };
+    match (result, default) {
+        (LuaValue::Nil, Some(default)) => default.call_async(()).await,
+        (result, _) => Ok(result),
+    }
 }
 
 impl LuaUserData for ScriptState {
@@ -286,6 +550,7 @@ impl LuaUserData for ScriptState {
             Ok(MqttProxy {
                 mqtt: ss.mqtt.clone(),
                 state: ss.state.clone(),
+                dry_run: ss.dry_run,
             })
         });
         fields.add_field_method_get("redis", |_, ss| {
@@ -295,13 +560,22 @@ impl LuaUserData for ScriptState {
         });
         fields.add_field_method_get("mode", |_, ss| {
             Ok(ss.state.mode.get().payload_str().to_string())
-        })
+        });
+        fields.add_field_method_get("timed_override", |lua, ss| {
+            lua.to_value(&*ss.state.timed_override.get())
+        });
+        fields.add_field_method_get("oneshot_override", |lua, ss| {
+            lua.to_value(&*ss.state.oneshot_override.get())
+        });
     }
 
     /// Adds custom methods and operators specific to this userdata.
     fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        // The block whose time most recently passed runs first. Only when it
+        // returns nil does the `default` (or `"*"`) function get a turn.
         methods.add_async_method("timed_program", |lua, _this, program: LuaTable| async move {
             let mut program_table = BTreeMap::new();
+            let mut default = None;
             for pair in program.pairs::<String, LuaFunction>() {
                 let Ok((time_str, func)) = pair else {
                     return Err(anyhow::anyhow!(
@@ -309,6 +583,15 @@ impl LuaUserData for ScriptState {
                         lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
                     )).luafy_error();
                 };
+                if time_str == "default" || time_str == "*" {
+                    if default.replace(func).is_some() {
+                        return Err(anyhow::anyhow!(
+                            "[src:{}] Timed program can only have one of 'default' and '*'",
+                            lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
+                        )).luafy_error();
+                    }
+                    continue;
+                }
                 let Ok(time) = NaiveTime::parse_from_str(&time_str, "%H:%M") else {
                     return Err(anyhow::anyhow!(
                         "[src:{}] Timed program keys must be in 'HH:MM' format. Found {time_str}",
@@ -317,21 +600,132 @@ impl LuaUserData for ScriptState {
                 };
                 program_table.insert(time, func);
             }
-            if program_table.is_empty() {
-                return Ok(LuaValue::Nil)
+            run_timed_program(&program_table, default, chrono::Local::now().time()).await
+        });
+
+        // Same as publishing to the override SET topics, e.g. a remote button
+        // starting a 30 minute boost with `state:set_timed_override('heat', 30)`
+        methods.add_async_method(
+            "set_timed_override",
+            |lua, ss, args: (String, f64)| async move {
+                let (command, minutes) = args;
+                let Some(command) = HvacRequest::from_string(command) else {
+                    return Err(anyhow::anyhow!(
+                        "[src:{}] set_timed_override() command must be 'off', 'heat' or 'cool'",
+                        lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
+                    )).luafy_error();
+                };
+                if !minutes.is_finite() || minutes <= 0.0 {
+                    return Err(anyhow::anyhow!(
+                        "[src:{}] set_timed_override() needs a positive number of minutes",
+                        lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
+                    )).luafy_error();
+                }
+
+                if ss.dry_run {
+                    skip_effect(lua, format!("set_timed_override({command}, {minutes})"));
+                    return Ok(());
+                }
+
+                let new_override = TimedOverride {
+                    command,
+                    expiration: Utc::now()
+                        + chrono::Duration::milliseconds((minutes * 60_000.0) as i64),
+                };
+                let mut redis = ss.redis.clone();
+                set_timed_override(&ss.mqtt, &mut redis, &ss.state, Some(new_override))
+                    .await
+                    .luafy_error()
+            },
+        );
+        methods.add_async_method("clear_timed_override", |lua, ss, ()| async move {
+            if ss.dry_run {
+                skip_effect(lua, "clear_timed_override()".into());
+                return Ok(());
             }
+            let mut redis = ss.redis.clone();
+            set_timed_override(&ss.mqtt, &mut redis, &ss.state, None)
+                .await
+                .luafy_error()
+        });
+
+        methods.add_async_method(
+            "set_oneshot_override",
+            |lua, ss, args: (String, String, f32, LuaValue)| async move {
+                let (probe, command, setpoint, comparison) = args;
+                let Some(command) = HvacRequest::from_string(command) else {
+                    return Err(anyhow::anyhow!(
+                        "[src:{}] set_oneshot_override() command must be 'off', 'heat' or 'cool'",
+                        lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
+                    )).luafy_error();
+                };
+                let Ok(comparison) = lua.from_value::<OneshotOrdering>(comparison) else {
+                    return Err(anyhow::anyhow!(
+                        "[src:{}] set_oneshot_override() comparison must be 'less' or 'greater'",
+                        lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
+                    )).luafy_error();
+                };
+                if !setpoint.is_finite() {
+                    return Err(anyhow::anyhow!(
+                        "[src:{}] set_oneshot_override() setpoint must be a finite number",
+                        lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
+                    )).luafy_error();
+                }
 
-            let current_time = chrono::Local::now().time();
-            let mut active_time = *program_table.last_key_value().unwrap().0;
-            for time in program_table.keys() {
-                if *time < current_time {
-                    active_time = *time;
-                } else {
-                    break;
+                if ss.dry_run {
+                    skip_effect(lua, format!(
+                        "set_oneshot_override({probe}, {command}, {setpoint}, {comparison:?})"
+                    ));
+                    return Ok(());
                 }
+
+                let new_override = OneshotOverride {
+                    command,
+                    comparison,
+                    setpoint,
+                    probe,
+                };
+                let mut redis = ss.redis.clone();
+                set_oneshot_override(&ss.mqtt, &mut redis, &ss.state, Some(new_override))
+                    .await
+                    .luafy_error()
+            },
+        );
+        methods.add_async_method("clear_oneshot_override", |lua, ss, ()| async move {
+            if ss.dry_run {
+                skip_effect(lua, "clear_oneshot_override()".into());
+                return Ok(());
+            }
+            let mut redis = ss.redis.clone();
+            set_oneshot_override(&ss.mqtt, &mut redis, &ss.state, None)
+                .await
+                .luafy_error()
+        });
+
+        methods.add_async_method("every", |lua, _this, args: (f64, LuaFunction)| async move {
+            let (seconds, func) = args;
+            let Ok(interval) = Duration::try_from_secs_f64(seconds) else {
+                return Err(anyhow::anyhow!(
+                    "[src:{}] every() interval must be a non-negative number of seconds",
+                    lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1)
+                )).luafy_error();
+            };
+
+            // Each call site keeps its own last-run time
+            let call_site = lua.inspect_stack(1).map(|d| d.curr_line()).unwrap_or(-1);
+            if lua.app_data_ref::<EveryScratchpad>().is_none() {
+                lua.set_app_data(EveryScratchpad::default());
             }
+            let due = lua
+                .app_data_mut::<EveryScratchpad>()
+                .unwrap()
+                .due(call_site, interval, Instant::now());
 
-            program_table[&active_time].call_async(()).await
+            if due {
+                func.call_async(()).await
+            } else {
+                Ok(LuaValue::Nil)
+            }
         });
 
         methods.add_function("serialize", |lua, value: LuaValue| {
@@ -345,10 +739,30 @@ impl LuaUserData for ScriptState {
     }
 }
 
+/// Last time each `state:every` call site ran, keyed by source line
+#[derive(Default)]
+struct EveryScratchpad {
+    last_run: HashMap<i32, Instant>,
+}
+
+impl EveryScratchpad {
+    /// Whether the call site should run at `now`, marking it as run if so
+    fn due(&mut self, call_site: i32, interval: Duration, now: Instant) -> bool {
+        match self.last_run.get(&call_site) {
+            Some(&last_run) if now - last_run < interval => false,
+            _ => {
+                self.last_run.insert(call_site, now);
+                true
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 struct MqttProxy {
     mqtt: rumqttc::AsyncClient,
     state: Arc<CommonState>,
+    dry_run: bool,
 }
 
 impl LuaUserData for MqttProxy {
@@ -358,7 +772,7 @@ impl LuaUserData for MqttProxy {
     /// Adds custom methods and operators specific to this userdata.
     fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_async_method("subscribe", |_, mp, topic: String| async move {
-            println!("Subscribing to {topic:?} at request of lua script");
+            info!(%topic, "Subscribing at request of lua script");
             mp.state
                 .retained_keys
                 .write()
@@ -372,12 +786,61 @@ impl LuaUserData for MqttProxy {
                 .luafy_error()?;
             Ok(())
         });
+        methods.add_async_method("publish", |lua, mp, args: (String, String)| async move {
+            let (topic, payload) = args;
+            mp.publish(lua, topic, payload, false).await
+        });
+        methods.add_async_method(
+            "publish_retained",
+            |lua, mp, args: (String, String)| async move {
+                let (topic, payload) = args;
+                mp.publish(lua, topic, payload, true).await
+            },
+        );
         methods.add_meta_method("__index", |_, mp, topic: String| {
             Ok(mp.state.retained_keys.read().unwrap().get(&topic).cloned())
         });
     }
 }
 
+/// Scripts may not publish to the topics that control the thermostat itself,
+/// otherwise a script could feed its own output back into itself.
+const PROTECTED_TOPIC_PREFIXES: &[&str] = &[
+    "home/thermostat/",
+    "home/thermostatd/",
+    channels::HVAC_REMOTESTATE_SET,
+];
+
+impl MqttProxy {
+    async fn publish(
+        &self,
+        lua: &Lua,
+        topic: String,
+        payload: String,
+        retain: bool,
+    ) -> LuaResult<()> {
+        if PROTECTED_TOPIC_PREFIXES
+            .iter()
+            .any(|prefix| topic.starts_with(prefix))
+        {
+            return Err(anyhow::anyhow!(
+                "Scripts may not publish to thermostat control topic {topic:?}"
+            ))
+            .luafy_error();
+        }
+        if self.dry_run {
+            skip_effect(lua, format!("publish {topic:?} {payload:?}"));
+            return Ok(());
+        }
+
+        self.mqtt
+            .publish(topic.clone(), QoS::AtLeastOnce, retain, payload)
+            .await
+            .with_context(|| format!("while publishing to {topic}"))
+            .luafy_error()
+    }
+}
+
 #[derive(Clone)]
 struct RedisProxy {
     redis: redis::aio::ConnectionManager,
@@ -398,6 +861,99 @@ impl LuaUserData for RedisProxy {
                 .luafy_error()?;
             Ok(value)
         });
+        methods.add_async_method("set", |_, mut rp, args: (String, String)| async move {
+            let (key, value) = args;
+            let result: bool = rp
+                .redis
+                .set(&key, value)
+                .await
+                .with_context(|| format!("Redis operation: SET {key:?}"))
+                .luafy_error()?;
+            Ok(result)
+        });
+        methods.add_async_method("del", |_, mut rp, key: String| async move {
+            let result: bool = rp
+                .redis
+                .del(&key)
+                .await
+                .with_context(|| format!("Redis operation: DEL {key:?}"))
+                .luafy_error()?;
+            Ok(result)
+        });
+        methods.add_async_method(
+            "incr",
+            |_, mut rp, args: (String, Option<i64>)| async move {
+                let (key, delta) = args;
+                let value: i64 = rp
+                    .redis
+                    .incr(&key, delta.unwrap_or(1))
+                    .await
+                    .with_context(|| format!("Redis operation: INCRBY {key:?}"))
+                    .luafy_error()?;
+                Ok(value)
+            },
+        );
+        methods.add_async_method(
+            "expire",
+            |_, mut rp, args: (String, usize)| async move {
+                let (key, seconds) = args;
+                let result: bool = rp
+                    .redis
+                    .expire(&key, seconds)
+                    .await
+                    .with_context(|| format!("Redis operation: EXPIRE {key:?} {seconds}"))
+                    .luafy_error()?;
+                Ok(result)
+            },
+        );
+
+        methods.add_async_method("lpush", |_, mut rp, args: (String, String)| async move {
+            let (key, value) = args;
+            let len: i64 = rp
+                .redis
+                .lpush(&key, value)
+                .await
+                .with_context(|| format!("Redis operation: LPUSH {key:?}"))
+                .luafy_error()?;
+            Ok(len)
+        });
+        methods.add_async_method(
+            "lrange",
+            |_, mut rp, args: (String, isize, isize)| async move {
+                let (key, start, stop) = args;
+                let values: Vec<String> = rp
+                    .redis
+                    .lrange(&key, start, stop)
+                    .await
+                    .with_context(|| format!("Redis operation: LRANGE {key:?} {start} {stop}"))
+                    .luafy_error()?;
+                Ok(values)
+            },
+        );
+
+        methods.add_async_method("hget", |_, mut rp, args: (String, String)| async move {
+            let (key, field) = args;
+            let value: String = rp
+                .redis
+                .hget(&key, &field)
+                .await
+                .with_context(|| format!("Redis operation: HGET {key:?} {field:?}"))
+                .luafy_error()?;
+            Ok(value)
+        });
+        methods.add_async_method(
+            "hset",
+            |_, mut rp, args: (String, String, String)| async move {
+                let (key, field, value) = args;
+                let result: bool = rp
+                    .redis
+                    .hset(&key, &field, value)
+                    .await
+                    .with_context(|| format!("Redis operation: HSET {key:?} {field:?}"))
+                    .luafy_error()?;
+                Ok(result)
+            },
+        );
     }
 }
 
@@ -433,3 +989,307 @@ where
             .map_err(|e| LuaError::ExternalError(Arc::from(e)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn block<'lua>(lua: &'lua Lua, chunk: &str) -> LuaFunction<'lua> {
+        lua.load(chunk).eval_async().await.unwrap()
+    }
+
+    async fn run_at(
+        lua: &Lua,
+        program: &BTreeMap<NaiveTime, LuaFunction<'_>>,
+        default: Option<&str>,
+        time: &str,
+    ) -> Option<String> {
+        let default = match default {
+            Some(chunk) => Some(block(lua, chunk).await),
+            None => None,
+        };
+        let now = NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+        let result = run_timed_program(program, default, now).await.unwrap();
+        lua.from_value(result).unwrap()
+    }
+
+    #[tokio::test]
+    async fn timed_program_default_fires_only_on_nil() {
+        let lua = Lua::new();
+        let at = |time: &str| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+        let program = BTreeMap::from([
+            (at("06:00"), block(&lua, "return function() return 'heat' end").await),
+            (at("22:00"), block(&lua, "return function() return nil end").await),
+        ]);
+        let off = Some("return function() return 'off' end");
+
+        assert_eq!(run_at(&lua, &program, off, "12:00").await.as_deref(), Some("heat"));
+        assert_eq!(run_at(&lua, &program, off, "23:00").await.as_deref(), Some("off"));
+        // Before the first block of the day the evening one is still running
+        assert_eq!(run_at(&lua, &program, off, "03:00").await.as_deref(), Some("off"));
+        // Without a default a nil block stays nil
+        assert_eq!(run_at(&lua, &program, None, "23:00").await, None);
+    }
+
+    #[tokio::test]
+    async fn timed_program_of_only_a_default() {
+        let lua = Lua::new();
+        let default = block(&lua, "return function() return 'cool' end").await;
+        let result = run_timed_program(&BTreeMap::new(), Some(default), NaiveTime::MIN)
+            .await
+            .unwrap();
+        assert_eq!(lua.from_value::<String>(result).unwrap(), "cool");
+    }
+
+    #[test]
+    fn math_helpers_from_a_script() {
+        let lua = Lua::new();
+        register_math_helpers(&lua).unwrap();
+        let eval = |chunk: &str| -> f64 { lua.load(chunk).eval().unwrap() };
+
+        assert_eq!(eval("return clamp(25, 18, 22)"), 22.0);
+        assert_eq!(eval("return clamp(10, 18, 22)"), 18.0);
+        assert_eq!(eval("return clamp(20, 18, 22)"), 20.0);
+        assert_eq!(eval("return lerp(18, 22, 0.25)"), 19.0);
+        assert_eq!(eval("return map(15, 10, 20, 0, 100)"), 50.0);
+        assert_eq!(eval("return map(5, 10, 20, 100, 0)"), 150.0);
+        // An empty input range doesn't divide by zero
+        assert_eq!(eval("return map(5, 10, 10, 1, 2)"), 1.0);
+    }
+
+    #[test]
+    fn persisted_counter_survives_a_reload() {
+        let script = "persist.count = (persist.count or 0) + 1";
+
+        let lua = Lua::new();
+        lua.globals().set(PERSIST_GLOBAL, persisted_table(&lua, None).unwrap()).unwrap();
+        lua.load(script).exec().unwrap();
+        let saved = serialize_persisted(&lua).unwrap();
+
+        let reloaded = Lua::new();
+        let table = persisted_table(&reloaded, Some(&saved)).unwrap();
+        reloaded.globals().set(PERSIST_GLOBAL, table).unwrap();
+        reloaded.load(script).exec().unwrap();
+        let count: i64 = reloaded.load("return persist.count").eval().unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn unreadable_persisted_state_starts_fresh() {
+        let lua = Lua::new();
+        let table = persisted_table(&lua, Some("not json")).unwrap();
+        assert_eq!(table.len().unwrap(), 0);
+    }
+
+    /// Needs a scratch Redis, e.g. `REDIS_URL=redis://127.0.0.1/15 cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn scripts_write_through_redis() {
+        let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1/15".into());
+        let client = redis::Client::open(url).unwrap();
+        let redis = redis::aio::ConnectionManager::new(client).await.unwrap();
+
+        let lua = Lua::new();
+        lua.globals().set("redis", RedisProxy { redis }).unwrap();
+        lua.load(
+            r#"
+            function init() redis:set("test:lua_counter", "1") end
+            function tick() return redis:incr("test:lua_counter") end
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let globals = lua.globals();
+        let init: LuaFunction = globals.get("init").unwrap();
+        let tick: LuaFunction = globals.get("tick").unwrap();
+        init.call_async::<_, ()>(()).await.unwrap();
+        assert_eq!(tick.call_async::<_, i64>(()).await.unwrap(), 2);
+
+        let value: String = lua
+            .load(r#"return redis:get("test:lua_counter")"#)
+            .eval_async()
+            .await
+            .unwrap();
+        assert_eq!(value, "2");
+        lua.load(r#"redis:del("test:lua_counter")"#).exec_async().await.unwrap();
+    }
+
+    /// Same database as `scripts_write_through_redis`
+    #[tokio::test]
+    #[ignore]
+    async fn scripts_set_overrides_they_can_read_back() {
+        let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1/15".into());
+        let client = redis::Client::open(url).unwrap();
+        let redis = redis::aio::ConnectionManager::new(client).await.unwrap();
+        let (proxy, _eventloop) = mqtt_proxy(false);
+        let state = ScriptState {
+            mqtt: proxy.mqtt,
+            redis,
+            state: Default::default(),
+            dry_run: false,
+        };
+
+        let lua = Lua::new();
+        lua.globals().set("state", state.clone()).unwrap();
+        let command: String = lua
+            .load(
+                r#"
+                state:set_timed_override('heat', 30)
+                return state.timed_override.command
+                "#,
+            )
+            .eval_async()
+            .await
+            .unwrap();
+        assert_eq!(command, "heat");
+        let expiration = state.state.timed_override.get().unwrap().expiration;
+        assert!(expiration > Utc::now() + chrono::Duration::minutes(29));
+
+        let cleared: bool = lua
+            .load("state:clear_timed_override() return state.timed_override == nil")
+            .eval_async()
+            .await
+            .unwrap();
+        assert!(cleared);
+    }
+
+    /// A connection for paths that must never reach Redis. The listener only
+    /// has to exist, on database 0 the client doesn't send anything to connect.
+    async fn unanswered_redis() -> (tokio::net::TcpListener, redis::aio::ConnectionManager) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        let client = redis::Client::open(url).unwrap();
+        let redis = redis::aio::ConnectionManager::new(client).await.unwrap();
+        (listener, redis)
+    }
+
+    #[tokio::test]
+    async fn dry_runs_only_record_overrides() {
+        let (_listener, redis) = unanswered_redis().await;
+        let (proxy, eventloop) = mqtt_proxy(true);
+        let state = ScriptState {
+            mqtt: proxy.mqtt,
+            redis,
+            state: Default::default(),
+            dry_run: true,
+        };
+
+        let lua = Lua::new();
+        lua.globals().set("state", state.clone()).unwrap();
+        lua.load("state:set_timed_override('heat', 30)")
+            .exec_async()
+            .await
+            .unwrap();
+
+        let skipped = lua.remove_app_data::<SkippedEffects>().unwrap().0;
+        assert_eq!(skipped, ["set_timed_override(Heat, 30)"]);
+        assert!(state.state.timed_override.get().is_none());
+        assert!(eventloop.requests_rx.is_empty());
+    }
+
+    #[tokio::test]
+    async fn oneshot_setpoints_must_be_finite() {
+        let (_listener, redis) = unanswered_redis().await;
+        let (proxy, _eventloop) = mqtt_proxy(true);
+        let state = ScriptState {
+            mqtt: proxy.mqtt,
+            redis,
+            state: Default::default(),
+            dry_run: true,
+        };
+
+        let lua = Lua::new();
+        lua.globals().set("state", state).unwrap();
+        let error = lua
+            .load("state:set_oneshot_override('primary', 'heat', 0/0, 'less')")
+            .exec_async()
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("finite"), "{error}");
+    }
+
+    /// A script-facing `mqtt` along with the queue its publishes land in
+    fn mqtt_proxy(dry_run: bool) -> (MqttProxy, rumqttc::EventLoop) {
+        let options = rumqttc::MqttOptions::new("scripting-test", "localhost", 1883);
+        let (mqtt, eventloop) = rumqttc::AsyncClient::new(options, 10);
+        let proxy = MqttProxy {
+            mqtt,
+            state: Default::default(),
+            dry_run,
+        };
+        (proxy, eventloop)
+    }
+
+    #[tokio::test]
+    async fn scripts_publish_to_their_own_topics() {
+        let (proxy, eventloop) = mqtt_proxy(false);
+        let lua = Lua::new();
+        lua.globals().set("mqtt", proxy).unwrap();
+        lua.load(r#"mqtt:publish_retained("home/porch/light", "on")"#)
+            .exec_async()
+            .await
+            .unwrap();
+
+        match eventloop.requests_rx.try_recv().unwrap() {
+            rumqttc::Request::Publish(publish) => {
+                assert_eq!(publish.topic, "home/porch/light");
+                assert_eq!(&publish.payload[..], b"on");
+                assert!(publish.retain);
+            }
+            request => panic!("expected a publish, got {request:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn scripts_cant_publish_to_control_topics() {
+        let (proxy, eventloop) = mqtt_proxy(false);
+        let lua = Lua::new();
+        lua.globals().set("mqtt", proxy).unwrap();
+        let result = lua
+            .load(r#"mqtt:publish("home/thermostatd/mode/set", "heat")"#)
+            .exec_async()
+            .await;
+
+        assert!(result.is_err());
+        assert!(eventloop.requests_rx.is_empty());
+    }
+
+    #[test]
+    fn every_fires_once_per_interval() {
+        let mut scratchpad = EveryScratchpad::default();
+        let start = Instant::now();
+        let interval = Duration::from_secs(10);
+
+        // Ticks come once a second, so every tenth one should fire
+        let fired: Vec<u64> = (0..30)
+            .filter(|&tick| scratchpad.due(1, interval, start + Duration::from_secs(tick)))
+            .collect();
+        assert_eq!(fired, [0, 10, 20]);
+    }
+
+    #[test]
+    fn every_call_sites_keep_their_own_time() {
+        let mut scratchpad = EveryScratchpad::default();
+        let now = Instant::now();
+        let interval = Duration::from_secs(60);
+        assert!(scratchpad.due(1, interval, now));
+        assert!(scratchpad.due(2, interval, now));
+        assert!(!scratchpad.due(1, interval, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn runaway_scripts_are_aborted() {
+        let lua = Lua::new();
+        arm_timeout_for(&lua, Duration::from_millis(50)).unwrap();
+        let error = lua.load("while true do end").exec().unwrap_err();
+        assert!(error.to_string().contains("time budget"), "{error}");
+    }
+
+    #[test]
+    fn functions_cant_be_persisted() {
+        let lua = Lua::new();
+        lua.load("persist = { callback = function() end }").exec().unwrap();
+        assert!(serialize_persisted(&lua).is_err());
+    }
+}